@@ -4,7 +4,7 @@ use std::{fs, io};
 
 /// You need a nu file called massive.nu in your project root
 fn format_massive_nu(file: &str) -> io::Result<String> {
-    Ok(format_nu(&file, Indentation::Default))
+    Ok(format_nu(file, Indentation::Default))
 }
 
 fn criterion_benchmark(c: &mut Criterion) {
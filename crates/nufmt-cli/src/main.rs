@@ -0,0 +1,1065 @@
+use clap::{clap_app, App, Arg, ArgMatches, SubCommand};
+use nufmt::{
+    cache::{cache_dir, DiskCache, DEFAULT_CAPACITY},
+    capabilities,
+    config::{find_config_file, load_config, Config},
+    daemon,
+    diff::{patch_path, unified_diff},
+    documents::format_documents,
+    file::{catch_panic, write_atomically},
+    format_nu_compact, format_nu_with_config,
+    generated::{is_generated, DEFAULT_MARKER, DEFAULT_MARKER_LINES},
+    header::{apply_header, current_year, Variables},
+    ignore::nufmt_ignored,
+    invariants::audit,
+    lexer::debug_tokens,
+    lint::{
+        fix, fix_keyword_casing, keyword_casing, layout, parse_severity, terminal_safe, Baseline,
+        Diagnostic, LineIndex, Severity, DEFAULT_MAX_LINE_WIDTH, FIXABLE,
+    },
+    migrate::migration_script,
+    nu_module::{nu_module, Flag},
+    reproducible::manifest,
+    stats::{daily_summary, history, record, stats_file, totals, RunStats},
+    style_diff::{style_diff, summary},
+    support::{unsafe_constructs, CONSTRUCTS},
+    tree::{filtered_nu_files, nu_files},
+    Indentation,
+};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The command line of nufmt
+fn app() -> App<'static, 'static> {
+    clap_app!(nufmt =>
+        (version: "1.1")
+        (author: "fdncred")
+        (about: "Formats nu from stdin or from a file")
+        (@arg stdout: -s --stdout "Output the result to stdout instead of the default output file. Windows only.")
+        (@arg indentation: -i --indent +takes_value "Set the indentation used (\\s for space, \\t for tab)")
+        (@arg config: --config +takes_value
+            "Use this config file instead of the nufmt.toml or .nufmt.toml found in the directory of the input or above")
+        (@arg output: -o --output +takes_value "The output file for the formatted nu")
+        (@arg output_format: --("output-format") +takes_value possible_value[text github json]
+            "Set the report format (github: print workflow annotations instead of writing the formatted nu, json: only with --capabilities)")
+        (@arg capabilities: --capabilities "Print what this nufmt can do, e.g. its options and the features compiled in, for editor plugins")
+        (@arg write_patch: --("write-patch") +takes_value
+            "Write the formatting changes to a patch file (git apply compatible) instead of the formatted nu")
+        (@arg format_generated: --("format-generated") "Also format generated files, which are left untouched by default")
+        (@arg generated_marker: --("generated-marker") +takes_value
+            "Set the text marking generated files in a comment of their first lines (default: @generated)")
+        (@arg header: --header +takes_value
+            "Insert or update the header comment of a template file at the top of formatted files ({year} and {filename} are replaced)")
+        (@arg fix_keyword_case: --("fix-keyword-case") "Lowercase keywords such as IF or Def instead of warning about them")
+        (@arg fix_only: --("fix-only") +takes_value
+            "Only apply the fixes of some comma separated rules instead of formatting (indentation, trailing-whitespace, keyword-casing)")
+        (@arg document_separator: --("document-separator") +takes_value
+            "Format the input as independent snippets separated by lines holding only this text, e.g. ---")
+        (@arg daemon: --daemon "Keep running and serve newline-delimited JSON format requests over TCP")
+        (@arg listen: --listen +takes_value requires[daemon] "Set the address the daemon listens on (default: 127.0.0.1:7865)")
+        (@arg max_size: --("max-size") +takes_value requires[daemon]
+            "Set the size in bytes above which the daemon sends sources back unformatted (default: 1048576)")
+        (@arg timeout_ms: --("timeout-ms") +takes_value requires[daemon]
+            "Set how many milliseconds formatting may take before the daemon sends the source back unformatted (default: 2000)")
+        (@arg max_jobs: --("max-jobs") +takes_value requires[daemon]
+            "Set how many sources the daemon formats at once before sending more back unformatted (default: 4)")
+        (@arg input: ... "The input files to format, or directories to format the nu scripts below, stdin if there is none or for -")
+        (@arg stdin_filepath: --("stdin-filepath") +takes_value
+            "Set the path of the script read from stdin, to find its config and .nufmtignore files as for that file")
+        (@arg include: --include +takes_value +multiple number_of_values(1)
+            "Only format the scripts of the input directories matching a glob, e.g. 'scripts/**/*.nu'")
+        (@arg exclude: --exclude +takes_value +multiple number_of_values(1)
+            "Leave out the scripts of the input directories matching a glob, e.g. 'vendor/**'")
+        (@arg no_ignore: --("no-ignore")
+            "Also format the scripts of the input directories ignored by .gitignore or .ignore files, .nufmtignore files still apply")
+        (@arg in_place: -w --("in-place") requires[input] "Rewrite the input files with their formatted content")
+        (@arg check: --check conflicts_with[in_place]
+            "Print the input files formatting would change instead of formatting them, and fail if there is any")
+        (@arg diff: --diff conflicts_with[in_place]
+            "Print a unified diff of the changes formatting would make instead of formatting, and fail if there is any")
+        (@arg audit: --audit "Check that formatting kept the script's meaning and fail instead of writing it otherwise")
+        (@arg debug_tokens: --("debug-tokens") "Print the tokens of the input with their spans instead of formatting it")
+        (@arg compact: --compact conflicts_with[in_place]
+            "Print the input with every comment and optional whitespace removed, on one line, instead of formatting it")
+        (@arg lenient: --lenient "Leave files using constructs nufmt cannot format safely untouched")
+        (@arg no_cache: --("no-cache") "Neither read nor write the cache of formatted scripts")
+        (@arg lint: --lint "Only report the layout problems formatting would fix along with the other warnings, and fail if there is any error")
+        (@arg severity: --severity +takes_value +multiple number_of_values(1) requires[lint]
+            "Set the severity of a rule in lint mode, e.g. line-length=warn (off, warn or error, error by default)")
+        (@arg baseline: --baseline +takes_value requires[lint] "Leave out the diagnostics recorded in a baseline file in lint mode")
+        (@arg write_baseline: --("write-baseline") +takes_value requires[lint] conflicts_with[baseline]
+            "Record the diagnostics of lint mode in a baseline file instead of reporting them")
+        (@subcommand support =>
+            (about: "Lists the nu constructs and whether nufmt formats them, passes them through or may break them")
+            (@arg json: --json "Print the table as JSON")
+        )
+        (@subcommand cache =>
+            (about: "Manages the cache of formatted scripts, set NUFMT_CACHE_DIR to move it")
+            (@setting SubcommandRequiredElseHelp)
+            (@subcommand clear => (about: "Removes every cached script"))
+            (@subcommand dir => (about: "Prints the cache directory"))
+        )
+        (@subcommand migrate =>
+            (about: "Formats every nu script below a directory and prints a script splitting the changes into commits")
+            (@arg root: "The directory to migrate, defaults to the current one")
+        )
+        (@subcommand stats =>
+            (about: "Prints the usage statistics recorded on this machine when NUFMT_STATS=1 is set, nothing is ever sent")
+            (@arg history: --history "Print the runs day by day instead of their totals")
+        )
+    )
+    // the macro only takes identifiers as subcommand names
+    .subcommand(
+        SubCommand::with_name("verify-cross-platform")
+            .about("Prints the hashes of the formatted scripts of a corpus, which must match on every platform")
+            .arg(Arg::with_name("root").help("The directory of the corpus, defaults to the current one"))
+            .arg(
+                Arg::with_name("expected")
+                    .long("expected")
+                    .takes_value(true)
+                    .help("Compare with a manifest printed before and fail if they differ"),
+            ),
+    )
+    .subcommand(
+        SubCommand::with_name("style-diff")
+            .about("Summarizes how the scripts of a directory would change from one style to another")
+            .arg(Arg::with_name("root").help("The directory of the scripts, defaults to the current one"))
+            .arg(
+                Arg::with_name("from")
+                    .long("from")
+                    .takes_value(true)
+                    .help("The config file of the current style (default: the default style)"),
+            )
+            .arg(
+                Arg::with_name("to")
+                    .long("to")
+                    .takes_value(true)
+                    .help("The config file of the new style (default: the default style)"),
+            ),
+    )
+    .subcommand(SubCommand::with_name("nu-module").about(
+        "Prints a nu module whose `nufmt format` formats the nu piped in, load it with `overlay use`",
+    ))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let app = app();
+    let matches = app.clone().get_matches();
+
+    let replaced_indent = matches.value_of("indentation").map(|value| {
+        value
+            .to_lowercase()
+            .chars()
+            .filter(|c| ['s', 't'].contains(c))
+            .collect::<String>()
+            .replace("s", " ")
+            .replace("t", "\t")
+    });
+
+    let indent = replaced_indent.as_deref().map(Indentation::of);
+
+    if matches.is_present("daemon") {
+        let address = matches
+            .value_of("listen")
+            .unwrap_or(daemon::DEFAULT_ADDRESS);
+        let listener = std::net::TcpListener::bind(address)?;
+        let mut budget = daemon::Budget::default();
+        if let Some(max_size) = matches.value_of("max_size") {
+            budget.max_size = max_size.parse()?;
+        }
+        if let Some(timeout) = matches.value_of("timeout_ms") {
+            budget.timeout = std::time::Duration::from_millis(timeout.parse()?);
+        }
+        if let Some(max_jobs) = matches.value_of("max_jobs") {
+            budget.max_jobs = max_jobs.parse()?;
+        }
+        eprintln!("nufmt daemon listening on {}", listener.local_addr()?);
+        daemon::serve(listener, budget)?;
+        return Ok(());
+    }
+
+    if matches.is_present("capabilities") {
+        if matches.value_of("output_format") == Some("json") {
+            println!("{}", serde_json::to_string(&capabilities())?);
+        } else {
+            print!("{}", capabilities());
+        }
+        return Ok(());
+    }
+    if matches.value_of("output_format") == Some("json") {
+        return Err("--output-format json is only available with --capabilities".into());
+    }
+
+    if let Some(support) = matches.subcommand_matches("support") {
+        print_support(support.is_present("json"));
+        return Ok(());
+    }
+
+    if matches.subcommand_matches("nu-module").is_some() {
+        print!("{}", nu_module(&formatting_flags(&app)));
+        return Ok(());
+    }
+
+    if let Some(cache) = matches.subcommand_matches("cache") {
+        let dir = cache_dir().ok_or("no cache directory, set NUFMT_CACHE_DIR")?;
+        if cache.subcommand_matches("clear").is_some() {
+            DiskCache::open(&dir, DEFAULT_CAPACITY)?.clear()?;
+        } else {
+            println!("{}", dir.display());
+        }
+        return Ok(());
+    }
+
+    if let Some(stats) = matches.subcommand_matches("stats") {
+        let path = stats_file().ok_or("no stats file, set NUFMT_STATS_FILE")?;
+        let runs = match std::fs::read_to_string(&path) {
+            Ok(contents) => history(&contents),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        if stats.is_present("history") {
+            print!("{}", daily_summary(&runs));
+        } else {
+            print!("{}", totals(&runs));
+        }
+        return Ok(());
+    }
+
+    if matches.is_present("debug_tokens") {
+        print!("{}", debug_tokens(&read_input(matches.value_of("input"))?));
+        return Ok(());
+    }
+
+    if matches.is_present("compact") {
+        println!(
+            "{}",
+            format_nu_compact(&read_input(matches.value_of("input"))?)
+        );
+        return Ok(());
+    }
+
+    let fix_only = match matches.value_of("fix_only") {
+        Some(rules) => {
+            let rules: Vec<&str> = rules.split(',').map(str::trim).collect();
+            if let Some(rule) = rules.iter().find(|rule| !FIXABLE.contains(rule)) {
+                return Err(format!(
+                    "rule `{}` has no fix, fixable rules are {}",
+                    rule,
+                    FIXABLE.join(", ")
+                )
+                .into());
+            }
+            Some(rules)
+        }
+        None => None,
+    };
+
+    let started = (SystemTime::now(), Instant::now());
+    let run = Run {
+        indent,
+        config: matches.value_of("config").map(Path::new),
+        // formatting works the same without a cache
+        cache: match cache_dir() {
+            Some(dir) if !matches.is_present("no_cache") => {
+                DiskCache::open(&dir, DEFAULT_CAPACITY).ok()
+            }
+            _ => None,
+        },
+        fix_only,
+        document_separator: matches.value_of("document_separator"),
+        format_generated: matches.is_present("format_generated"),
+        generated_marker: matches
+            .value_of("generated_marker")
+            .unwrap_or(DEFAULT_MARKER),
+        fix_keyword_case: matches.is_present("fix_keyword_case"),
+        lenient: matches.is_present("lenient"),
+        audit: matches.is_present("audit"),
+        stdin_filepath: matches.value_of("stdin_filepath").map(Path::new),
+        header: match matches.value_of("header") {
+            Some(template) => Some(std::fs::read_to_string(template)?),
+            None => None,
+        },
+        files: Cell::new(0),
+        changed: Cell::new(0),
+    };
+
+    let result = input_files(&matches).and_then(|files| execute(&matches, &run, &files));
+    if nufmt::stats::enabled() && run.files.get() > 0 {
+        let stats = RunStats {
+            timestamp: started
+                .0
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+            files: run.files.get(),
+            changed: run.changed.get(),
+            millis: started.1.elapsed().as_millis() as u64,
+        };
+        // statistics are only nice to have, they never fail a run
+        if let Some(path) = stats_file() {
+            let _ = record(&path, &stats);
+        }
+    }
+    if !result? {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Runs the mode of the command line which formats or checks scripts, returns whether it succeeded
+fn execute(matches: &ArgMatches, run: &Run, files: &[String]) -> Result<bool, Box<dyn Error>> {
+    let stdin = matches.values_of("input").is_none();
+    let input = files.first().map(String::as_str);
+    if matches.is_present("lint") {
+        let mut files: Vec<Option<&str>> = files.iter().map(|file| Some(file.as_str())).collect();
+        if stdin {
+            files.push(None);
+        }
+        let mut severities = HashMap::new();
+        for setting in matches.values_of("severity").into_iter().flatten() {
+            let (rule, severity) = parse_severity(setting).ok_or_else(|| {
+                format!(
+                    "invalid severity `{}`, expected <rule>=off|warn|error",
+                    setting
+                )
+            })?;
+            severities.insert(rule, severity);
+        }
+        let mut baseline = match matches.value_of("baseline") {
+            Some(path) => Baseline::parse(&std::fs::read_to_string(path)?),
+            None => Baseline::default(),
+        };
+
+        let mut recorded = String::new();
+        let mut failed = false;
+        for file in files {
+            let nu = read_input(file)?;
+            let path = file.map(Path::new);
+            let file = file.unwrap_or("<stdin>");
+            let diagnostics = match contain_panic(file, || run.lint(&nu, path)) {
+                Some(diagnostics) => diagnostics?,
+                None => {
+                    failed = true;
+                    continue;
+                }
+            };
+            let diagnostics: Vec<Diagnostic> = baseline
+                .filter(file, &nu, diagnostics)
+                .into_iter()
+                .filter(|diagnostic| severities.get(diagnostic.rule) != Some(&Severity::Off))
+                .collect();
+            if matches.is_present("write_baseline") {
+                recorded.push_str(&Baseline::record(file, &nu, &diagnostics));
+                continue;
+            }
+            for diagnostic in &diagnostics {
+                let severity = severities
+                    .get(diagnostic.rule)
+                    .copied()
+                    .unwrap_or(Severity::Error);
+                failed |= severity == Severity::Error;
+                print_diagnostic(diagnostic, file, severity);
+            }
+        }
+        if let Some(path) = matches.value_of("write_baseline") {
+            std::fs::write(path, recorded)?;
+        }
+        if failed {
+            return Ok(false);
+        }
+        return Ok(true);
+    }
+
+    if let Some(verify) = matches.subcommand_matches("verify-cross-platform") {
+        let manifest = manifest(Path::new(verify.value_of("root").unwrap_or(".")))?;
+        match verify.value_of("expected") {
+            Some(expected) => {
+                let expected = std::fs::read_to_string(expected)?.replace("\r\n", "\n");
+                if let Some(diff) = unified_diff(&expected, &manifest, "manifest") {
+                    print!("{}", diff);
+                    return Ok(false);
+                }
+            }
+            None => print!("{}", manifest),
+        }
+        return Ok(true);
+    }
+
+    if let Some(styles) = matches.subcommand_matches("style-diff") {
+        let style = |name| match styles.value_of(name) {
+            Some(path) => load_config(Path::new(path)),
+            None => Ok(Config::default()),
+        };
+        let root = Path::new(styles.value_of("root").unwrap_or("."));
+        let differences = style_diff(root, &style("from")?, &style("to")?)?;
+        print!("{}", summary(&differences, nu_files(root)?.len()));
+        return Ok(true);
+    }
+
+    if let Some(migrate) = matches.subcommand_matches("migrate") {
+        let root = Path::new(migrate.value_of("root").unwrap_or("."));
+        let mut changed = Vec::new();
+        let mut failed = false;
+        for path in nu_files(root)? {
+            let original = std::fs::read_to_string(&path)?;
+            let file = path.to_string_lossy();
+            let formatted = match contain_panic(&file, || run.format(&original, Some(&path))) {
+                Some(formatted) => formatted?,
+                None => {
+                    failed = true;
+                    continue;
+                }
+            };
+            match formatted {
+                Some(formatted) if formatted != original => {
+                    write_atomically(&path, &formatted)?;
+                    changed.push(path);
+                }
+                _ => {}
+            }
+        }
+        print!("{}", migration_script(root, &changed));
+        return Ok(!failed);
+    }
+
+    let files: Vec<&str> = files.iter().map(String::as_str).collect();
+    let inputs: Vec<Option<&str>> = if stdin {
+        vec![None]
+    } else {
+        files.iter().copied().map(Some).collect()
+    };
+    if matches.value_of("output_format") == Some("github") {
+        let mut unformatted = false;
+        for &file in &inputs {
+            let (annotations, needs_formatting) = match github_annotations(run, file) {
+                Ok(result) => result,
+                Err(err) => {
+                    let annotation =
+                        format_github_annotation("error", file, None, &err.to_string());
+                    (vec![annotation], true)
+                }
+            };
+            for annotation in annotations {
+                println!("{}", annotation);
+            }
+            unformatted |= needs_formatting;
+        }
+        if unformatted {
+            return Ok(false);
+        }
+        return Ok(true);
+    }
+
+    if let Some(patch_file) = matches.value_of("write_patch") {
+        // one patch for all the files, applied at once with `git apply`
+        let mut patch = String::new();
+        for &path in &inputs {
+            let original = read_input(path)?;
+            let diff = run
+                .format(&original, path.map(Path::new))?
+                .and_then(|formatted| unified_diff(&original, &formatted, &diff_path(path)));
+            patch.push_str(&diff.unwrap_or_default());
+        }
+        std::fs::write(patch_file, patch)?;
+        return Ok(true);
+    }
+
+    if matches.is_present("check") || matches.is_present("diff") {
+        let mut unformatted = false;
+        for file in inputs {
+            let original = read_input(file)?;
+            let diagnostics = run.diagnostics(&original, file.map(Path::new));
+            print_diagnostics(&diagnostics, file.unwrap_or("<stdin>"));
+            let formatted = match contain_panic(file.unwrap_or("<stdin>"), || {
+                run.format(&original, file.map(Path::new))
+            }) {
+                Some(formatted) => formatted?,
+                None => {
+                    unformatted = true;
+                    continue;
+                }
+            };
+            let diff = formatted
+                .and_then(|formatted| unified_diff(&original, &formatted, &diff_path(file)));
+            if let Some(diff) = diff {
+                if matches.is_present("diff") {
+                    print!("{}", diff);
+                } else {
+                    println!("{}", file.unwrap_or("<stdin>"));
+                }
+                unformatted = true;
+            }
+        }
+        if unformatted {
+            return Ok(false);
+        }
+        return Ok(true);
+    }
+    if matches.is_present("in_place") {
+        if files.contains(&STDIN) {
+            return Err("stdin cannot be formatted in place".into());
+        }
+        let mut failed = false;
+        for file in files {
+            let original = read_input(Some(file))?;
+            print_diagnostics(&run.diagnostics(&original, Some(Path::new(file))), file);
+            let formatted =
+                match contain_panic(file, || run.format(&original, Some(Path::new(file)))) {
+                    Some(formatted) => formatted?,
+                    None => {
+                        failed = true;
+                        continue;
+                    }
+                };
+            match formatted {
+                Some(formatted) if formatted != original => {
+                    write_atomically(Path::new(file), &formatted)?
+                }
+                _ => {}
+            }
+        }
+        return Ok(!failed);
+    }
+    let directories = matches
+        .values_of("input")
+        .into_iter()
+        .flatten()
+        .any(|input| Path::new(input).is_dir());
+    if files.len() > 1 || directories {
+        return Err(
+            "several input files or directories can only be formatted with --in-place".into(),
+        );
+    }
+
+    let file = input.unwrap_or("<stdin>");
+    let path = input.map(Path::new);
+    let input = read_input(input)?;
+    print_diagnostics(&run.diagnostics(&input, path), file);
+
+    let mut output = matches.value_of("output");
+    #[cfg_attr(not(windows), allow(unused_mut))]
+    let mut windows_output_default_file: Option<String> = None;
+
+    #[cfg(windows)]
+    if !matches.is_present("stdout") {
+        if let Some(file) = path.filter(|path| *path != Path::new(STDIN)) {
+            // on windows, set the default output file if no stdout flag is provided
+            // this makes it work with drag and drop in windows explorer
+            windows_output_default_file = Some(file.to_string_lossy().replace(".nu", "_f.nu"))
+        }
+    }
+
+    output = windows_output_default_file.as_deref().or(output);
+
+    // Note: on-stack dynamic dispatch
+    let (mut file, mut stdout);
+    let writer: &mut dyn Write = match output {
+        Some(filename) => {
+            file = File::create(filename)?;
+            &mut file
+        }
+        None => {
+            stdout = std::io::stdout();
+            &mut stdout
+        }
+    };
+
+    // skipped files are passed through so nufmt can still be used as a filter
+    let formatted = run.format(&input, path)?.unwrap_or(input);
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(formatted.as_bytes())?;
+
+    Ok(true)
+}
+
+/// The input files, with the nu scripts below the input directories which match the filters
+fn input_files(matches: &ArgMatches) -> Result<Vec<String>, Box<dyn Error>> {
+    let include: Vec<&str> = matches.values_of("include").into_iter().flatten().collect();
+    let exclude: Vec<&str> = matches.values_of("exclude").into_iter().flatten().collect();
+    let mut files = Vec::new();
+    for input in matches.values_of("input").into_iter().flatten() {
+        let path = Path::new(input);
+        if path.is_dir() {
+            let respect_ignore_files = !matches.is_present("no_ignore");
+            for file in filtered_nu_files(path, &include, &exclude, respect_ignore_files)? {
+                files.push(file.to_string_lossy().into_owned());
+            }
+        } else {
+            files.push(input.to_string());
+        }
+    }
+    Ok(files)
+}
+
+/// The options shared by every mode of the command line
+struct Run<'a> {
+    /// Overrides the indentation of the config
+    indent: Option<Indentation<'a>>,
+    /// The config file to use instead of looking one up
+    config: Option<&'a Path>,
+    cache: Option<DiskCache>,
+    /// The rules whose fixes are applied instead of formatting
+    fix_only: Option<Vec<&'a str>>,
+    /// The separator of the snippets formatted independently, see [nufmt::documents]
+    document_separator: Option<&'a str>,
+    format_generated: bool,
+    generated_marker: &'a str,
+    fix_keyword_case: bool,
+    lenient: bool,
+    audit: bool,
+    /// Where the script read from stdin would be, for its config and `.nufmtignore` files
+    stdin_filepath: Option<&'a Path>,
+    header: Option<String>,
+    /// How many scripts were formatted, for the usage statistics
+    files: Cell<usize>,
+    /// How many of them changed
+    changed: Cell<usize>,
+}
+
+impl<'a> Run<'a> {
+    /// The path of a script, the `--stdin-filepath` one if it is read from stdin
+    fn script_path<'p>(&'p self, path: Option<&'p Path>) -> Option<&'p Path> {
+        match path {
+            Some(path) if path != Path::new(STDIN) => Some(path),
+            _ => self.stdin_filepath,
+        }
+    }
+
+    /// Whether a script is generated or ignored by its `.nufmtignore` file, and is left alone
+    fn ignores(&self, nu: &str, path: Option<&Path>) -> Result<bool, Box<dyn Error>> {
+        let generated =
+            !self.format_generated && is_generated(nu, self.generated_marker, DEFAULT_MARKER_LINES);
+        Ok(generated || path.map_or(Ok(false), nufmt_ignored)?)
+    }
+
+    /// Whether a script is left untouched, also when it uses unsupported constructs in lenient mode
+    fn skips(&self, nu: &str, path: Option<&Path>) -> Result<bool, Box<dyn Error>> {
+        Ok(self.ignores(nu, path)? || (self.lenient && !unsafe_constructs(nu).is_empty()))
+    }
+
+    /// Formats a script, or returns `None` if it is skipped
+    fn format(&self, nu: &str, path: Option<&Path>) -> Result<Option<String>, Box<dyn Error>> {
+        let path = self.script_path(path);
+        if self.skips(nu, path)? {
+            return Ok(None);
+        }
+        let original = nu;
+        let nu = if self.fix_keyword_case {
+            Cow::Owned(fix_keyword_casing(nu))
+        } else {
+            Cow::Borrowed(nu)
+        };
+
+        let config = self.config(path)?;
+        let formatted = match &self.fix_only {
+            Some(rules) => fix(&nu, rules, config.indentation()),
+            None => match self.document_separator {
+                Some(separator) => format_documents(&nu, separator, |document| {
+                    self.format_cached(document, &config)
+                }),
+                None => self.format_cached(&nu, &config),
+            },
+        };
+        if self.audit {
+            if let Some(violation) = audit(&nu, &formatted).into_iter().next() {
+                return Err(violation.into());
+            }
+        }
+        let formatted = match &self.header {
+            Some(template) => {
+                let filename = path
+                    .and_then(Path::file_name)
+                    .map(|name| name.to_string_lossy())
+                    .unwrap_or(Cow::Borrowed("stdin"));
+                let variables = Variables {
+                    year: current_year(),
+                    filename: &filename,
+                };
+                apply_header(&formatted, template, &variables)
+            }
+            None => formatted,
+        };
+        self.files.set(self.files.get() + 1);
+        if formatted != original {
+            self.changed.set(self.changed.get() + 1);
+        }
+        Ok(Some(formatted))
+    }
+
+    /// The diagnostics of a script that formatting does not fix
+    fn diagnostics(&self, nu: &str, path: Option<&Path>) -> Vec<Diagnostic> {
+        let path = self.script_path(path);
+        // an unreadable `.nufmtignore` is reported when formatting
+        if self.ignores(nu, path).unwrap_or(false) {
+            return Vec::new();
+        }
+
+        let mut diagnostics = Vec::new();
+        if self.lenient {
+            let lines = LineIndex::new(nu);
+            for (construct, span) in unsafe_constructs(nu) {
+                let message = format!(
+                    "{} is not supported yet, leaving the file untouched",
+                    construct.name
+                );
+                diagnostics.push(Diagnostic::new(
+                    &lines,
+                    "unsupported-construct",
+                    span,
+                    message,
+                ));
+            }
+        }
+        let fixes_keywords = self
+            .fix_only
+            .as_ref()
+            .is_some_and(|rules| rules.contains(&"keyword-casing"));
+        if !self.fix_keyword_case && !fixes_keywords {
+            diagnostics.extend(keyword_casing(nu));
+        }
+        diagnostics
+    }
+
+    /// The diagnostics of a script along with the layout problems formatting would fix
+    fn lint(&self, nu: &str, path: Option<&Path>) -> Result<Vec<Diagnostic>, Box<dyn Error>> {
+        let path = self.script_path(path);
+        let mut diagnostics = self.diagnostics(nu, path);
+        if !self.skips(nu, path)? {
+            let indent = self.config(path)?.indentation();
+            diagnostics.extend(layout(nu, indent, DEFAULT_MAX_LINE_WIDTH));
+            diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+        }
+        Ok(diagnostics)
+    }
+
+    /// Formats a script, reusing the result of a previous run if it is cached
+    fn format_cached(&self, nu: &str, config: &Config) -> String {
+        let key = DiskCache::key(nu, config);
+        if let Some(formatted) = self.cache.as_ref().and_then(|cache| cache.get(key)) {
+            return formatted;
+        }
+        let formatted = format_nu_with_config(nu, config);
+        if let Some(cache) = &self.cache {
+            // a cache which cannot be written is only slower
+            let _ = cache.put(key, &formatted);
+        }
+        formatted
+    }
+
+    /// The config of a script, from the config file applying to it and the command line
+    fn config(&self, path: Option<&Path>) -> Result<Config<'a>, Box<dyn Error>> {
+        let file = match self.config {
+            Some(config) => Some(config.to_path_buf()),
+            None => {
+                let dir = match path.and_then(Path::parent) {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                    _ => std::env::current_dir()?,
+                };
+                find_config_file(&dir)
+            }
+        };
+        let config = match file {
+            Some(file) => load_config(&file)?,
+            None => Config::default(),
+        };
+        Ok(match self.indent {
+            Some(indent) => config.to_builder().indentation(indent).build(),
+            None => config,
+        })
+    }
+}
+
+/// Runs the work on one file of a bulk run, a panic is printed as an error of the file and gives
+/// `None` so the run carries on with the next file
+fn contain_panic<T>(file: &str, work: impl FnOnce() -> T) -> Option<T> {
+    match catch_panic(work) {
+        Ok(value) => Some(value),
+        Err(err) => {
+            eprintln!("{}: error: {}", file, err);
+            None
+        }
+    }
+}
+
+/// Prints the diagnostics of a script to stderr as warnings
+fn print_diagnostics(diagnostics: &[Diagnostic], file: &str) {
+    for diagnostic in diagnostics {
+        print_diagnostic(diagnostic, file, Severity::Warning);
+    }
+}
+
+fn print_diagnostic(diagnostic: &Diagnostic, file: &str, severity: Severity) {
+    eprintln!(
+        "{}:{}:{}: {}: {} [{}]",
+        file,
+        diagnostic.line,
+        diagnostic.column,
+        severity.label(),
+        terminal_safe(&diagnostic.message),
+        diagnostic.rule
+    );
+}
+
+/// Formats the input in memory and returns the GitHub workflow annotations for its diagnostics and
+/// the first changed position, if any, along with whether the input needs formatting.
+fn github_annotations(
+    run: &Run,
+    file: Option<&str>,
+) -> Result<(Vec<String>, bool), Box<dyn Error>> {
+    let original = read_input(file)?;
+    let mut annotations: Vec<String> = run
+        .diagnostics(&original, file.map(Path::new))
+        .iter()
+        .map(|diagnostic| {
+            let position = Some((diagnostic.line, diagnostic.column));
+            format_github_annotation("warning", file, position, &diagnostic.message)
+        })
+        .collect();
+
+    let difference = run
+        .format(&original, file.map(Path::new))?
+        .and_then(|formatted| first_difference(&original, &formatted));
+    if let Some(position) = difference {
+        let message = "File is not formatted, run nufmt on it";
+        annotations.push(format_github_annotation(
+            "error",
+            file,
+            Some(position),
+            message,
+        ));
+    }
+
+    Ok((annotations, difference.is_some()))
+}
+
+/// Prints the support status of every construct
+fn print_support(json: bool) {
+    if json {
+        let constructs: Vec<serde_json::Value> = CONSTRUCTS
+            .iter()
+            .map(|construct| {
+                serde_json::json!({
+                    "name": construct.name,
+                    "example": construct.example,
+                    "support": construct.support.as_str(),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::Value::Array(constructs));
+        return;
+    }
+
+    let width = CONSTRUCTS.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    for construct in CONSTRUCTS {
+        println!(
+            "{:width$}  {:11}  {}",
+            construct.name,
+            construct.support.as_str(),
+            construct.example,
+            width = width
+        );
+    }
+}
+
+/// The options which are modes of their own or work on files rather than on the nu piped in
+const NOT_FORMATTING: &[&str] = &[
+    "stdout",
+    "output",
+    "output_format",
+    "capabilities",
+    "write_patch",
+    "daemon",
+    "listen",
+    "max_size",
+    "timeout_ms",
+    "max_jobs",
+    "include",
+    "exclude",
+    "no_ignore",
+    "in_place",
+    "check",
+    "diff",
+    "debug_tokens",
+    "compact",
+    "lint",
+    "severity",
+    "baseline",
+    "write_baseline",
+];
+
+/// The options of the command line formatting the nu piped in, in their order of definition
+fn formatting_flags<'a>(app: &'a App) -> Vec<Flag<'a>> {
+    // clap 2 has no public accessors for the arguments of an app
+    let flags = app.p.flags.iter().map(|flag| (&flag.b, &flag.s, false));
+    let options = app.p.opts.iter().map(|option| (&option.b, &option.s, true));
+    let mut args: Vec<_> = flags
+        .chain(options)
+        .filter(|(base, _, _)| !NOT_FORMATTING.contains(&base.name))
+        .collect();
+    args.sort_by_key(|(_, switched, _)| switched.unified_ord);
+    args.into_iter()
+        .filter_map(|(base, switched, takes_value)| {
+            Some(Flag {
+                long: switched.long?,
+                short: switched.short,
+                help: base.help.unwrap_or_default(),
+                takes_value,
+            })
+        })
+        .collect()
+}
+
+/// The input file standing for stdin
+const STDIN: &str = "-";
+
+/// Reads the whole input file, or stdin if there is none or it is `-`
+fn read_input(file: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let mut input = String::new();
+    match file {
+        Some(path) if path != STDIN => File::open(path)?.read_to_string(&mut input)?,
+        _ => std::io::stdin().read_to_string(&mut input)?,
+    };
+    Ok(input)
+}
+
+/// The path of an input file in the headers of a diff, relative to the current directory if it is
+/// below it
+fn diff_path(file: Option<&str>) -> String {
+    match (file, std::env::current_dir()) {
+        (Some(file), Ok(dir)) => patch_path(Path::new(file), &dir),
+        (Some(file), Err(_)) => file.to_string(),
+        (None, _) => "stdin.nu".to_string(),
+    }
+}
+
+/// Returns the 1-based line and column of the first character where `a` and `b` differ
+fn first_difference(a: &str, b: &str) -> Option<(usize, usize)> {
+    let (mut line, mut col) = (1, 1);
+    let (mut a, mut b) = (a.chars(), b.chars());
+    loop {
+        match (a.next(), b.next()) {
+            (None, None) => return None,
+            (x, y) if x == y => {
+                if x == Some('\n') {
+                    line += 1;
+                    col = 1;
+                } else {
+                    col += 1;
+                }
+            }
+            _ => return Some((line, col)),
+        }
+    }
+}
+
+/// Builds a `::error` or `::warning` workflow command, see
+/// <https://docs.github.com/en/actions/using-workflows/workflow-commands-for-github-actions>
+fn format_github_annotation(
+    level: &str,
+    file: Option<&str>,
+    position: Option<(usize, usize)>,
+    message: &str,
+) -> String {
+    let mut properties = Vec::new();
+    if let Some(file) = file {
+        properties.push(format!("file={}", escape_github(file, true)));
+    }
+    if let Some((line, col)) = position {
+        properties.push(format!("line={},col={}", line, col));
+    }
+
+    let message = escape_github(&terminal_safe(message), false);
+    if properties.is_empty() {
+        format!("::{}::{}", level, message)
+    } else {
+        format!("::{} {}::{}", level, properties.join(","), message)
+    }
+}
+
+fn escape_github(value: &str, property: bool) -> String {
+    let value = value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A");
+    if property {
+        value.replace(':', "%3A").replace(',', "%2C")
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nu_module_leaves_out_daemon_options() {
+        let app = app();
+        let module = nu_module(&formatting_flags(&app));
+        let requires_daemon = |requires: &Option<Vec<(Option<&str>, &str)>>| {
+            requires
+                .iter()
+                .flatten()
+                .any(|(_, required)| *required == "daemon")
+        };
+        let daemon_only = app
+            .p
+            .flags
+            .iter()
+            .map(|flag| (&flag.b, &flag.s))
+            .chain(app.p.opts.iter().map(|option| (&option.b, &option.s)))
+            .filter(|(base, _)| requires_daemon(&base.requires))
+            .filter_map(|(_, switched)| switched.long);
+        for long in daemon_only {
+            assert!(!module.contains(&format!("--{}", long)), "--{}", long);
+        }
+        assert!(!module.contains("--daemon"));
+        assert!(module.contains("--indent"));
+    }
+
+    #[test]
+    fn escapes_github_values() {
+        let value = "50% a\r\nb: c, d";
+        assert_eq!("50%25 a%0D%0Ab%3A c%2C d", escape_github(value, true));
+        // messages keep their colons and commas
+        assert_eq!("50%25 a%0D%0Ab: c, d", escape_github(value, false));
+    }
+
+    #[test]
+    fn first_differences() {
+        assert_eq!(None, first_difference("ls\nps", "ls\nps"));
+        assert_eq!(Some((2, 2)), first_difference("ls\nps", "ls\npx"));
+        assert_eq!(Some((1, 1)), first_difference("ls", "ps"));
+        // one is a prefix of the other
+        assert_eq!(Some((1, 3)), first_difference("ls", "ls\nps"));
+        assert_eq!(Some((2, 1)), first_difference("ls\nps", "ls\n"));
+        assert_eq!(Some((1, 1)), first_difference("", "ls"));
+    }
+
+    #[test]
+    fn github_annotation_shapes() {
+        assert_eq!(
+            "::error::needs formatting",
+            format_github_annotation("error", None, None, "needs formatting")
+        );
+        assert_eq!(
+            "::warning file=a%2Cb.nu,line=2,col=3::needs formatting: yes",
+            format_github_annotation(
+                "warning",
+                Some("a,b.nu"),
+                Some((2, 3)),
+                "needs formatting: yes"
+            )
+        );
+        // control characters of messages are written escaped, like on a terminal
+        assert_eq!(
+            "::error line=1,col=1::50%25 \\u{1b}",
+            format_github_annotation("error", None, Some((1, 1)), "50% \u{1b}")
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//!
+//! A cache of formatted scripts on disk, shared by every nufmt process of a user.
+//!
+//! Each entry is a file named after the hash of the source and of the config it was formatted
+//! with. Entries are written to a temporary file first and renamed, so readers never see half an
+//! entry, and every change of the directory is made holding an exclusive lock on its `.lock`
+//! file, so concurrent invocations do not evict each other's entries while writing them.
+
+use crate::Config;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How many bytes of entries are kept by default
+pub const DEFAULT_CAPACITY: u64 = 32 * 1024 * 1024;
+
+/// The version of the layout nufmt writes, bumped with every change of the formatted output
+///
+/// It is part of the keys of the entries, which are then written again by the new layout instead
+/// of being returned as formatted by an earlier build of the same version of nufmt.
+pub const FORMAT_VERSION: u32 = 2;
+
+const LOCK_FILE: &str = ".lock";
+
+///
+/// # The directory of the cache of the current user
+///
+/// `NUFMT_CACHE_DIR` if set, else the platform cache directory: `$XDG_CACHE_HOME/nufmt` or
+/// `~/.cache/nufmt` on Linux, `~/Library/Caches/nufmt` on macOS and `%LOCALAPPDATA%\nufmt` on
+/// Windows. `None` if none of them is known.
+///
+pub fn cache_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|value| !value.is_empty());
+    if let Some(dir) = var("NUFMT_CACHE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    let base = if cfg!(windows) {
+        var("LOCALAPPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| Path::new(&home).join("Library").join("Caches"))
+    } else {
+        var("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| var("HOME").map(|home| Path::new(&home).join(".cache")))
+    };
+    base.map(|base| base.join("nufmt"))
+}
+
+/// The formatted scripts stored in a directory
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    dir: PathBuf,
+    capacity: u64,
+}
+
+impl DiskCache {
+    /// The cache in `dir`, holding up to `capacity` bytes of entries, the directory is created
+    pub fn open(dir: &Path, capacity: u64) -> io::Result<DiskCache> {
+        fs::create_dir_all(dir)?;
+        Ok(DiskCache {
+            dir: dir.to_path_buf(),
+            capacity,
+        })
+    }
+
+    /// The key of a source formatted with a config, which changes with the version of nufmt and
+    /// with [FORMAT_VERSION]
+    pub fn key(source: &str, config: &Config) -> u64 {
+        DiskCache::versioned_key(FORMAT_VERSION, source, config)
+    }
+
+    fn versioned_key(format_version: u32, source: &str, config: &Config) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (env!("CARGO_PKG_VERSION"), format_version, source, config).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The formatted script of a key, `None` if it is not cached
+    pub fn get(&self, key: u64) -> Option<String> {
+        fs::read_to_string(self.entry(key)).ok()
+    }
+
+    ///
+    /// # Stores the formatted script of a key
+    ///
+    /// The oldest entries are then evicted until the cache fits in its capacity.
+    ///
+    pub fn put(&self, key: u64, formatted: &str) -> io::Result<()> {
+        let temporary = self
+            .dir
+            .join(format!("{:016x}.{}.tmp", key, std::process::id()));
+        fs::write(&temporary, formatted)?;
+
+        let _lock = self.lock()?;
+        fs::rename(&temporary, self.entry(key))?;
+        self.evict()
+    }
+
+    /// Removes every entry
+    pub fn clear(&self) -> io::Result<()> {
+        let _lock = self.lock()?;
+        for (path, _) in self.entries()? {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// The size of the entries in bytes
+    pub fn size(&self) -> io::Result<u64> {
+        Ok(self
+            .entries()?
+            .iter()
+            .map(|(_, metadata)| metadata.len())
+            .sum())
+    }
+
+    fn entry(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}", key))
+    }
+
+    /// Locks the directory until the returned file is dropped
+    fn lock(&self) -> io::Result<File> {
+        let lock = File::create(self.dir.join(LOCK_FILE))?;
+        lock.lock()?;
+        Ok(lock)
+    }
+
+    /// The entries with their metadata, the lock and the temporary files excepted
+    fn entries(&self) -> io::Result<Vec<(PathBuf, fs::Metadata)>> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let is_entry = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.len() == 16 && u64::from_str_radix(name, 16).is_ok());
+            if is_entry {
+                entries.push((entry.path(), entry.metadata()?));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Removes the least recently written entries beyond the capacity, the lock must be held
+    fn evict(&self) -> io::Result<()> {
+        let mut entries = self.entries()?;
+        let mut size: u64 = entries.iter().map(|(_, metadata)| metadata.len()).sum();
+        entries.sort_by_key(|(_, metadata)| metadata.modified().ok());
+        for (path, metadata) in entries {
+            if size <= self.capacity {
+                break;
+            }
+            // another process may have removed it already
+            match fs::remove_file(&path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => size -= metadata.len(),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn temporary_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nufmt-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn stores_and_clears_entries() {
+        let dir = temporary_dir("cache");
+        let cache = DiskCache::open(&dir, DEFAULT_CAPACITY).unwrap();
+        let key = DiskCache::key("[1,2]", &Config::default());
+        assert_ne!(
+            key,
+            DiskCache::key("[1,2]", &Config::builder().max_width(40).build())
+        );
+
+        assert_eq!(None, cache.get(key));
+        cache.put(key, "[1, 2]").unwrap();
+        assert_eq!(Some("[1, 2]".to_string()), cache.get(key));
+        assert_eq!(6, cache.size().unwrap());
+
+        cache.clear().unwrap();
+        assert_eq!(None, cache.get(key));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn format_versions_invalidate_entries() {
+        let dir = temporary_dir("format-version");
+        let cache = DiskCache::open(&dir, DEFAULT_CAPACITY).unwrap();
+        let config = Config::default();
+        let previous = DiskCache::versioned_key(FORMAT_VERSION - 1, "[1,2]", &config);
+        cache.put(previous, "[\n    1,\n    2\n]").unwrap();
+
+        assert_ne!(previous, DiskCache::key("[1,2]", &config));
+        assert_eq!(None, cache.get(DiskCache::key("[1,2]", &config)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn evicts_beyond_capacity() {
+        let dir = temporary_dir("eviction");
+        let cache = DiskCache::open(&dir, 10).unwrap();
+        let threads: Vec<_> = (0..4u64)
+            .map(|key| {
+                let cache = cache.clone();
+                std::thread::spawn(move || cache.put(key, "four").unwrap())
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert_eq!(8, cache.size().unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+//!
+//! What the nufmt at hand can do, for editor plugins and other integrators.
+//!
+//! A plugin asks the installed nufmt with `nufmt --capabilities --output-format json` instead of
+//! guessing from its version, e.g. whether it can read config files or which options directives
+//! accept.
+
+use crate::directive::OPTION_VALUES;
+
+/// What this build of nufmt can do, see [capabilities]
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "daemon", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct Capabilities {
+    /// The version of nufmt
+    pub version: &'static str,
+    /// The languages it formats
+    pub dialects: Vec<&'static str>,
+    /// The style editions it can format with. There are none yet: a style is a
+    /// [Config](crate::Config), and the default one only changes with the version
+    pub style_editions: Vec<&'static str>,
+    /// The optional features compiled in, e.g. `config-file`
+    pub features: Vec<&'static str>,
+    /// The options a config or a directive accepts
+    pub options: Vec<&'static str>,
+}
+
+/// The capabilities of this build of nufmt
+pub fn capabilities() -> Capabilities {
+    let features = [
+        ("config-file", cfg!(feature = "config-file")),
+        ("daemon", cfg!(feature = "daemon")),
+    ];
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        dialects: vec!["nu"],
+        style_editions: Vec::new(),
+        features: features
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(feature, _)| *feature)
+            .collect(),
+        options: OPTION_VALUES.iter().map(|(option, _)| *option).collect(),
+    }
+}
+
+/// The items of a line of the text output, `none` if there is none
+fn list(items: &[&str]) -> String {
+    match items {
+        [] => "none".to_string(),
+        items => items.join(" "),
+    }
+}
+
+impl std::fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "version         {}", self.version)?;
+        writeln!(f, "dialects        {}", list(&self.dialects))?;
+        writeln!(f, "style editions  {}", list(&self.style_editions))?;
+        writeln!(f, "features        {}", list(&self.features))?;
+        writeln!(f, "options         {}", list(&self.options))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn describes_the_build() {
+        let capabilities = capabilities();
+        assert_eq!(env!("CARGO_PKG_VERSION"), capabilities.version);
+        assert_eq!(vec!["nu"], capabilities.dialects);
+        assert_eq!(
+            cfg!(feature = "daemon"),
+            capabilities.features.contains(&"daemon")
+        );
+        assert!(capabilities.options.contains(&"max_width"));
+        assert!(capabilities
+            .to_string()
+            .contains("\ndialects        nu\nstyle editions  none\n"));
+    }
+}
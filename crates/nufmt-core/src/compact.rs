@@ -0,0 +1,259 @@
+//!
+//! Compact output, the smallest source meaning the same as a script.
+//!
+//! Comments are dropped, the lines of a block are joined with `;`, those of a match with `,` and
+//! those of lists, records and subexpressions with a space, so the whole script ends up on one
+//! line. Whitespace is only kept where nu needs it, between words and around operators, and where
+//! it could change the meaning, e.g. the arguments of external commands, which see it. A shebang
+//! is kept on its line, and so are attributes, which must precede their def.
+
+use crate::lexer::{opens_record, tokenize, Token, TokenKind};
+
+/// What the tokens of a delimiter are separated by
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Context {
+    /// Statements, separated by newlines or `;`
+    Block,
+    /// The arms of a `match`, separated by newlines or `,`
+    Match,
+    /// A subexpression, where newlines are whitespace
+    Paren,
+    List,
+    Record,
+}
+
+/// What goes between the last token written and the next one
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord)]
+enum Separator {
+    Space,
+    Comma,
+    Semicolon,
+    Newline,
+}
+
+///
+/// # Formats nu as compactly as possible
+///
+/// Useful to embed generated nuon or to pass structured data between tools, see the [module
+/// documentation](self) for what is removed. Formatting the result again with [format_nu_compact]
+/// does not change it.
+///
+pub fn format_nu_compact(nu: &str) -> String {
+    let tokens = tokenize(nu);
+    let mut output = String::with_capacity(nu.len());
+    let mut contexts = vec![Context::Block];
+    let mut pending: Option<Separator> = None;
+    let mut last: Option<TokenKind> = None;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let context = *contexts.last().unwrap_or(&Context::Block);
+        let kind = token.kind;
+        match kind {
+            TokenKind::Whitespace => {
+                pending = pending.max(Some(Separator::Space));
+                continue;
+            }
+            TokenKind::Comment => {
+                if token.span.start == 0 && token.text(nu).starts_with("#!") {
+                    output.push_str(token.text(nu));
+                    pending = Some(Separator::Newline);
+                }
+                continue;
+            }
+            TokenKind::Newline => {
+                let separator = match context {
+                    // a newline after a pipe or an arrow goes on with the same statement or arm
+                    _ if last == Some(TokenKind::Pipe) || last_text(&output) == "=>" => {
+                        Separator::Space
+                    }
+                    Context::Block => Separator::Semicolon,
+                    Context::Match => Separator::Comma,
+                    Context::Paren | Context::List | Context::Record => Separator::Space,
+                };
+                if last.is_some() {
+                    pending = pending.max(Some(separator));
+                }
+                continue;
+            }
+            TokenKind::Semicolon => {
+                pending = pending.max(Some(Separator::Semicolon));
+                continue;
+            }
+            TokenKind::Comma if context == Context::Match => {
+                pending = pending.max(Some(Separator::Comma));
+                continue;
+            }
+            _ => {}
+        }
+
+        let closes = matches!(
+            kind,
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen
+        );
+        // tokens which need no space before them
+        let separated = kind == TokenKind::Pipe
+            || matches!(context, Context::List | Context::Record) && kind == TokenKind::Comma
+            || context == Context::Record && kind == TokenKind::Colon;
+        let opened = matches!(
+            last,
+            Some(TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen)
+        );
+        match pending.take() {
+            Some(Separator::Newline) if !closes => output.push('\n'),
+            // a pipe starting a line goes on with the pipeline of the line above
+            Some(Separator::Semicolon) if !closes && !opened && kind != TokenKind::Pipe => {
+                output.push(';')
+            }
+            Some(Separator::Comma) if !closes && !opened => output.push(','),
+            Some(Separator::Space) if !closes && !separated && needs_space(last, context) => {
+                output.push(' ')
+            }
+            _ => {}
+        }
+        output.push_str(token.text(nu));
+        last = Some(kind);
+
+        match kind {
+            TokenKind::OpenBracket => contexts.push(Context::List),
+            TokenKind::OpenParen => contexts.push(Context::Paren),
+            TokenKind::OpenBrace => contexts.push(brace_context(nu, &tokens, i)),
+            _ if closes => {
+                contexts.pop();
+            }
+            TokenKind::Attribute => pending = Some(Separator::Newline),
+            _ => {}
+        }
+    }
+    output
+}
+
+/// Whether a space must separate the last token written from the next one
+fn needs_space(last: Option<TokenKind>, context: Context) -> bool {
+    match last {
+        None => false,
+        Some(
+            TokenKind::OpenBracket
+            | TokenKind::OpenBrace
+            | TokenKind::OpenParen
+            | TokenKind::Pipe
+            | TokenKind::Semicolon,
+        ) => false,
+        Some(TokenKind::Comma) => !matches!(context, Context::List | Context::Record),
+        Some(TokenKind::Colon) => context != Context::Record,
+        Some(_) => true,
+    }
+}
+
+/// The last word written, to tell the arrows of match arms
+fn last_text(output: &str) -> &str {
+    output.rsplit(' ').next().unwrap_or(output)
+}
+
+/// What the brace at `index` opens
+fn brace_context(nu: &str, tokens: &[Token], index: usize) -> Context {
+    let significant: Vec<Token> = tokens[index + 1..]
+        .iter()
+        .filter(|token| {
+            !matches!(
+                token.kind,
+                TokenKind::Whitespace | TokenKind::Newline | TokenKind::Comment
+            )
+        })
+        .take(2)
+        .copied()
+        .collect();
+    if opens_record(&significant) {
+        Context::Record
+    } else if opens_match(nu, &tokens[..index]) {
+        Context::Match
+    } else {
+        Context::Block
+    }
+}
+
+/// Whether the tokens before a brace end with `match` and the value it matches
+fn opens_match(nu: &str, before: &[Token]) -> bool {
+    let mut significant = before
+        .iter()
+        .rev()
+        .filter(|token| token.kind != TokenKind::Whitespace);
+    let mut depth = 0usize;
+    // the value is a single token or a subexpression, list or record
+    for token in &mut significant {
+        match token.kind {
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => depth += 1,
+            TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen => {
+                match depth.checked_sub(1) {
+                    Some(outer) => depth = outer,
+                    None => return false,
+                }
+            }
+            TokenKind::Word | TokenKind::String => {}
+            _ if depth > 0 => {}
+            _ => return false,
+        }
+        if depth == 0 {
+            break;
+        }
+    }
+    significant
+        .next()
+        .is_some_and(|token| token.kind == TokenKind::Word && token.text(nu) == "match")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn joins_statements() {
+        let nu = "# build\nlet x = 1 # one\n\nls | where size > 1\n  | get name\nif $x > 0 {\n  print a\n  print b\n} else { print c }\n";
+        assert_eq!(
+            "let x = 1;ls|where size > 1|get name;if $x > 0 {print a;print b} else {print c}",
+            format_nu_compact(nu)
+        );
+    }
+
+    #[test]
+    fn packs_collections() {
+        let nu = "{\n  name: \"nufmt\",\n  tags: [a, b\n    c],\n  nested: { x: 1 }\n}";
+        assert_eq!(
+            "{name:\"nufmt\",tags:[a,b c],nested:{x:1}}",
+            format_nu_compact(nu)
+        );
+        assert_eq!("[[a b];[1 2]]", format_nu_compact("[ [a b]; [1 2] ]"));
+    }
+
+    #[test]
+    fn keeps_meaningful_whitespace() {
+        let nu =
+            "^git commit -m 'a  b' \"c d\"\necho a , b\nlet r = (\n  1 +\n  2\n)\n{|x| $x + 1}";
+        assert_eq!(
+            "^git commit -m 'a  b' \"c d\";echo a , b;let r = (1 + 2);{|x|$x + 1}",
+            format_nu_compact(nu)
+        );
+    }
+
+    #[test]
+    fn separates_match_arms() {
+        let nu = "match ($x | get a) {\n  1 => {\n    a\n  }\n  _ =>\n    b\n}";
+        assert_eq!("match ($x|get a) {1 => {a},_ => b}", format_nu_compact(nu));
+    }
+
+    #[test]
+    fn keeps_shebang_and_attributes() {
+        let nu = "#!/usr/bin/env nu\n@example \"adds\" { add 1 2 }\ndef add [a b] {\n  $a + $b\n}";
+        assert_eq!(
+            "#!/usr/bin/env nu\n@example \"adds\" { add 1 2 }\ndef add [a b] {$a + $b}",
+            format_nu_compact(nu)
+        );
+    }
+
+    #[test]
+    fn compact_is_stable() {
+        let nu =
+            "def f [x: int] {\n  [1, 2] | each {|i| {v: $i}}\n}\nmatch 1 {\n  1 => a,\n  _ => b\n}";
+        let once = format_nu_compact(nu);
+        assert_eq!(once, format_nu_compact(&once));
+    }
+}
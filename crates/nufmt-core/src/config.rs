@@ -0,0 +1,256 @@
+//!
+//! The options given to the formatter, as opposed to the directives written in the nu.
+//!
+//! A [Config] holds the same options as directives, which still override it where they are
+//! written, so a config file and the comments of a script agree on names and values.
+//!
+//! A team shares its config in a `nufmt.toml`, applying to the scripts of its directory and
+//! below:
+//!
+//! ```toml
+//! indent = 4
+//! max_width = 100
+//! pipe_placement = "leading"
+//! item_spacing = true
+//! disable = ["colon-spacing"]
+//! ```
+//!
+//! Data and config subtrees may rather keep their diffs small with a preset of their own:
+//!
+//! ```toml
+//! preset = "diff-friendly"
+//! ```
+
+use crate::directive::Options;
+use crate::Indentation;
+use std::path::{Path, PathBuf};
+
+/// The names of config files, looked up in this order in every directory
+pub const CONFIG_FILES: &[&str] = &["nufmt.toml", ".nufmt.toml"];
+
+/// How to format, see [format_nu_with_config](crate::format_nu_with_config)
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub struct Config<'a> {
+    pub(crate) indentation: Indentation<'a>,
+    pub(crate) options: Options,
+    pub(crate) max_input_size: Option<usize>,
+}
+
+impl<'a> Config<'a> {
+    /// A builder starting from the defaults
+    pub fn builder() -> ConfigBuilder<'a> {
+        ConfigBuilder::default()
+    }
+
+    /// The indentation, which an `indent` option replaces
+    pub fn indentation(&self) -> Indentation<'a> {
+        self.indentation
+    }
+
+    /// The size in bytes above which the entry points holding the nu in memory refuse it
+    pub fn max_input_size(&self) -> Option<usize> {
+        self.max_input_size
+    }
+
+    /// A builder starting from this config, e.g. to override it with command line flags
+    pub fn to_builder(&self) -> ConfigBuilder<'a> {
+        ConfigBuilder {
+            config: self.clone(),
+        }
+    }
+}
+
+///
+/// # Finds the config file applying to a directory
+///
+/// The directory and then its parents are searched, the nearest file wins.
+///
+pub fn find_config_file(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .flat_map(|dir| CONFIG_FILES.iter().map(move |name| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+///
+/// # Finds the config file applying to a directory of a workspace
+///
+/// Like [find_config_file], but the search stops at `root`, so the folders of a multi-root
+/// workspace each get their own config and none from above them.
+///
+pub fn find_config_file_within(dir: &Path, root: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .take_while(|dir| dir.starts_with(root))
+        .flat_map(|dir| CONFIG_FILES.iter().map(move |name| dir.join(name)))
+        .find(|path| path.is_file())
+}
+
+/// Reads a TOML config file
+#[cfg(feature = "config-file")]
+pub fn load_config(path: &Path) -> Result<Config<'static>, crate::Error> {
+    let text = std::fs::read_to_string(path)?;
+    toml::from_str(&text).map_err(|err| crate::Error::InvalidConfig {
+        path: path.to_path_buf(),
+        message: err.to_string(),
+    })
+}
+
+///
+/// # Reads a config from a table of options
+///
+/// The keys are the names of the options of directives, `indent` setting the indentation. Values
+/// may be written as booleans, numbers or strings, `disable` and `enable` also take lists of rules.
+/// Unlike in directives, unknown keys and invalid values are errors. A `preset` is applied first,
+/// so the other keys of the table override it wherever they are written.
+///
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Config<'static> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use crate::directive::{parse_indent, OPTION_VALUES};
+        use serde::de::Error;
+
+        #[derive(serde::Deserialize)]
+        #[serde(untagged)]
+        enum Value {
+            Flag(bool),
+            Number(u64),
+            Text(String),
+            List(Vec<String>),
+        }
+
+        let table = std::collections::BTreeMap::<String, Value>::deserialize(deserializer)?;
+        let mut table: Vec<_> = table.into_iter().collect();
+        table.sort_by_key(|(key, _)| key != "preset");
+        let mut config = Config::default();
+        for (key, value) in table {
+            let known = key == "enable" || OPTION_VALUES.iter().any(|(name, _)| *name == key);
+            if !known {
+                return Err(D::Error::custom(format!("unknown option `{}`", key)));
+            }
+            let values = match value {
+                Value::Flag(enabled) => vec![enabled.to_string()],
+                Value::Number(number) => vec![number.to_string()],
+                Value::Text(text) => vec![text],
+                Value::List(list) => list,
+            };
+            for value in values {
+                let valid = if key == "indent" {
+                    parse_indent(&value)
+                        .map(|indent| config.indentation = indent)
+                        .is_some()
+                } else {
+                    config.options.apply(&key, &value)
+                };
+                if !valid {
+                    let message = format!("invalid value `{}` for `{}`", value, key);
+                    return Err(D::Error::custom(message));
+                }
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Builds a [Config], every option left unset keeps its default
+#[derive(Debug, Clone, Default)]
+pub struct ConfigBuilder<'a> {
+    config: Config<'a>,
+}
+
+impl<'a> ConfigBuilder<'a> {
+    /// Sets the indentation, two spaces by default
+    pub fn indentation(mut self, indentation: Indentation<'a>) -> Self {
+        self.config.indentation = indentation;
+        self
+    }
+
+    /// Sets the width lines are kept within where the layout depends on it, 100 by default
+    pub fn max_width(mut self, width: usize) -> Self {
+        self.config.options.apply("max_width", &width.to_string());
+        self
+    }
+
+    ///
+    /// # Sets an option by its directive name
+    ///
+    /// Any `key=value` pair a directive accepts, e.g. `("item_spacing", "true")`. Unknown keys and
+    /// invalid values are ignored, as in directives.
+    ///
+    pub fn option(mut self, key: &str, value: &str) -> Self {
+        self.config.options.apply(key, value);
+        self
+    }
+
+    ///
+    /// # Sets the size in bytes above which the entry points holding the nu in memory refuse it
+    ///
+    /// Unlimited by default. A service formatting the scripts of its requests bounds the memory of
+    /// each request with it, [try_format_nu_with_config](crate::try_format_nu_with_config) failing
+    /// with [Error::InputTooLarge](crate::Error::InputTooLarge) beyond it.
+    ///
+    pub fn max_input_size(mut self, bytes: usize) -> Self {
+        self.config.max_input_size = Some(bytes);
+        self
+    }
+
+    pub fn build(self) -> Config<'a> {
+        self.config
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builds_options() {
+        let config = Config::builder()
+            .indentation(Indentation::Tabs(1))
+            .max_width(0)
+            .max_width(40)
+            .option("item_spacing", "true")
+            .option("unknown", "true")
+            .build();
+        assert_eq!(Indentation::Tabs(1), config.indentation());
+        assert_eq!(Some(40), config.options.max_width);
+        assert!(config.options.item_spacing);
+    }
+
+    #[cfg(feature = "config-file")]
+    #[test]
+    fn reads_toml() {
+        let text = "indent = \"tab\"\nmax_width = 100\npipe_placement = \"leading\"\nlist_fill = true\ndisable = [\"colon-spacing\"]";
+        let config: Config = toml::from_str(text).unwrap();
+        let expected = Config::builder()
+            .indentation(Indentation::Tabs(1))
+            .max_width(100)
+            .option("pipe_placement", "leading")
+            .option("list_fill", "true")
+            .option("disable", "colon-spacing")
+            .build();
+        assert_eq!(expected, config);
+
+        let invalid = |text: &str| toml::from_str::<Config>(text).unwrap_err().to_string();
+        assert!(invalid("indentation = 4").starts_with("unknown option `indentation`"));
+        assert!(invalid("list_fill = \"yes\"").starts_with("invalid value `yes` for `list_fill`"));
+
+        let text = "break_pipelines = false\npreset = \"diff-friendly\"";
+        let config: Config = toml::from_str(text).unwrap();
+        assert!(config.options.trailing_commas && !config.options.break_pipelines);
+    }
+
+    #[test]
+    fn finds_the_nearest_config_file() {
+        let root = std::env::temp_dir().join(format!("nufmt-config-{}", std::process::id()));
+        let nested = root.join("a").join("b");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(root.join("nufmt.toml"), "").unwrap();
+        std::fs::write(root.join("a").join(".nufmt.toml"), "").unwrap();
+
+        let found = find_config_file(&nested);
+        let within = find_config_file_within(&nested, &root.join("a").join("b"));
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(Some(root.join("a").join(".nufmt.toml")), found);
+        assert_eq!(None, within);
+    }
+}
@@ -0,0 +1,932 @@
+//!
+//! A long running formatting service, saving editors the startup cost of a process per format.
+//!
+//! Clients connect over TCP and send one JSON request per line, each answered by one JSON response
+//! line:
+//!
+//! ```text
+//! {"id": 1, "source": "[1,2]", "indent": "    "}
+//! {"id":1,"formatted":"[\n    1,\n    2\n]","changed":true}
+//! ```
+//!
+//! `id` is echoed back as is and `indent` is optional. Invalid requests get an `error` response.
+//!
+//! The source is formatted with the options of the command line for the same file: `path` is the
+//! file the source is from, formatted with the nearest [config file](crate::config) of its
+//! directory or above, and `config` a config file to use instead. `indent` overrides both. Config
+//! files are read once and read again when they change.
+//!
+//! ```text
+//! {"id": 2, "source": "[1,2]", "path": "/project/scripts/build.nu"}
+//! ```
+//!
+//! Editors with several folders open send them as `workspace_folders`, after which the config
+//! files of a folder are looked up within it only. They send `changed` files to have config files
+//! read again right away. Both are answered with the `id` alone:
+//!
+//! ```text
+//! {"id": 3, "workspace_folders": ["/project/api", "/project/web"], "changed": []}
+//! {"id":3}
+//! ```
+//!
+//! Documents other than nu ones are sent back unchanged with a `diagnostic`, by their
+//! `language_id` (`nu`, `nushell` or `nuon`) or else their `path`: a `.nu` or `.nuon` extension,
+//! or no extension and a nu shebang.
+//!
+//! With `"folding": true`, the response also holds the [folding ranges](crate::folding) of the
+//! source as sent, for editors using the daemon as their language support. They are worked out
+//! along with the formatted source, within the same budget:
+//!
+//! ```text
+//! {"id": 4, "source": "[\n1]", "folding": true}
+//! {"id":4,"formatted":"[\n  1\n]","changed":true,"folding_ranges":[{"start_line":1,"end_line":2,"kind":"collection"}]}
+//! ```
+//!
+//! With a `cursor`, a byte offset of the source, the response holds the code actions there
+//! instead of the formatted source. Formatting the block, definition or pipeline of the cursor is
+//! offered unless it is formatted already, with the config and within the budget of formatting
+//! the whole source:
+//!
+//! ```text
+//! {"id": 5, "source": "def f [] {\nls}", "cursor": 12}
+//! {"id":5,"code_actions":[{"title":"Format this block","start":0,"end":14,"formatted":"def f [] {\n  ls\n}"}]}
+//! ```
+//!
+//! Editors format on save, so a source is not formatted beyond the [Budget] of the daemon. It is
+//! then sent back unchanged along with a `diagnostic`, and the editor has no edits to apply. The
+//! requests of a workspace may set their own budget with `max_size`, in bytes, and `timeout_ms`:
+//!
+//! ```text
+//! {"id": 6, "source": "ls", "max_size": 1}
+//! {"id":6,"formatted":"ls","changed":false,"diagnostic":"not formatted: the source is 2 bytes, more than the 1 of the budget"}
+//! ```
+
+use crate::config::{find_config_file, find_config_file_within, load_config, Config};
+use crate::folding::{folding_ranges, FoldingRange};
+use crate::range::{block_at, format_range};
+use crate::{format_nu_with_config, Indentation};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, SystemTime};
+
+/// The address the daemon listens on by default
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7865";
+
+/// How many formatted sources are remembered
+const CACHE_CAPACITY: usize = 512;
+
+/// The largest source formatted by default, in bytes
+pub const DEFAULT_MAX_SIZE: usize = 1024 * 1024;
+
+/// How long formatting a source may take by default
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How many sources may be formatted at once by default
+pub const DEFAULT_MAX_JOBS: usize = 4;
+
+/// How many times longer than the largest source a request line may be, JSON escapes taking up to
+/// six bytes per byte of the source
+const LINE_SIZE_FACTOR: usize = 6;
+
+/// The room left on a request line for the fields besides the source, in bytes
+const LINE_OVERHEAD: usize = 64 * 1024;
+
+/// How large a source may be and how long formatting it may take, see the [module](self)
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Budget {
+    /// The largest source formatted, in bytes. Request lines are read up to a size derived from
+    /// it, longer ones are answered with an error without being held in memory.
+    pub max_size: usize,
+    /// How long formatting may take before the source is sent back unchanged. The formatting
+    /// goes on in the background, but its result is thrown away.
+    pub timeout: Duration,
+    /// How many sources may be formatted at once, those still formatted in the background
+    /// included. Sources sent beyond it are sent back unchanged until one is done.
+    pub max_jobs: usize,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget {
+            max_size: DEFAULT_MAX_SIZE,
+            timeout: DEFAULT_TIMEOUT,
+            max_jobs: DEFAULT_MAX_JOBS,
+        }
+    }
+}
+
+impl Budget {
+    /// The longest request line read, in bytes
+    fn max_line(&self) -> usize {
+        self.max_size
+            .saturating_mul(LINE_SIZE_FACTOR)
+            .saturating_add(LINE_OVERHEAD)
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: serde_json::Value,
+    source: String,
+    indent: Option<String>,
+    path: Option<PathBuf>,
+    config: Option<PathBuf>,
+    /// The language of the document as the editor knows it, e.g. `nu`
+    language_id: Option<String>,
+    #[serde(default)]
+    folding: bool,
+    /// A byte offset of the source, asking for the code actions there rather than a format
+    cursor: Option<usize>,
+    max_size: Option<usize>,
+    timeout_ms: Option<u64>,
+}
+
+/// A message about the workspace rather than a source, answered with its `id` alone
+#[derive(Deserialize)]
+struct Notification {
+    #[serde(default)]
+    id: serde_json::Value,
+    /// The folders open in the editor, replacing those sent before
+    workspace_folders: Option<Vec<PathBuf>>,
+    /// The files which changed, config files among them read again
+    changed: Option<Vec<PathBuf>>,
+}
+
+#[derive(Serialize)]
+struct Response {
+    id: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    formatted: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    changed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diagnostic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    folding_ranges: Option<Vec<FoldingRange>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code_actions: Option<Vec<CodeAction>>,
+}
+
+/// An edit offered at the cursor, replacing the bytes from `start` to `end` of the source
+#[derive(Debug, Serialize)]
+struct CodeAction {
+    title: &'static str,
+    start: usize,
+    end: usize,
+    formatted: String,
+}
+
+impl Response {
+    fn new(id: serde_json::Value) -> Self {
+        Response {
+            id,
+            formatted: None,
+            changed: None,
+            error: None,
+            diagnostic: None,
+            folding_ranges: None,
+            code_actions: None,
+        }
+    }
+
+    fn error(id: serde_json::Value, error: String) -> Self {
+        Response {
+            error: Some(error),
+            ..Response::new(id)
+        }
+    }
+}
+
+///
+/// # What the daemon remembers between requests
+///
+/// Every part is locked on its own and only to be read or updated, never while a source is
+/// formatted, so a slow source does not hold up the requests of other clients.
+///
+#[derive(Default)]
+pub struct Cache {
+    /// Formatted sources by hash of their source and options
+    formatted: Mutex<HashMap<u64, String>>,
+    /// The sources which took longer to format than a timeout, not formatted again within it
+    timed_out: Mutex<HashMap<u64, Duration>>,
+    /// The config files read, with their modification time when they were
+    configs: Mutex<HashMap<PathBuf, (Option<SystemTime>, Config<'static>)>>,
+    /// The folders of the workspace, each with its own config files
+    workspace_folders: Mutex<Vec<PathBuf>>,
+    /// What is parsed out of sources rather than formatted, by hash of the source
+    parsed: Mutex<HashMap<u64, Vec<FoldingRange>>>,
+    /// How many sources are being formatted, by threads which may outlive their request
+    jobs: Arc<AtomicUsize>,
+}
+
+/// A source being formatted, counted in the jobs of the [Cache] until the thread formatting it
+/// ends, also by panicking
+struct Job(Arc<AtomicUsize>);
+
+impl Job {
+    /// Starts a job, unless `max_jobs` are running already
+    fn start(jobs: &Arc<AtomicUsize>, max_jobs: usize) -> Option<Job> {
+        jobs.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |running| {
+            (running < max_jobs).then(|| running + 1)
+        })
+        .ok()
+        .map(|_| Job(Arc::clone(jobs)))
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Why a source was not formatted
+enum Skipped {
+    /// It is beyond the budget of the request, for the reason of the diagnostic
+    OverBudget(String),
+    /// Formatting it panicked, which is a bug of nufmt
+    Failed,
+}
+
+/// Locks a part of the cache, which stays usable if a thread panicked while holding it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Whether a document is nu, editors sending the other files of a workspace as well
+fn is_nu(path: Option<&Path>, language_id: Option<&str>, source: &str) -> bool {
+    if let Some(language_id) = language_id {
+        return matches!(language_id, "nu" | "nushell" | "nuon");
+    }
+    match path {
+        Some(path) => match path.extension() {
+            Some(extension) => extension == "nu" || extension == "nuon",
+            // scripts without an extension run nu with their shebang, `#!/usr/bin/env nu`
+            None => source.lines().next().is_some_and(|line| {
+                line.starts_with("#!")
+                    && line
+                        .split(|c: char| c == '/' || c.is_whitespace())
+                        .any(|word| word == "nu")
+            }),
+        },
+        None => true,
+    }
+}
+
+fn hash_of(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Cache {
+    /// The config of a request, read again if its file changed since it was last read
+    fn config(&self, request: &Request) -> Result<Config<'static>, String> {
+        let file = match (&request.config, &request.path) {
+            (Some(config), _) => Some(config.clone()),
+            (None, Some(path)) => {
+                let dir = path.parent();
+                // the innermost folder holding the file, config files above it belong to others
+                let folder = lock(&self.workspace_folders)
+                    .iter()
+                    .filter(|folder| path.starts_with(folder))
+                    .max_by_key(|folder| folder.components().count())
+                    .cloned();
+                match folder {
+                    Some(folder) => dir.and_then(|dir| find_config_file_within(dir, &folder)),
+                    None => dir.and_then(find_config_file),
+                }
+            }
+            (None, None) => None,
+        };
+        let file = match file {
+            Some(file) => file,
+            None => return Ok(Config::default()),
+        };
+        let modified = std::fs::metadata(&file)
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        if let Some((read, config)) = lock(&self.configs).get(&file) {
+            if *read == modified {
+                return Ok(config.clone());
+            }
+        }
+        let config = load_config(&file).map_err(|err| err.to_string())?;
+        lock(&self.configs).insert(file, (modified, config.clone()));
+        Ok(config)
+    }
+
+    ///
+    /// # Formats a source, along with its folding ranges if `folding`
+    ///
+    /// Both are worked out by the same job, within the budget, unless they are cached.
+    ///
+    fn format(
+        &self,
+        source: &str,
+        indent: Option<&str>,
+        config: Config<'static>,
+        budget: Budget,
+        folding: bool,
+    ) -> Result<(String, Option<Vec<FoldingRange>>), Skipped> {
+        let key = hash_of((source, indent, &config));
+        let parsed_key = hash_of(source);
+
+        let formatted = lock(&self.formatted).get(&key).cloned();
+        let ranges = match folding {
+            true => lock(&self.parsed).get(&parsed_key).cloned().map(Some),
+            false => Some(None),
+        };
+        if let (Some(formatted), Some(ranges)) = (&formatted, &ranges) {
+            return Ok((formatted.clone(), ranges.clone()));
+        }
+
+        let (nu, indent) = (source.to_string(), indent.map(str::to_string));
+        let (formatted, ranges) = self.run(key, source.len(), budget, move || {
+            let formatted = formatted.unwrap_or_else(|| {
+                let config = match indent.as_deref() {
+                    Some(indent) => config
+                        .to_builder()
+                        .indentation(Indentation::of(indent))
+                        .build(),
+                    None => config,
+                };
+                format_nu_with_config(&nu, &config)
+            });
+            let ranges = ranges.unwrap_or_else(|| Some(folding_ranges(&nu)));
+            (formatted, ranges)
+        })?;
+
+        let mut cache = lock(&self.formatted);
+        if cache.len() >= CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(key, formatted.clone());
+        if let Some(ranges) = &ranges {
+            let mut parsed = lock(&self.parsed);
+            if parsed.len() >= CACHE_CAPACITY {
+                parsed.clear();
+            }
+            parsed.insert(parsed_key, ranges.clone());
+        }
+        Ok((formatted, ranges))
+    }
+
+    ///
+    /// # Runs the work on a source within the budget
+    ///
+    /// The work runs on a thread of its own, counted in the jobs, so that the request can be
+    /// answered in time. The work of a `key` which took longer than a timeout is not run again
+    /// within it.
+    ///
+    fn run<T, F>(&self, key: u64, size: usize, budget: Budget, work: F) -> Result<T, Skipped>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        if size > budget.max_size {
+            return Err(Skipped::OverBudget(format!(
+                "the source is {} bytes, more than the {} of the budget",
+                size, budget.max_size
+            )));
+        }
+        let timed_out = |timeout: Duration| {
+            Skipped::OverBudget(format!(
+                "formatting takes longer than the {} ms of the budget",
+                timeout.as_millis()
+            ))
+        };
+        if lock(&self.timed_out)
+            .get(&key)
+            .is_some_and(|&timeout| budget.timeout <= timeout)
+        {
+            return Err(timed_out(budget.timeout));
+        }
+
+        let job = Job::start(&self.jobs, budget.max_jobs).ok_or_else(|| {
+            Skipped::OverBudget(format!(
+                "the {} formatting jobs of the budget are all busy",
+                budget.max_jobs
+            ))
+        })?;
+
+        let (sender, receiver) = channel();
+        std::thread::spawn(move || {
+            let done = work();
+            // the request may be answered by now, another source can be formatted
+            drop(job);
+            let _ = sender.send(done);
+        });
+        match receiver.recv_timeout(budget.timeout) {
+            Ok(done) => Ok(done),
+            Err(RecvTimeoutError::Timeout) => {
+                let mut cache = lock(&self.timed_out);
+                if cache.len() >= CACHE_CAPACITY {
+                    cache.clear();
+                }
+                cache.insert(key, budget.timeout);
+                Err(timed_out(budget.timeout))
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(Skipped::Failed),
+        }
+    }
+
+    fn answer(&self, request: Request, budget: Budget) -> Response {
+        let budget = Budget {
+            max_size: request.max_size.unwrap_or(budget.max_size),
+            timeout: request
+                .timeout_ms
+                .map_or(budget.timeout, Duration::from_millis),
+            max_jobs: budget.max_jobs,
+        };
+        let language_id = request.language_id.as_deref();
+        if !is_nu(request.path.as_deref(), language_id, &request.source) {
+            return Response {
+                formatted: Some(request.source),
+                changed: Some(false),
+                diagnostic: Some("not formatted: the document is not nu".to_string()),
+                ..Response::new(request.id)
+            };
+        }
+        let config = match self.config(&request) {
+            Ok(config) => config,
+            Err(err) => return Response::error(request.id, format!("invalid config: {}", err)),
+        };
+        if let Some(cursor) = request.cursor {
+            return self.code_actions(request, cursor, config, budget);
+        }
+        let indent = request.indent.as_deref();
+        match self.format(&request.source, indent, config, budget, request.folding) {
+            Ok((formatted, folding)) => Response {
+                changed: Some(formatted != request.source),
+                formatted: Some(formatted),
+                folding_ranges: folding,
+                ..Response::new(request.id)
+            },
+            // no edits, the editor goes on with the source as it is
+            Err(Skipped::OverBudget(reason)) => Response {
+                formatted: Some(request.source),
+                changed: Some(false),
+                diagnostic: Some(format!("not formatted: {}", reason)),
+                ..Response::new(request.id)
+            },
+            Err(Skipped::Failed) => Response::error(
+                request.id,
+                "formatting failed, this is a bug of nufmt".to_string(),
+            ),
+        }
+    }
+
+    /// The code actions at the cursor: formatting the block, definition or pipeline it is in,
+    /// offered only if formatting changes it. The block is formatted like the whole source, with
+    /// its config and within the budget.
+    fn code_actions(
+        &self,
+        request: Request,
+        cursor: usize,
+        config: Config<'static>,
+        budget: Budget,
+    ) -> Response {
+        let key = hash_of((&request.source, cursor, &request.indent, &config));
+        let (source, indent) = (request.source.clone(), request.indent.clone());
+        let actions = self.run(key, source.len(), budget, move || {
+            let config = match indent.as_deref() {
+                Some(indent) => config
+                    .to_builder()
+                    .indentation(Indentation::of(indent))
+                    .build(),
+                None => config,
+            };
+            block_at(&source, cursor)
+                .map(|block| format_range(&source, block, &config))
+                .filter(|block| block.formatted != source[block.range.clone()])
+                .map(|block| CodeAction {
+                    title: "Format this block",
+                    start: block.range.start,
+                    end: block.range.end,
+                    formatted: block.formatted,
+                })
+        });
+        match actions {
+            Ok(actions) => Response {
+                code_actions: Some(actions.into_iter().collect()),
+                ..Response::new(request.id)
+            },
+            Err(Skipped::OverBudget(reason)) => Response {
+                code_actions: Some(Vec::new()),
+                diagnostic: Some(format!("no code actions: {}", reason)),
+                ..Response::new(request.id)
+            },
+            Err(Skipped::Failed) => Response::error(
+                request.id,
+                "formatting failed, this is a bug of nufmt".to_string(),
+            ),
+        }
+    }
+
+    fn notify(&self, notification: Notification) -> Response {
+        if let Some(folders) = notification.workspace_folders {
+            *lock(&self.workspace_folders) = folders;
+        }
+        if let Some(changed) = notification.changed {
+            let mut configs = lock(&self.configs);
+            for file in changed {
+                configs.remove(&file);
+            }
+        }
+        Response::new(notification.id)
+    }
+}
+
+///
+/// # Answers a request line
+///
+/// Returns the response line, without its newline. The `max_size` and `timeout_ms` of the request
+/// override those of `budget`.
+///
+pub fn handle_request(line: &str, cache: &Cache, budget: Budget) -> String {
+    let response = match serde_json::from_str::<Request>(line) {
+        Ok(request) => cache.answer(request, budget),
+        Err(err) => match serde_json::from_str::<Notification>(line) {
+            Ok(notification)
+                if notification.workspace_folders.is_some() || notification.changed.is_some() =>
+            {
+                cache.notify(notification)
+            }
+            _ => Response::error(serde_json::Value::Null, format!("invalid request: {}", err)),
+        },
+    };
+
+    serde_json::to_string(&response).expect("responses are always serializable")
+}
+
+///
+/// # Serves format requests until the process is stopped
+///
+/// Every connection is handled on its own thread, all sharing the same cache and `budget`.
+///
+pub fn serve(listener: TcpListener, budget: Budget) -> std::io::Result<()> {
+    let cache = Arc::new(Cache::default());
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let cache = Arc::clone(&cache);
+        std::thread::spawn(move || {
+            // a client going away is not an error of the daemon
+            let _ = serve_connection(stream, &cache, budget);
+        });
+    }
+    Ok(())
+}
+
+fn serve_connection(stream: TcpStream, cache: &Cache, budget: Budget) -> std::io::Result<()> {
+    let writer = stream.try_clone()?;
+    serve_lines(BufReader::new(stream), writer, cache, budget)
+}
+
+/// Answers the request lines of `reader` on `writer`, reading no more of a line than the budget
+/// allows
+fn serve_lines(
+    mut reader: impl BufRead,
+    mut writer: impl Write,
+    cache: &Cache,
+    budget: Budget,
+) -> std::io::Result<()> {
+    let max_line = budget.max_line();
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let limit = max_line as u64 + 1;
+        if (&mut reader).take(limit).read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        let ended = line.ends_with(b"\n");
+        let response = if line.len() - usize::from(ended) > max_line {
+            if !ended {
+                reader.skip_until(b'\n')?;
+            }
+            let error = format!("invalid request: longer than {} bytes", max_line);
+            let response = Response::error(serde_json::Value::Null, error);
+            serde_json::to_string(&response).expect("responses are always serializable")
+        } else {
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            handle_request(&line, cache, budget)
+        };
+        writer.write_all(response.as_bytes())?;
+        writer.write_all(b"\n")?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats_requests() {
+        let cache = Cache::default();
+        let response = handle_request(r#"{"id": 7, "source": "[1,2]"}"#, &cache, Budget::default());
+        assert_eq!(r#"{"id":7,"formatted":"[1, 2]","changed":true}"#, response);
+    }
+
+    #[test]
+    fn custom_indent_and_cache() {
+        let cache = Cache::default();
+        let request = r#"{"id": "a", "source": "{a: 1}", "indent": "\t"}"#;
+        let expected = r#"{"id":"a","formatted":"{a: 1}","changed":false}"#;
+        assert_eq!(expected, handle_request(request, &cache, Budget::default()));
+        assert_eq!(expected, handle_request(request, &cache, Budget::default()));
+        assert_eq!(1, lock(&cache.formatted).len());
+    }
+
+    #[test]
+    fn config_files() {
+        let root = std::env::temp_dir().join(format!("nufmt-daemon-{}", std::process::id()));
+        let nested = root.join("scripts");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(root.join("nufmt.toml"), "indent = 4").unwrap();
+        let other = root.join("other.toml");
+        std::fs::write(&other, "indent = \"tab\"").unwrap();
+
+        let cache = Cache::default();
+        let request =
+            serde_json::json!({"id": 1, "source": "{a: 1\n}", "path": nested.join("a.nu")});
+        let by_path = handle_request(&request.to_string(), &cache, Budget::default());
+        let request = serde_json::json!({"id": 2, "source": "{a: 1\n}", "config": other});
+        let by_config = handle_request(&request.to_string(), &cache, Budget::default());
+        let request =
+            serde_json::json!({"id": 3, "source": "ls", "config": root.join("none.toml")});
+        let missing = handle_request(&request.to_string(), &cache, Budget::default());
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(
+            r#"{"id":1,"formatted":"{\n    a: 1\n}","changed":true}"#,
+            by_path
+        );
+        assert_eq!(
+            r#"{"id":2,"formatted":"{\n\ta: 1\n}","changed":true}"#,
+            by_config
+        );
+        assert!(missing.starts_with(r#"{"id":3,"error":"invalid config: "#));
+        assert_eq!(2, lock(&cache.configs).len());
+    }
+
+    #[test]
+    fn workspace_folders() {
+        let root = std::env::temp_dir().join(format!("nufmt-workspaces-{}", std::process::id()));
+        let (api, web) = (root.join("api"), root.join("web"));
+        std::fs::create_dir_all(&api).unwrap();
+        std::fs::create_dir_all(&web).unwrap();
+        std::fs::write(root.join("nufmt.toml"), "indent = 4").unwrap();
+        std::fs::write(api.join("nufmt.toml"), "indent = \"tab\"").unwrap();
+
+        let cache = Cache::default();
+        let format = |id: u32, folder: &Path| {
+            let request =
+                serde_json::json!({"id": id, "source": "{a: 1\n}", "path": folder.join("a.nu")});
+            handle_request(&request.to_string(), &cache, Budget::default())
+        };
+        let notification = serde_json::json!({"id": 1, "workspace_folders": [&api, &web]});
+        let answer = handle_request(&notification.to_string(), &cache, Budget::default());
+        let (in_api, in_web) = (format(2, &api), format(3, &web));
+
+        // a config file changing within the modification time of the one read
+        std::fs::write(api.join("nufmt.toml"), "indent = 3").unwrap();
+        let notification = serde_json::json!({"id": 4, "changed": [api.join("nufmt.toml")]});
+        handle_request(&notification.to_string(), &cache, Budget::default());
+        let changed = format(5, &api);
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(r#"{"id":1}"#, answer);
+        assert_eq!(
+            r#"{"id":2,"formatted":"{\n\ta: 1\n}","changed":true}"#,
+            in_api
+        );
+        // the config file above the folder is not the one of its workspace
+        assert_eq!(
+            r#"{"id":3,"formatted":"{\n  a: 1\n}","changed":true}"#,
+            in_web
+        );
+        assert_eq!(
+            r#"{"id":5,"formatted":"{\n   a: 1\n}","changed":true}"#,
+            changed
+        );
+    }
+
+    #[test]
+    fn code_actions() {
+        let cache = Cache::default();
+        let source = "ls\ndef f [] {\nps}\nlet a = [1, 2]";
+        let at = |id: u32, cursor: usize| {
+            let request = serde_json::json!({"id": id, "source": source, "cursor": cursor});
+            handle_request(&request.to_string(), &cache, Budget::default())
+        };
+        assert_eq!(
+            r#"{"id":1,"code_actions":[{"title":"Format this block","start":3,"end":17,"formatted":"def f [] {\n  ps\n}"}]}"#,
+            at(1, 14)
+        );
+        // already formatted
+        assert_eq!(r#"{"id":2,"code_actions":[]}"#, at(2, 20));
+        assert!(lock(&cache.formatted).is_empty());
+
+        // formatted with the options of the config file, like the whole source
+        let root = std::env::temp_dir().join(format!("nufmt-actions-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("nufmt.toml"), "trailing_commas = true").unwrap();
+        let source = "let a = {b: 1,\nc: 2}";
+        let request =
+            serde_json::json!({"id": 3, "source": source, "cursor": 0, "path": root.join("a.nu")});
+        let with_config = handle_request(&request.to_string(), &cache, Budget::default());
+        std::fs::remove_dir_all(&root).unwrap();
+        assert_eq!(
+            r#"{"id":3,"code_actions":[{"title":"Format this block","start":0,"end":20,"formatted":"let a = {\n  b: 1,\n  c: 2,\n}"}]}"#,
+            with_config
+        );
+
+        // and within the budget
+        let budget = Budget {
+            max_jobs: 0,
+            ..Budget::default()
+        };
+        let request = serde_json::json!({"id": 4, "source": source, "cursor": 0});
+        assert_eq!(
+            r#"{"id":4,"diagnostic":"no code actions: the 0 formatting jobs of the budget are all busy","code_actions":[]}"#,
+            handle_request(&request.to_string(), &cache, budget)
+        );
+    }
+
+    #[test]
+    fn dialects() {
+        let cache = Cache::default();
+        let format = |request: serde_json::Value| {
+            handle_request(&request.to_string(), &cache, Budget::default())
+        };
+        let not_nu = |id: u32| {
+            format!(
+                r#"{{"id":{},"formatted":"[1,2]","changed":false,"diagnostic":"not formatted: the document is not nu"}}"#,
+                id
+            )
+        };
+
+        assert_eq!(
+            not_nu(1),
+            format(serde_json::json!({"id": 1, "source": "[1,2]", "path": "/p/a.json"}))
+        );
+        assert_eq!(
+            not_nu(2),
+            format(serde_json::json!({"id": 2, "source": "[1,2]", "language_id": "json"}))
+        );
+        assert_eq!(
+            not_nu(3),
+            format(serde_json::json!({"id": 3, "source": "[1,2]", "path": "/p/build"}))
+        );
+        let formatted = r#""formatted":"[1, 2]","changed":true}"#;
+        let requests = [
+            serde_json::json!({"id": 4, "source": "[1,2]", "path": "/p/a.nuon"}),
+            serde_json::json!({"id": 4, "source": "[1,2]", "path": "/p/a", "language_id": "nu"}),
+            serde_json::json!({"id": 4, "source": "[1,2]"}),
+        ];
+        for request in requests {
+            assert_eq!(format!(r#"{{"id":4,{}"#, formatted), format(request));
+        }
+        assert!(is_nu(
+            Some(Path::new("/p/build")),
+            None,
+            "#!/usr/bin/env nu\nls"
+        ));
+        assert!(!is_nu(Some(Path::new("/p/build")), None, "#!/bin/sh\nls"));
+    }
+
+    #[test]
+    fn folding_requests() {
+        let cache = Cache::default();
+        let response = handle_request(
+            r#"{"id": 2, "source": "[\n1]", "folding": true}"#,
+            &cache,
+            Budget::default(),
+        );
+        assert_eq!(
+            r#"{"id":2,"formatted":"[\n  1\n]","changed":true,"folding_ranges":[{"start_line":1,"end_line":2,"kind":"collection"}]}"#,
+            response
+        );
+        assert_eq!(1, lock(&cache.parsed).len());
+
+        // folding shares the budget of formatting
+        let budget = Budget {
+            timeout: Duration::from_millis(0),
+            ..Budget::default()
+        };
+        let source = "[\n1]\n".repeat(20_000);
+        let request = serde_json::json!({"id": 3, "source": source, "folding": true});
+        let response = handle_request(&request.to_string(), &cache, budget);
+        assert!(response.ends_with(
+            r#""changed":false,"diagnostic":"not formatted: formatting takes longer than the 0 ms of the budget"}"#
+        ));
+        assert_eq!(1, lock(&cache.parsed).len());
+
+        let budget = Budget {
+            max_jobs: 0,
+            ..Budget::default()
+        };
+        let request = r#"{"id": 4, "source": "[\n2]", "folding": true}"#;
+        assert_eq!(
+            r#"{"id":4,"formatted":"[\n2]","changed":false,"diagnostic":"not formatted: the 0 formatting jobs of the budget are all busy"}"#,
+            handle_request(request, &cache, budget)
+        );
+    }
+
+    #[test]
+    fn requests_beyond_the_budget() {
+        let cache = Cache::default();
+        let response = handle_request(
+            r#"{"id": 3, "source": "[1,2]", "max_size": 4, "folding": true}"#,
+            &cache,
+            Budget::default(),
+        );
+        assert_eq!(
+            r#"{"id":3,"formatted":"[1,2]","changed":false,"diagnostic":"not formatted: the source is 5 bytes, more than the 4 of the budget"}"#,
+            response
+        );
+
+        let budget = Budget {
+            timeout: Duration::from_millis(0),
+            ..Budget::default()
+        };
+        let source = "[1,2]\n".repeat(20_000);
+        let request = serde_json::json!({"id": 4, "source": source}).to_string();
+        let response = handle_request(&request, &cache, budget);
+        assert!(response.ends_with(
+            r#""changed":false,"diagnostic":"not formatted: formatting takes longer than the 0 ms of the budget"}"#
+        ));
+        assert_eq!(1, lock(&cache.timed_out).len());
+
+        // a workspace may allow more time
+        let request = serde_json::json!({"id": 4, "source": source, "timeout_ms": 60_000});
+        let response = handle_request(&request.to_string(), &cache, budget);
+        assert!(response.ends_with(r#""changed":true}"#));
+    }
+
+    #[test]
+    fn bounded_jobs() {
+        let cache = Cache::default();
+        let budget = Budget {
+            max_jobs: 1,
+            ..Budget::default()
+        };
+        let job = Job::start(&cache.jobs, budget.max_jobs);
+        assert!(job.is_some());
+        let response = handle_request(r#"{"id": 5, "source": "ls"}"#, &cache, budget);
+        assert_eq!(
+            r#"{"id":5,"formatted":"ls","changed":false,"diagnostic":"not formatted: the 1 formatting jobs of the budget are all busy"}"#,
+            response
+        );
+
+        drop(job);
+        let response = handle_request(r#"{"id": 5, "source": "ls"}"#, &cache, budget);
+        assert_eq!(r#"{"id":5,"formatted":"ls","changed":false}"#, response);
+        assert_eq!(0, cache.jobs.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn long_request_lines() {
+        let budget = Budget {
+            max_size: 4,
+            ..Budget::default()
+        };
+        let long = format!(
+            r#"{{"id": 1, "source": "{}"}}"#,
+            "a".repeat(budget.max_line())
+        );
+        let input = format!("{}\n{}\n", long, r#"{"id": 2, "source": "ls"}"#);
+        let mut output = Vec::new();
+        serve_lines(input.as_bytes(), &mut output, &Cache::default(), budget).unwrap();
+        let expected = format!(
+            "{}\n{}\n",
+            r#"{"id":null,"error":"invalid request: longer than 65560 bytes"}"#,
+            r#"{"id":2,"formatted":"ls","changed":false}"#
+        );
+        assert_eq!(expected, String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn invalid_request() {
+        let response = handle_request("{\"id\": 1}", &Cache::default(), Budget::default());
+        assert!(
+            response.starts_with(r#"{"id":null,"error":"invalid request: missing field `source`"#)
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//!
+//! Line based unified diffs between an original and a formatted nu script.
+//!
+//! The output is compatible with `patch -p1` and `git apply`.
+
+use std::path::Path;
+
+const CONTEXT_LINES: usize = 3;
+
+/// The path of a file in the headers of a patch applied from `base`, relative to it if the file is
+/// below it
+pub fn patch_path(path: &Path, base: &Path) -> String {
+    path.strip_prefix(base)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Edit {
+    Equal,
+    Delete,
+    Insert,
+}
+
+///
+/// # Computes a unified diff
+///
+/// Returns `None` if both texts are identical. The file headers use the `a/` and `b/` prefixes
+/// git uses, so `path` should be relative to the directory the patch will be applied from, see
+/// [patch_path]. Absolute paths are written without them.
+///
+pub fn unified_diff(original: &str, formatted: &str, path: &str) -> Option<String> {
+    if original == formatted {
+        return None;
+    }
+
+    let old: Vec<&str> = original.split_inclusive('\n').collect();
+    let new: Vec<&str> = formatted.split_inclusive('\n').collect();
+    let edits = diff_lines(&old, &new);
+
+    let mut patch = if Path::new(path).is_absolute() {
+        format!("--- {}\n+++ {}\n", path, path)
+    } else {
+        format!("--- a/{}\n+++ b/{}\n", path, path)
+    };
+    for (start, end) in hunks(&edits) {
+        // line positions at the start of the hunk
+        let old_pos = edits[..start]
+            .iter()
+            .filter(|e| **e != Edit::Insert)
+            .count();
+        let new_pos = edits[..start]
+            .iter()
+            .filter(|e| **e != Edit::Delete)
+            .count();
+        let old_len = edits[start..end]
+            .iter()
+            .filter(|e| **e != Edit::Insert)
+            .count();
+        let new_len = edits[start..end]
+            .iter()
+            .filter(|e| **e != Edit::Delete)
+            .count();
+
+        patch.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_pos, old_len),
+            hunk_range(new_pos, new_len)
+        ));
+
+        let (mut o, mut n) = (old_pos, new_pos);
+        for edit in &edits[start..end] {
+            let (prefix, line) = match edit {
+                Edit::Equal => {
+                    o += 1;
+                    n += 1;
+                    (' ', old[o - 1])
+                }
+                Edit::Delete => {
+                    o += 1;
+                    ('-', old[o - 1])
+                }
+                Edit::Insert => {
+                    n += 1;
+                    ('+', new[n - 1])
+                }
+            };
+            patch.push(prefix);
+            patch.push_str(line);
+            if !line.ends_with('\n') {
+                patch.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+
+    Some(patch)
+}
+
+fn hunk_range(pos: usize, len: usize) -> String {
+    // an empty range points at the line before it
+    let start = if len == 0 { pos } else { pos + 1 };
+    if len == 1 {
+        start.to_string()
+    } else {
+        format!("{},{}", start, len)
+    }
+}
+
+/// Groups the edits into `(start, end)` index ranges, each surrounded by up to
+/// `CONTEXT_LINES` unchanged lines.
+fn hunks(edits: &[Edit]) -> Vec<(usize, usize)> {
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (i, _) in edits.iter().enumerate().filter(|(_, e)| **e != Edit::Equal) {
+        let start = i.saturating_sub(CONTEXT_LINES);
+        let end = (i + 1 + CONTEXT_LINES).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if last.1 >= start => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    hunks
+}
+
+///
+/// # Pairs the lines both texts have in common
+///
+/// Returns the `(old, new)` indexes of the lines kept by the smallest diff, in order.
+///
+pub fn matching_lines(old: &[&str], new: &[&str]) -> Vec<(usize, usize)> {
+    let (mut o, mut n) = (0, 0);
+    let mut pairs = Vec::new();
+    for edit in diff_lines(old, new) {
+        match edit {
+            Edit::Equal => {
+                pairs.push((o, n));
+                o += 1;
+                n += 1;
+            }
+            Edit::Delete => o += 1,
+            Edit::Insert => n += 1,
+        }
+    }
+    pairs
+}
+
+/// Myers' O(ND) difference algorithm on lines
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    let index = |k: isize| (k + max + 1) as usize;
+
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace = Vec::new();
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[index(prev_k)];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            edits.push(if x == prev_x {
+                Edit::Insert
+            } else {
+                Edit::Delete
+            });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+
+    edits.reverse();
+    edits
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn identical_has_no_diff() {
+        assert_eq!(None, unified_diff("ls\n", "ls\n", "a.nu"));
+    }
+
+    #[test]
+    fn absolute_paths() {
+        let base = Path::new("/repo");
+        assert_eq!("lib/x.nu", patch_path(Path::new("/repo/lib/x.nu"), base));
+        assert_eq!(
+            "/elsewhere/x.nu",
+            patch_path(Path::new("/elsewhere/x.nu"), base)
+        );
+        assert_eq!("x.nu", patch_path(Path::new("x.nu"), base));
+
+        let diff = unified_diff("a\n", "b\n", "/elsewhere/x.nu").unwrap();
+        assert!(diff.starts_with("--- /elsewhere/x.nu\n+++ /elsewhere/x.nu\n"));
+    }
+
+    #[test]
+    fn single_changed_line() {
+        let original = "a\nb\nc\n";
+        let formatted = "a\nB\nc\n";
+        let expected = "--- a/x.nu
++++ b/x.nu
+@@ -1,3 +1,3 @@
+ a
+-b
++B
+ c
+";
+        assert_eq!(
+            Some(expected.to_string()),
+            unified_diff(original, formatted, "x.nu")
+        );
+    }
+
+    #[test]
+    fn missing_newline_at_end_of_file() {
+        let expected = "--- a/x.nu
++++ b/x.nu
+@@ -1 +1 @@
+-[1,2]
+\\ No newline at end of file
++[1, 2]
+";
+        assert_eq!(
+            Some(expected.to_string()),
+            unified_diff("[1,2]", "[1, 2]\n", "x.nu")
+        );
+    }
+
+    #[test]
+    fn pairs_common_lines() {
+        let old = ["a", "b", "c", "d"];
+        let new = ["a", "x", "c", "d", "e"];
+        assert_eq!(vec![(0, 0), (2, 2), (3, 3)], matching_lines(&old, &new));
+    }
+
+    #[test]
+    fn distant_changes_get_separate_hunks() {
+        let original = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let formatted = "one\n2\n3\n4\n5\n6\n7\n8\n9\nten\n";
+        let diff = unified_diff(original, formatted, "x.nu").unwrap();
+        assert!(diff.contains("@@ -1,4 +1,4 @@\n-1\n+one\n 2\n 3\n 4\n"));
+        assert!(diff.contains("@@ -7,4 +7,4 @@\n 7\n 8\n 9\n-10\n+ten\n"));
+    }
+}
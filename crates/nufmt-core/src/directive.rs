@@ -0,0 +1,540 @@
+//!
+//! Option overrides written as comments in the formatted nu itself.
+//!
+//! A directive looks like `# nufmt: indent=4` and may hold several comma or space separated
+//! `key=value` pairs. It applies from the line it is written on until the end of the enclosing
+//! block, so a directive at the top of a file applies to the whole file.
+//!
+//! Besides options, some rules applied everywhere can be turned off with `disable=<rule>`, and
+//! back on with `enable=<rule>`, see [Rule].
+
+use crate::Indentation;
+
+/// Where the pipes or operators continuing a pipeline or an expression on a new line go
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Placement {
+    /// At the start of the continuation lines
+    Leading,
+    /// At the end of the lines being continued
+    Trailing,
+}
+
+/// How closures without parameters are written
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ClosureStyle {
+    /// With empty parameters, `{|| ...}`
+    Explicit,
+    /// Without parameters, `{ ... }`
+    Implicit,
+}
+
+/// The rules which can be disabled, the code they apply to is then kept as written
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[allow(clippy::enum_variant_names)] // named after the rules in directives
+pub enum Rule {
+    /// `colon-spacing`, a space after the colon of keys and annotations, `{a: 1}` or `x: int`
+    ColonSpacing,
+    /// `comment-spacing`, the padding before comments at the end of a line
+    CommentSpacing,
+    /// `operator-spacing`, a single space around the operators of expressions, `1 + 2`
+    OperatorSpacing,
+}
+
+impl Rule {
+    /// Every rule with its name in directives
+    pub const NAMES: &'static [(&'static str, Rule)] = &[
+        ("colon-spacing", Rule::ColonSpacing),
+        ("comment-spacing", Rule::CommentSpacing),
+        ("operator-spacing", Rule::OperatorSpacing),
+    ];
+
+    /// The rule of a name, `None` if unknown
+    pub fn parse(name: &str) -> Option<Rule> {
+        Rule::NAMES
+            .iter()
+            .find(|(rule_name, _)| *rule_name == name)
+            .map(|(_, rule)| *rule)
+    }
+}
+
+/// The options a directive can override
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+pub struct Options {
+    /// Replaces the indentation given to the formatter
+    pub indent: Option<Indentation<'static>>,
+    /// Normalize the spacing of nu code embedded in strings, see [hints](crate::hints)
+    pub embedded_code: bool,
+    /// Normalize the spacing of the subexpressions of interpolated strings
+    pub interpolation_spacing: bool,
+    /// Moves the pipes of multiline pipelines, they are kept where they are written if `None`
+    pub pipe_placement: Option<Placement>,
+    /// Writes every pipeline a stage per line, not only those wider than `max_width`
+    pub break_pipelines: bool,
+    /// Joins the lines of pipelines split at their pipes if `Some(false)`, leaving the breaks to
+    /// `max_width` and `break_pipelines`, they are kept if `None`
+    pub preserve_line_breaks: Option<bool>,
+    /// Moves the operators of multiline expressions in parentheses, kept as written if `None`
+    pub operator_placement: Option<Placement>,
+    /// Separates the top level items, e.g. defs, from each other and from statements by a blank
+    /// line, statements between them stay together
+    pub item_spacing: bool,
+    /// The blank lines between consecutive top level `def`, `export def` and `alias` items,
+    /// inserted or removed to get exactly that many, kept as written if `None`
+    pub definition_blank_lines: Option<usize>,
+    /// Lays out the calls of some known commands spanning several lines in parentheses with their
+    /// positional arguments on the first line and a flag per line, see [hints](crate::hints)
+    pub flag_grouping: bool,
+    /// Converts closures without parameters where both styles mean the same, kept if `None`
+    pub closure_style: Option<ClosureStyle>,
+    /// Writes lists of short strings and numbers with several items per line
+    pub list_fill: bool,
+    /// The most items on a line of a list written with `list_fill`, as many as fit if `None`
+    pub fill_max_items: Option<usize>,
+    /// The width the items of a list written with `list_fill` are padded to along with their
+    /// comma, lining them up in columns, not padded if `None`
+    pub fill_min_item_width: Option<usize>,
+    /// The spaces between code and a comment at the end of its line, one if `None`
+    pub comment_min_spaces_before: Option<usize>,
+    /// Moves words and strings which do not fit on their line to a line of their own
+    pub wrap_long_tokens: bool,
+    /// Pads the cells of table literals, `[[a b]; [1 2]]`, written with a row per line, to align
+    /// their columns, unless the rows get too wide
+    pub table_alignment: bool,
+    /// Keeps the padding lining up tokens of consecutive lines, e.g. the values of a record, instead
+    /// of collapsing it to a single space
+    pub preserve_alignment: bool,
+    /// Aligns the comments describing the parameters of signatures written a parameter per line
+    pub signature_comment_alignment: bool,
+    /// Pads the inside of lists written on one line, `[ 1 2 ]` instead of `[1 2]`
+    pub bracket_spacing: bool,
+    /// Pads the inside of records and closure bodies written on one line, `{ a: 1 }` and
+    /// `{|x| $x }`, blocks and closures kept on the line of their key are always padded
+    pub brace_spacing: bool,
+    /// Writes every list and record on several lines, instead of keeping those written on one line
+    /// on it when they fit within `max_width`
+    pub expand_collections: bool,
+    /// Writes a comma after the last item of the lists and records written an item per line, if
+    /// their items are separated by commas
+    pub trailing_commas: bool,
+    /// The blank lines kept where they are written between statements, comments and entries,
+    /// runs of more are shortened to it, 1 if `None`. `0` removes them all
+    pub max_blank_lines: Option<usize>,
+    /// The width lines are kept within where the layout depends on it, e.g. whether a list or
+    /// record stays on one line or a pipeline is written a stage per line, 100 if `None`
+    pub max_width: Option<usize>,
+    /// The rules turned off, `disable=none` turns them all back on
+    pub disabled: Vec<Rule>,
+}
+
+/// Every option with the values worth testing, the first one being the default
+pub const OPTION_VALUES: &[(&str, &[&str])] = &[
+    ("indent", &["2", "4", "tab"]),
+    ("embedded_code", &["false", "true"]),
+    ("interpolation_spacing", &["false", "true"]),
+    ("pipe_placement", &["keep", "leading", "trailing"]),
+    ("break_pipelines", &["false", "true"]),
+    ("preserve_line_breaks", &["true", "false"]),
+    ("operator_placement", &["keep", "leading", "trailing"]),
+    ("item_spacing", &["false", "true"]),
+    ("definition_blank_lines", &["keep", "1", "0", "2"]),
+    ("flag_grouping", &["false", "true"]),
+    ("closure_style", &["keep", "explicit", "implicit"]),
+    ("list_fill", &["false", "true"]),
+    ("fill_max_items", &["none", "4"]),
+    ("fill_min_item_width", &["0", "8"]),
+    ("comment_min_spaces_before", &["1", "2"]),
+    ("wrap_long_tokens", &["false", "true"]),
+    ("table_alignment", &["false", "true"]),
+    ("preserve_alignment", &["false", "true"]),
+    ("signature_comment_alignment", &["false", "true"]),
+    ("bracket_spacing", &["false", "true"]),
+    ("brace_spacing", &["false", "true"]),
+    ("expand_collections", &["false", "true"]),
+    ("trailing_commas", &["false", "true"]),
+    ("max_blank_lines", &["1", "0", "2"]),
+    ("max_width", &["100", "40"]),
+    ("disable", &["none", "colon-spacing", "comment-spacing"]),
+    ("preset", &["none", "diff-friendly"]),
+];
+
+///
+/// # Sets of options applied together with `preset=<name>`
+///
+/// `diff-friendly` keeps git diffs small, for data and config files: every collection of several
+/// items is written an item per line with trailing commas, and every pipeline a stage per line.
+/// Options written after the preset still override it.
+///
+pub const PRESETS: &[(&str, &[(&str, &str)])] = &[(
+    "diff-friendly",
+    &[
+        ("expand_collections", "true"),
+        ("trailing_commas", "true"),
+        ("break_pipelines", "true"),
+    ],
+)];
+
+impl Options {
+    ///
+    /// # Applies a `key=value` pair
+    ///
+    /// Unknown keys and invalid values are ignored, so directives written for newer versions of
+    /// nufmt do no harm. Returns whether the pair was applied.
+    ///
+    pub fn apply(&mut self, key: &str, value: &str) -> bool {
+        match key {
+            "indent" => {
+                if let Some(indent) = parse_indent(value) {
+                    self.indent = Some(indent);
+                    return true;
+                }
+            }
+            "embedded_code" => {
+                if let Ok(enabled) = value.parse() {
+                    self.embedded_code = enabled;
+                    return true;
+                }
+            }
+            "interpolation_spacing" => {
+                if let Ok(enabled) = value.parse() {
+                    self.interpolation_spacing = enabled;
+                    return true;
+                }
+            }
+            "pipe_placement" => {
+                if let Some(placement) = parse_placement(value) {
+                    self.pipe_placement = placement;
+                    return true;
+                }
+            }
+            "operator_placement" => {
+                if let Some(placement) = parse_placement(value) {
+                    self.operator_placement = placement;
+                    return true;
+                }
+            }
+            "item_spacing" => {
+                if let Ok(enabled) = value.parse() {
+                    self.item_spacing = enabled;
+                    return true;
+                }
+            }
+            "definition_blank_lines" => {
+                let lines = match value {
+                    "keep" => Some(None),
+                    lines => lines.parse().ok().map(Some),
+                };
+                if let Some(lines) = lines {
+                    self.definition_blank_lines = lines;
+                    return true;
+                }
+            }
+            "flag_grouping" => {
+                if let Ok(enabled) = value.parse() {
+                    self.flag_grouping = enabled;
+                    return true;
+                }
+            }
+            "closure_style" => {
+                let style = match value {
+                    "explicit" => Some(Some(ClosureStyle::Explicit)),
+                    "implicit" => Some(Some(ClosureStyle::Implicit)),
+                    "keep" => Some(None),
+                    _ => None,
+                };
+                if let Some(style) = style {
+                    self.closure_style = style;
+                    return true;
+                }
+            }
+            "list_fill" => {
+                if let Ok(enabled) = value.parse() {
+                    self.list_fill = enabled;
+                    return true;
+                }
+            }
+            "fill_max_items" => {
+                let items = match value {
+                    "none" => Some(None),
+                    items => items.parse().ok().filter(|items| *items > 0).map(Some),
+                };
+                if let Some(items) = items {
+                    self.fill_max_items = items;
+                    return true;
+                }
+            }
+            "fill_min_item_width" => {
+                if let Ok(width) = value.parse() {
+                    self.fill_min_item_width = Some(width);
+                    return true;
+                }
+            }
+            "preserve_alignment" => {
+                if let Ok(enabled) = value.parse() {
+                    self.preserve_alignment = enabled;
+                    return true;
+                }
+            }
+            "signature_comment_alignment" => {
+                if let Ok(enabled) = value.parse() {
+                    self.signature_comment_alignment = enabled;
+                    return true;
+                }
+            }
+            "bracket_spacing" => {
+                if let Ok(enabled) = value.parse() {
+                    self.bracket_spacing = enabled;
+                    return true;
+                }
+            }
+            "brace_spacing" => {
+                if let Ok(enabled) = value.parse() {
+                    self.brace_spacing = enabled;
+                    return true;
+                }
+            }
+            "expand_collections" => {
+                if let Ok(enabled) = value.parse() {
+                    self.expand_collections = enabled;
+                    return true;
+                }
+            }
+            "trailing_commas" => {
+                if let Ok(enabled) = value.parse() {
+                    self.trailing_commas = enabled;
+                    return true;
+                }
+            }
+            "break_pipelines" => {
+                if let Ok(enabled) = value.parse() {
+                    self.break_pipelines = enabled;
+                    return true;
+                }
+            }
+            "preserve_line_breaks" => {
+                if let Ok(preserved) = value.parse() {
+                    self.preserve_line_breaks = Some(preserved);
+                    return true;
+                }
+            }
+            "preset" if value == "none" => return true,
+            "preset" => {
+                if let Some((_, options)) = PRESETS.iter().find(|(name, _)| *name == value) {
+                    for (key, value) in options.iter() {
+                        self.apply(key, value);
+                    }
+                    return true;
+                }
+            }
+            "table_alignment" => {
+                if let Ok(enabled) = value.parse() {
+                    self.table_alignment = enabled;
+                    return true;
+                }
+            }
+            "wrap_long_tokens" => {
+                if let Ok(enabled) = value.parse() {
+                    self.wrap_long_tokens = enabled;
+                    return true;
+                }
+            }
+            "max_width" => {
+                if let Some(width) = value.parse().ok().filter(|width| *width > 0) {
+                    self.max_width = Some(width);
+                    return true;
+                }
+            }
+            "max_blank_lines" => {
+                if let Ok(lines) = value.parse() {
+                    self.max_blank_lines = Some(lines);
+                    return true;
+                }
+            }
+            "comment_min_spaces_before" => {
+                if let Some(spaces) = value.parse().ok().filter(|spaces| *spaces > 0) {
+                    self.comment_min_spaces_before = Some(spaces);
+                    return true;
+                }
+            }
+            "disable" if value == "none" => {
+                self.disabled.clear();
+                return true;
+            }
+            "disable" => {
+                if let Some(rule) = Rule::parse(value) {
+                    if !self.disabled.contains(&rule) {
+                        self.disabled.push(rule);
+                    }
+                    return true;
+                }
+            }
+            "enable" => {
+                if let Some(rule) = Rule::parse(value) {
+                    self.disabled.retain(|disabled| *disabled != rule);
+                    return true;
+                }
+            }
+            _ => {}
+        }
+        false
+    }
+
+    /// Whether a rule is applied
+    pub fn enabled(&self, rule: Rule) -> bool {
+        !self.disabled.contains(&rule)
+    }
+}
+
+///
+/// # Parses a comment as a directive
+///
+/// `comment` is the comment text following the `#`. Returns `None` if it is not a directive.
+///
+pub fn parse_directive(comment: &str) -> Option<Vec<(&str, &str)>> {
+    let options = comment.trim().strip_prefix("nufmt:")?;
+    Some(
+        options
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter_map(|option| option.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .collect(),
+    )
+}
+
+/// Accepts `leading`, `trailing` or `keep`
+fn parse_placement(value: &str) -> Option<Option<Placement>> {
+    match value {
+        "leading" => Some(Some(Placement::Leading)),
+        "trailing" => Some(Some(Placement::Trailing)),
+        "keep" => Some(None),
+        _ => None,
+    }
+}
+
+/// Accepts a number of spaces or `tab`
+pub(crate) fn parse_indent(value: &str) -> Option<Indentation<'static>> {
+    match value {
+        "tab" | "tabs" => Some(Indentation::Tabs(1)),
+        _ => value.parse().ok().map(Indentation::Spaces),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn not_a_directive() {
+        assert_eq!(None, parse_directive(" just a comment"));
+    }
+
+    #[test]
+    fn multiple_options() {
+        assert_eq!(
+            Some(vec![("indent", "4"), ("max_width", "120")]),
+            parse_directive(" nufmt: indent=4, max_width=120")
+        );
+    }
+
+    #[test]
+    fn apply_options() {
+        let mut options = Options::default();
+        options.apply("indent", "2");
+        options.apply("embedded_code", "true");
+        options.apply("max_width", "120");
+        options.apply("max_width", "wide");
+        options.apply("embedded_code", "maybe");
+        options.apply("pipe_placement", "leading");
+        options.apply("pipe_placement", "middle");
+        options.apply("operator_placement", "trailing");
+        options.apply("operator_placement", "keep");
+        options.apply("item_spacing", "true");
+        options.apply("definition_blank_lines", "2");
+        options.apply("definition_blank_lines", "many");
+        options.apply("closure_style", "implicit");
+        options.apply("closure_style", "none");
+        options.apply("fill_max_items", "4");
+        options.apply("fill_max_items", "0");
+        options.apply("fill_min_item_width", "8");
+        options.apply("comment_min_spaces_before", "2");
+        options.apply("comment_min_spaces_before", "0");
+        options.apply("max_blank_lines", "0");
+        options.apply("max_blank_lines", "-1");
+        options.apply("wrap_long_tokens", "yes");
+        options.apply("table_alignment", "true");
+        options.apply("preserve_alignment", "true");
+        options.apply("signature_comment_alignment", "true");
+        options.apply("bracket_spacing", "true");
+        options.apply("brace_spacing", "no");
+        options.apply("expand_collections", "true");
+        options.apply("preset", "diff-friendly");
+        options.apply("preset", "smallest");
+        options.apply("break_pipelines", "false");
+        options.apply("preserve_line_breaks", "false");
+        options.apply("preserve_line_breaks", "keep");
+        options.apply("disable", "colon-spacing");
+        options.apply("disable", "comment-spacing");
+        options.apply("disable", "colon-spacing");
+        options.apply("disable", "operator-spacing");
+        options.apply("enable", "comment-spacing");
+
+        let expected = Options {
+            indent: Some(Indentation::Spaces(2)),
+            embedded_code: true,
+            interpolation_spacing: false,
+            pipe_placement: Some(Placement::Leading),
+            break_pipelines: false,
+            preserve_line_breaks: Some(false),
+            operator_placement: None,
+            item_spacing: true,
+            definition_blank_lines: Some(2),
+            flag_grouping: false,
+            closure_style: Some(ClosureStyle::Implicit),
+            list_fill: false,
+            fill_max_items: Some(4),
+            fill_min_item_width: Some(8),
+            comment_min_spaces_before: Some(2),
+            max_blank_lines: Some(0),
+            wrap_long_tokens: false,
+            table_alignment: true,
+            preserve_alignment: true,
+            signature_comment_alignment: true,
+            bracket_spacing: true,
+            brace_spacing: false,
+            expand_collections: true,
+            trailing_commas: true,
+            max_width: Some(120),
+            disabled: vec![Rule::ColonSpacing, Rule::OperatorSpacing],
+        };
+        assert_eq!(expected, options);
+
+        options.apply("disable", "none");
+        let expected = Options {
+            disabled: Vec::new(),
+            ..expected
+        };
+        assert_eq!(expected, options);
+    }
+
+    #[test]
+    fn indent_values() {
+        assert_eq!(Some(Indentation::Spaces(4)), parse_indent("4"));
+        assert_eq!(Some(Indentation::Tabs(1)), parse_indent("tab"));
+        assert_eq!(None, parse_indent("wide"));
+    }
+
+    #[test]
+    fn option_values_are_valid() {
+        for (key, values) in OPTION_VALUES {
+            let mut options: Vec<Options> = values
+                .iter()
+                .map(|value| {
+                    let mut options = Options::default();
+                    options.apply(key, value);
+                    options
+                })
+                .collect();
+            options.dedup();
+            assert_eq!(values.len(), options.len(), "{}", key);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//!
+//! Inputs holding several independent snippets, such as test fixtures or the examples of docs.
+//!
+//! The snippets, or documents, are separated by lines holding only a separator, `---` by default:
+//!
+//! ```text
+//! ls | length
+//! ---
+//! [1,2]
+//! ```
+//!
+//! Every document is formatted on its own, so the directives of one do not apply to the next, and
+//! the separator lines are written back as they are.
+
+/// The separator of documents by default
+pub const DEFAULT_SEPARATOR: &str = "---";
+
+///
+/// # Formats the documents of an input separated by `separator` lines
+///
+/// `format` formats a single document, e.g. `|nu| format_nu_with_config(nu, &config)`. A line is a
+/// separator if it holds `separator` and nothing else but trailing whitespace.
+///
+pub fn format_documents(
+    nu: &str,
+    separator: &str,
+    mut format: impl FnMut(&str) -> String,
+) -> String {
+    let mut formatted = String::with_capacity(nu.len());
+    let mut document_start = 0;
+    let mut line_start = 0;
+    for line in nu.split_inclusive('\n') {
+        let line_end = line_start + line.len();
+        if line.trim_end() == separator {
+            write_document(&mut formatted, format(&nu[document_start..line_start]));
+            formatted.push_str(line);
+            document_start = line_end;
+        }
+        line_start = line_end;
+    }
+    if document_start < nu.len() {
+        formatted.push_str(&format(&nu[document_start..]));
+    }
+    formatted
+}
+
+/// Writes a document followed by a separator, which starts a line of its own
+fn write_document(formatted: &mut String, document: String) {
+    formatted.push_str(&document);
+    if !document.is_empty() && !document.ends_with('\n') {
+        formatted.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{format_nu, Indentation};
+
+    #[test]
+    fn formats_every_document() {
+        let nu = "# nufmt: indent=4\n[1,\n2]\n---  \n[1,\n2]\n---\n\n---\nls   |  length";
+        let expected = "# nufmt: indent=4\n[\n    1,\n    2\n]\n---  \n[\n  1,\n  2\n]\n---\n\n---\nls | length";
+        let formatted = format_documents(nu, DEFAULT_SEPARATOR, |nu| {
+            format_nu(nu, Indentation::Default)
+        });
+        assert_eq!(expected, formatted);
+
+        let nu = "ls  # ---\n----\n---";
+        let formatted = format_documents(nu, DEFAULT_SEPARATOR, |nu| {
+            format_nu(nu, Indentation::Default)
+        });
+        assert_eq!("ls # ---\n----\n---", formatted);
+    }
+}
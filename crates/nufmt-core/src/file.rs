@@ -0,0 +1,194 @@
+//!
+//! Formatting of nu scripts on disk.
+//!
+//! A script is never written in place: the new content goes to a temporary file next to it, which
+//! then replaces it, so a crash or a full disk leaves either the old or the new script, never a
+//! truncated one.
+
+use crate::tree::nu_files;
+use crate::{check_input_size, format_nu_with_config, Config, Error};
+use std::convert::TryFrom;
+use std::fs;
+use std::io::{self, Write};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+///
+/// # Formats a script file
+///
+/// Returns whether it changed. A file already formatted is not written at all, so its
+/// modification time stays as it was. A file larger than the max input size of the config is
+/// refused without being read.
+///
+pub fn format_file(path: &Path, config: &Config) -> Result<bool, Error> {
+    if let Some(limit) = config.max_input_size() {
+        let size = fs::metadata(path)?.len();
+        if size > limit as u64 {
+            let size = usize::try_from(size).unwrap_or(usize::MAX);
+            return Err(Error::InputTooLarge { size, limit });
+        }
+    }
+    let original = fs::read_to_string(path)?;
+    // the file may have grown since
+    check_input_size(&original, config)?;
+    let formatted = format_nu_with_config(&original, config);
+    if formatted == original {
+        return Ok(false);
+    }
+    write_atomically(path, &formatted)?;
+    Ok(true)
+}
+
+///
+/// # Formats every script below a directory
+///
+/// Returns each script found by [nu_files] with whether it changed, or why it could not be
+/// formatted: one script failing, even by panicking, does not stop the others. Only listing the
+/// directory fails the whole tree.
+///
+pub fn format_tree(
+    root: &Path,
+    config: &Config,
+) -> io::Result<Vec<(PathBuf, Result<bool, Error>)>> {
+    Ok(nu_files(root)?
+        .into_iter()
+        .map(|path| {
+            let result = catch_panic(|| format_file(&path, config)).and_then(|result| result);
+            (path, result)
+        })
+        .collect())
+}
+
+///
+/// # Runs a function, turning a panic into an [Error::Panicked]
+///
+/// Bulk runs format each file through it, so a pathological file is reported with the others
+/// instead of aborting the whole run. The panic hook still prints the panic as usual.
+///
+pub fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, Error> {
+    // nothing is shared between files, so a file cannot be left half updated by another's panic
+    catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => match payload.downcast::<&str>() {
+                Ok(message) => message.to_string(),
+                Err(_) => "unknown panic".to_string(),
+            },
+        };
+        Error::Panicked(message)
+    })
+}
+
+///
+/// # Replaces the content of a file
+///
+/// The content is written and synced to a temporary file in the same directory, which keeps the
+/// permissions of the file, then renamed over it.
+///
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+    let temporary = path.with_file_name(format!(
+        ".{}.{}.nufmt.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let written = (|| {
+        let mut file = fs::File::create(&temporary)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temporary, metadata.permissions())?;
+        }
+        fs::rename(&temporary, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    written
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats_files() {
+        let dir = std::env::temp_dir().join(format!("nufmt-file-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.nu");
+        fs::write(&path, "ls   -a\n").unwrap();
+
+        let config = Config::default();
+        let changed = format_file(&path, &config).unwrap();
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let unchanged = format_file(&path, &config).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        let entries = fs::read_dir(&dir).unwrap().count();
+        let still_modified = fs::metadata(&path).unwrap().modified().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(changed);
+        assert!(!unchanged);
+        assert_eq!("ls -a\n", content);
+        assert_eq!(modified, still_modified);
+        // no temporary file is left behind
+        assert_eq!(1, entries);
+    }
+
+    #[test]
+    fn refuses_large_files() {
+        let dir = std::env::temp_dir().join(format!("nufmt-file-limit-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.nu");
+        fs::write(&path, "ls   -a\n").unwrap();
+
+        let config = Config::builder().max_input_size(4).build();
+        let result = format_file(&path, &config);
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(matches!(
+            result,
+            Err(Error::InputTooLarge { size: 8, limit: 4 })
+        ));
+        assert_eq!("ls   -a\n", content);
+    }
+
+    #[test]
+    fn catches_panics() {
+        assert!(matches!(catch_panic(|| 1), Ok(1)));
+        let message = |err| match err {
+            Error::Panicked(message) => message,
+            _ => unreachable!(),
+        };
+        let err = catch_panic(|| panic!("at the disco")).unwrap_err();
+        assert_eq!("at the disco", message(err));
+        let err = catch_panic(|| panic!("{} {}", "formatted", 2)).unwrap_err();
+        assert_eq!("formatted 2", message(err));
+    }
+
+    #[test]
+    fn formats_trees() {
+        let root = std::env::temp_dir().join(format!("nufmt-format-tree-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("nested")).unwrap();
+        fs::write(root.join("a.nu"), "ls\n").unwrap();
+        fs::write(root.join("nested").join("b.nu"), "ls   -a\n").unwrap();
+        fs::write(root.join("c.nu"), [0xff, 0xfe]).unwrap();
+
+        let results = format_tree(&root, &Config::default()).unwrap();
+        let content = fs::read_to_string(root.join("nested").join("b.nu")).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        let names: Vec<_> = results
+            .iter()
+            .map(|(path, _)| path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(vec!["a.nu", "c.nu", "b.nu"], names);
+        assert!(matches!(results[0].1, Ok(false)));
+        assert!(results[1].1.is_err());
+        assert!(matches!(results[2].1, Ok(true)));
+        assert_eq!("ls -a\n", content);
+    }
+}
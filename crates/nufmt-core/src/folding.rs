@@ -0,0 +1,128 @@
+//!
+//! Folding ranges, the parts of a script an editor may collapse.
+//!
+//! They come from the same tokens the formatter works on, so editors do not need a parser of their
+//! own to offer folding.
+
+use crate::lexer::{at_line_start, tokenize, TokenKind};
+use crate::lint::LineIndex;
+
+/// What a folding range holds
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "daemon", derive(serde::Serialize))]
+#[cfg_attr(feature = "daemon", serde(rename_all = "lowercase"))]
+pub enum FoldingKind {
+    /// A block, record or subexpression, `{...}` or `(...)`
+    Block,
+    /// A list or table, `[...]`
+    Collection,
+    /// Consecutive lines holding only comments
+    Comment,
+}
+
+/// A range of lines which can be collapsed
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[cfg_attr(feature = "daemon", derive(serde::Serialize))]
+pub struct FoldingRange {
+    /// 1-based line of the opening delimiter or first comment
+    pub start_line: usize,
+    /// 1-based line of the closing delimiter or last comment
+    pub end_line: usize,
+    pub kind: FoldingKind,
+}
+
+///
+/// # Finds the folding ranges of a script
+///
+/// Delimiters closed on a later line than they are opened on and runs of at least two comment
+/// lines fold. Ranges are sorted by start line, outer ones before the ranges they hold.
+///
+pub fn folding_ranges(nu: &str) -> Vec<FoldingRange> {
+    let tokens = tokenize(nu);
+    let lines = LineIndex::new(nu);
+    let line = |offset| lines.line_column(offset).0;
+
+    let mut ranges = Vec::new();
+    let mut open = Vec::new();
+    let mut comments: Option<FoldingRange> = None;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::OpenBrace | TokenKind::OpenParen | TokenKind::OpenBracket => {
+                open.push((token.kind, line(token.span.start)));
+            }
+            TokenKind::CloseBrace | TokenKind::CloseParen | TokenKind::CloseBracket => {
+                if let Some((kind, start_line)) = open.pop() {
+                    let end_line = line(token.span.start);
+                    if end_line > start_line {
+                        ranges.push(FoldingRange {
+                            start_line,
+                            end_line,
+                            kind: if kind == TokenKind::OpenBracket {
+                                FoldingKind::Collection
+                            } else {
+                                FoldingKind::Block
+                            },
+                        });
+                    }
+                }
+            }
+            TokenKind::Comment if at_line_start(&tokens[..i]) => {
+                let comment_line = line(token.span.start);
+                match &mut comments {
+                    Some(run) if run.end_line + 1 == comment_line => run.end_line = comment_line,
+                    run => {
+                        ranges.extend(run.take().filter(|run| run.end_line > run.start_line));
+                        *run = Some(FoldingRange {
+                            start_line: comment_line,
+                            end_line: comment_line,
+                            kind: FoldingKind::Comment,
+                        });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    ranges.extend(comments.filter(|run| run.end_line > run.start_line));
+
+    ranges.sort_by_key(|range| (range.start_line, std::cmp::Reverse(range.end_line)));
+    ranges
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn range(start_line: usize, end_line: usize, kind: FoldingKind) -> FoldingRange {
+        FoldingRange {
+            start_line,
+            end_line,
+            kind,
+        }
+    }
+
+    #[test]
+    fn blocks_and_collections() {
+        let nu = "def f [] {\n  let a = [\n    1\n  ]\n  (ls\n  | length)\n}\nlet b = {a: 1}";
+        assert_eq!(
+            vec![
+                range(1, 7, FoldingKind::Block),
+                range(2, 4, FoldingKind::Collection),
+                range(5, 6, FoldingKind::Block),
+            ],
+            folding_ranges(nu)
+        );
+    }
+
+    #[test]
+    fn comment_runs() {
+        let nu = "# a\n# b\nls # c\n# d\n\n# e\n  # f\nps";
+        assert_eq!(
+            vec![
+                range(1, 2, FoldingKind::Comment),
+                range(6, 7, FoldingKind::Comment),
+            ],
+            folding_ranges(nu)
+        );
+    }
+}
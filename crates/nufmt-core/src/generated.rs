@@ -0,0 +1,44 @@
+//!
+//! Detection of generated nu scripts, which are skipped by default like other formatters do.
+
+/// The marker looked for when none is configured
+pub const DEFAULT_MARKER: &str = "@generated";
+
+/// How many lines at the start of a script are searched for the marker
+pub const DEFAULT_MARKER_LINES: usize = 5;
+
+///
+/// # Checks whether a script is generated
+///
+/// A script is generated if one of its first `lines` lines is a comment containing `marker`,
+/// e.g. `# @generated by nu-bindgen`.
+///
+pub fn is_generated(nu: &str, marker: &str, lines: usize) -> bool {
+    nu.lines()
+        .take(lines)
+        .map(str::trim_start)
+        .any(|line| line.starts_with('#') && line.contains(marker))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn marker_in_header_comment() {
+        let nu = "#!/usr/bin/env nu\n# @generated by a tool\nls";
+        assert!(is_generated(nu, DEFAULT_MARKER, DEFAULT_MARKER_LINES));
+    }
+
+    #[test]
+    fn marker_outside_comment() {
+        let nu = "echo '@generated'";
+        assert!(!is_generated(nu, DEFAULT_MARKER, DEFAULT_MARKER_LINES));
+    }
+
+    #[test]
+    fn marker_too_far_down() {
+        let nu = "ls\nls\n# @generated";
+        assert!(!is_generated(nu, DEFAULT_MARKER, 2));
+    }
+}
@@ -0,0 +1,240 @@
+//!
+//! File headers, e.g. a license notice kept at the top of every script.
+//!
+//! A header template is plain text where `{year}` and `{filename}` are replaced. Lines which are
+//! not comments yet are turned into comments, so a license file can be used as it is.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The values of the variables of a header template
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Variables<'a> {
+    pub year: i64,
+    pub filename: &'a str,
+}
+
+const VARIABLES: &[&str] = &["{year}", "{filename}"];
+
+/// Turns a template line into a comment line
+fn comment(line: &str) -> String {
+    let line = line.trim_end();
+    if line.is_empty() {
+        "#".to_string()
+    } else if line.starts_with('#') {
+        line.to_string()
+    } else {
+        format!("# {}", line)
+    }
+}
+
+/// The comment lines of a template with its variables replaced
+pub fn render(template: &str, variables: &Variables) -> Vec<String> {
+    template
+        .lines()
+        .map(|line| {
+            comment(
+                &line
+                    .replace("{year}", &variables.year.to_string())
+                    .replace("{filename}", variables.filename),
+            )
+        })
+        .collect()
+}
+
+/// Splits a template line at its variables
+fn literals(line: &str) -> Vec<&str> {
+    let mut literals = vec![line];
+    for variable in VARIABLES {
+        literals = literals
+            .into_iter()
+            .flat_map(|literal| literal.split(variable))
+            .collect();
+    }
+    literals
+}
+
+/// Whether a line is a template line with any values for its variables
+fn matches(template_line: &str, line: &str) -> bool {
+    let literals = literals(template_line);
+    if literals.len() == 1 {
+        return template_line == line;
+    }
+
+    let (first, last) = (literals[0], literals[literals.len() - 1]);
+    if !line.starts_with(first) || !line[first.len()..].ends_with(last) {
+        return false;
+    }
+    let mut rest = &line[first.len()..line.len() - last.len()];
+    for literal in &literals[1..literals.len() - 1] {
+        match rest.find(literal) {
+            Some(index) => rest = &rest[index + literal.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+///
+/// # Inserts or updates the header of a script
+///
+/// The header goes at the very top, after the shebang if there is one. If the script already
+/// starts with the header, whatever the values of its variables, e.g. an older year, it is
+/// replaced by the rendered one.
+///
+pub fn apply_header(nu: &str, template: &str, variables: &Variables) -> String {
+    let (shebang, body) = match nu.strip_prefix("#!") {
+        Some(_) => match nu.find('\n') {
+            Some(end) => nu.split_at(end + 1),
+            None => (nu, ""),
+        },
+        None => ("", nu),
+    };
+
+    let template_lines: Vec<String> = template.lines().map(comment).collect();
+    let body_lines: Vec<&str> = body.lines().collect();
+    let existing = template_lines.len() <= body_lines.len()
+        && template_lines
+            .iter()
+            .zip(&body_lines)
+            .all(|(template_line, line)| matches(template_line, line.trim_end()));
+    let rest = if existing {
+        body.split_inclusive('\n')
+            .skip(template_lines.len())
+            .collect::<String>()
+    } else {
+        body.to_string()
+    };
+
+    let mut output = shebang.to_string();
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+    for line in render(template, variables) {
+        output.push_str(&line);
+        output.push('\n');
+    }
+    output.push_str(&rest);
+    output
+}
+
+/// The year, month and day of a number of days since the Unix epoch, in the proleptic Gregorian
+/// calendar
+pub(crate) fn date_of_day(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // the computation starts the year in March
+    let march_based_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * march_based_month + 2) / 5 + 1;
+    let year = year_of_era + era * 400;
+    if march_based_month >= 10 {
+        (year + 1, march_based_month - 9, day)
+    } else {
+        (year, march_based_month + 3, day)
+    }
+}
+
+/// The year of a number of days since the Unix epoch
+fn year_of_day(days: i64) -> i64 {
+    date_of_day(days).0
+}
+
+/// The current year in UTC
+pub fn current_year() -> i64 {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0);
+    year_of_day(seconds.div_euclid(86_400))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEMPLATE: &str = "Copyright (c) {year} nu contributors\n\n{filename} is MIT licensed";
+
+    fn variables(year: i64) -> Variables<'static> {
+        Variables {
+            year,
+            filename: "build.nu",
+        }
+    }
+
+    #[test]
+    fn renders_comments() {
+        assert_eq!(
+            vec![
+                "# Copyright (c) 2024 nu contributors",
+                "#",
+                "# build.nu is MIT licensed"
+            ],
+            render(TEMPLATE, &variables(2024))
+        );
+        assert_eq!(vec!["## {a}"], render("## {a}", &variables(2024)));
+    }
+
+    #[test]
+    fn inserts_header() {
+        assert_eq!(
+            "# Copyright (c) 2024 nu contributors\n#\n# build.nu is MIT licensed\nls\n",
+            apply_header("ls\n", TEMPLATE, &variables(2024))
+        );
+    }
+
+    #[test]
+    fn keeps_shebang_first() {
+        assert_eq!(
+            "#!/usr/bin/env nu\n# (c) 2024\nls\n",
+            apply_header("#!/usr/bin/env nu\nls\n", "(c) {year}", &variables(2024))
+        );
+        assert_eq!(
+            "#!/usr/bin/env nu\n# (c) 2024\n",
+            apply_header("#!/usr/bin/env nu", "(c) {year}", &variables(2024))
+        );
+    }
+
+    #[test]
+    fn updates_header() {
+        let old = "#!/usr/bin/env nu\n# Copyright (c) 2019-2021 nu contributors\n#\n# old.nu is MIT licensed\nls\n";
+        assert_eq!(
+            "#!/usr/bin/env nu\n# Copyright (c) 2024 nu contributors\n#\n# build.nu is MIT licensed\nls\n",
+            apply_header(old, TEMPLATE, &variables(2024))
+        );
+    }
+
+    #[test]
+    fn header_is_stable() {
+        let once = apply_header("# a comment\nls\n", TEMPLATE, &variables(2024));
+        assert_eq!(once, apply_header(&once, TEMPLATE, &variables(2024)));
+        assert!(once.ends_with("# build.nu is MIT licensed\n# a comment\nls\n"));
+    }
+
+    #[test]
+    fn matches_variables() {
+        assert!(matches("# (c) {year} {filename}", "# (c) 2024 a.nu"));
+        assert!(matches("# (c) {year}", "# (c) 2020-2024"));
+        assert!(!matches("# (c) {year}", "# (C) 2024"));
+        assert!(!matches("# license", "# license!"));
+    }
+
+    #[test]
+    fn years() {
+        assert_eq!(1970, year_of_day(0));
+        assert_eq!(2023, year_of_day(19722));
+        assert_eq!(2024, year_of_day(19723));
+        assert_eq!(2024, year_of_day(20088));
+        assert_eq!(1969, year_of_day(-1));
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!((1970, 1, 1), date_of_day(0));
+        assert_eq!((2024, 2, 29), date_of_day(19782));
+        assert_eq!((2024, 12, 31), date_of_day(20088));
+        assert_eq!((1969, 12, 31), date_of_day(-1));
+    }
+}
@@ -0,0 +1,246 @@
+//!
+//! Layout hints for well known commands.
+//!
+//! Some formatting rules depend on what a command does with its arguments, which cannot be seen
+//! from the syntax alone. This table tells the formatter about such commands.
+
+/// What the formatter knows about a command
+#[derive(Debug)]
+pub struct LayoutHint {
+    /// The name of the command, without the `^` of external calls
+    pub command: &'static str,
+    /// Flags whose string argument is nu code, e.g. `nu -c "ls | length"`
+    pub embedded_code_flags: &'static [&'static str],
+    /// Multiline calls are laid out with a flag per line, see the `flag_grouping` directive option
+    pub group_flags: bool,
+    /// The first argument is a closure, so `{ ... }` and `{|| ...}` mean the same there
+    pub closure_argument: bool,
+}
+
+/// The layout hints of all known commands
+pub const LAYOUT_HINTS: &[LayoutHint] = &[
+    LayoutHint {
+        command: "nu",
+        embedded_code_flags: &["-c", "--commands"],
+        group_flags: false,
+        closure_argument: false,
+    },
+    LayoutHint {
+        command: "cargo",
+        embedded_code_flags: &[],
+        group_flags: true,
+        closure_argument: false,
+    },
+    LayoutHint {
+        command: "git",
+        embedded_code_flags: &[],
+        group_flags: true,
+        closure_argument: false,
+    },
+    LayoutHint {
+        command: "docker",
+        embedded_code_flags: &[],
+        group_flags: true,
+        closure_argument: false,
+    },
+    LayoutHint {
+        command: "kubectl",
+        embedded_code_flags: &[],
+        group_flags: true,
+        closure_argument: false,
+    },
+    LayoutHint {
+        command: "do",
+        embedded_code_flags: &[],
+        group_flags: false,
+        closure_argument: true,
+    },
+    LayoutHint {
+        command: "each",
+        embedded_code_flags: &[],
+        group_flags: false,
+        closure_argument: true,
+    },
+    LayoutHint {
+        command: "par-each",
+        embedded_code_flags: &[],
+        group_flags: false,
+        closure_argument: true,
+    },
+    LayoutHint {
+        command: "each-while",
+        embedded_code_flags: &[],
+        group_flags: false,
+        closure_argument: true,
+    },
+    LayoutHint {
+        command: "filter",
+        embedded_code_flags: &[],
+        group_flags: false,
+        closure_argument: true,
+    },
+    LayoutHint {
+        command: "tee",
+        embedded_code_flags: &[],
+        group_flags: false,
+        closure_argument: true,
+    },
+];
+
+/// The boolean and arithmetic operators continuation lines of an expression may start or end with
+pub const CONTINUATION_OPERATORS: &[&str] = &[
+    "and", "or", "xor", "+", "-", "*", "/", "//", "mod", "**", "++", "bit-and", "bit-or",
+    "bit-xor", "bit-shl", "bit-shr",
+];
+
+/// The comparison operators of expressions, kept on the line of their operands
+pub const COMPARISON_OPERATORS: &[&str] = &[
+    "==",
+    "!=",
+    "<",
+    "<=",
+    ">",
+    ">=",
+    "=~",
+    "!~",
+    "in",
+    "not-in",
+    "has",
+    "not-has",
+    "like",
+    "not-like",
+    "starts-with",
+    "ends-with",
+];
+
+/// Keywords evaluated at parse time, their statements are kept as written
+pub const PARSE_TIME_KEYWORDS: &[&str] = &["source", "source-env", "use", "hide"];
+
+/// Keywords starting the items of a module, as opposed to plain statements
+pub const ITEM_KEYWORDS: &[&str] = &["def", "export", "export-env", "extern", "module", "use"];
+
+/// Keywords taking blocks, the braces of their statements never open records, `if $x { a: 1 }`
+pub const BLOCK_KEYWORDS: &[&str] = &[
+    "if",
+    "else",
+    "try",
+    "catch",
+    "do",
+    "loop",
+    "while",
+    "for",
+    "def",
+    "module",
+    "export-env",
+    "match",
+];
+
+/// Keywords defining a command or an alias, also after `export`
+pub const DEFINITION_KEYWORDS: &[&str] = &["def", "alias"];
+
+/// Whether a statement, starting with its first line, defines a command or an alias
+pub fn is_definition(statement: &str) -> bool {
+    let mut words = statement.split_whitespace();
+    match words.next() {
+        Some("export") => words
+            .next()
+            .is_some_and(|word| DEFINITION_KEYWORDS.contains(&word)),
+        word => word.is_some_and(|word| DEFINITION_KEYWORDS.contains(&word)),
+    }
+}
+
+///
+/// # Looks up the layout hint of a command
+///
+/// External calls like `^nu` find the hint of the command they call.
+///
+pub fn layout_hint(command: &str) -> Option<&'static LayoutHint> {
+    let command = command.trim_start_matches('^');
+    LAYOUT_HINTS.iter().find(|hint| hint.command == command)
+}
+
+///
+/// # Normalizes the spacing of nu code embedded in a double quoted string
+///
+/// `code` is the raw string content, escapes included. Runs of whitespace are collapsed to a
+/// single space and trimmed at both ends, except inside the quotes of the embedded code, which are
+/// kept as they are. Returns `None` for code spanning several lines, which is left alone.
+///
+pub fn normalize_embedded_code(code: &str) -> Option<String> {
+    if code.contains('\n') {
+        return None;
+    }
+
+    let mut normalized = String::with_capacity(code.len());
+    let mut chars = code.chars();
+    let mut in_single_quotes = false;
+    let mut in_escaped_quotes = false;
+    let mut space_requested = false;
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() && !in_single_quotes && !in_escaped_quotes {
+            space_requested = !normalized.is_empty();
+            continue;
+        }
+        if space_requested {
+            normalized.push(' ');
+            space_requested = false;
+        }
+        normalized.push(c);
+
+        match c {
+            '\\' => {
+                // escapes are kept as they are
+                if let Some(escaped) = chars.next() {
+                    normalized.push(escaped);
+                    if escaped == '"' && !in_single_quotes {
+                        in_escaped_quotes = !in_escaped_quotes;
+                    }
+                }
+            }
+            '\'' if !in_escaped_quotes => in_single_quotes = !in_single_quotes,
+            _ => {}
+        }
+    }
+
+    Some(normalized)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn external_call_hint() {
+        assert!(layout_hint("^nu").is_some());
+        assert!(layout_hint("ls").is_none());
+    }
+
+    #[test]
+    fn collapses_spacing() {
+        assert_eq!(
+            Some("ls | where size > 10kb".to_string()),
+            normalize_embedded_code("  ls   |  where size  >   10kb ")
+        );
+    }
+
+    #[test]
+    fn definitions() {
+        assert!(is_definition("def --env f [] {"));
+        assert!(is_definition("export alias ll = ls -l"));
+        assert!(!is_definition("export const x = 1"));
+        assert!(!is_definition("define"));
+    }
+
+    #[test]
+    fn keeps_quoted_spacing() {
+        assert_eq!(
+            Some(r#"echo 'a  b' | str replace \"  \" ' '"#.to_string()),
+            normalize_embedded_code(r#"echo  'a  b'  |  str replace  \"  \"  ' '"#)
+        );
+    }
+
+    #[test]
+    fn multiline_is_left_alone() {
+        assert_eq!(None, normalize_embedded_code("ls\n  | length"));
+    }
+}
@@ -0,0 +1,299 @@
+//!
+//! Ignore files, in the syntax of `.gitignore`.
+//!
+//! Walking a directory tree skips what the `.gitignore` and `.ignore` files of its directories
+//! ignore, along with the files of the directories above it up to the root of the git repository,
+//! so vendored or generated scripts are left alone. The rules are those of git: `#` starts a
+//! comment, `!` includes again what an earlier rule ignored, a trailing `/` only matches
+//! directories, a pattern with another `/` is relative to the directory of its file and one
+//! without matches at any depth. The last matching rule wins, and the rules of a deeper file win
+//! over the rules above it. A `.ignore` file wins over the `.gitignore` of its directory.
+//!
+//! A `.nufmtignore` file, in the same syntax, lists the scripts a project never wants formatted,
+//! e.g. generated ones. It is looked up like a [config file](crate::config::find_config_file),
+//! the nearest one applies, and it is followed however nufmt is invoked, even on a single file or
+//! with the other ignore files turned off.
+
+use crate::tree::{anchored_glob_match, glob_match};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The ignore files of a directory, from the lowest to the highest precedence
+pub const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+/// The name of the file listing the scripts nufmt never formats
+pub const NUFMTIGNORE_FILE: &str = ".nufmtignore";
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Rule {
+    pattern: String,
+    negated: bool,
+    directory_only: bool,
+    /// Whether the pattern is matched from the directory of its file rather than at any depth
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let line = line.trim_end_matches('\r');
+        // trailing spaces are ignored unless escaped
+        let line = match line.trim_end_matches(' ') {
+            trimmed if trimmed.ends_with('\\') && trimmed.len() < line.len() => {
+                &line[..trimmed.len() + 1]
+            }
+            trimmed => trimmed,
+        };
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        // `\#` and `\!` start patterns with those characters
+        let line = line.strip_prefix('\\').unwrap_or(line);
+        let (directory_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let pattern = line.trim_start_matches('/').replace("\\ ", " ");
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(Rule {
+            pattern,
+            negated,
+            directory_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.directory_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            anchored_glob_match(&self.pattern, path)
+        } else {
+            glob_match(&self.pattern, path)
+        }
+    }
+}
+
+/// The rules of an ignore file
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct IgnoreFile {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreFile {
+    /// Parses the content of an ignore file, lines which are no rule are left out
+    pub fn parse(contents: &str) -> IgnoreFile {
+        IgnoreFile {
+            rules: contents.lines().filter_map(Rule::parse).collect(),
+        }
+    }
+
+    ///
+    /// # Whether the file ignores a path
+    ///
+    /// The path is relative to the directory of the file, with `/` separators. `None` if no rule
+    /// matches it, `Some(false)` if the last matching rule includes it again.
+    ///
+    pub fn matched(&self, path: &str, is_dir: bool) -> Option<bool> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(path, is_dir))
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// The ignore files applying to a directory of a walk, with the absolute paths of their
+/// directories, from the top
+#[derive(Debug, Clone, Default)]
+pub struct Ignores {
+    files: Vec<(PathBuf, IgnoreFile)>,
+}
+
+impl Ignores {
+    /// The ignore files of the directories above `dir`, up to the root of its git repository, and
+    /// none if it is not in one
+    pub fn above(dir: &Path) -> io::Result<Ignores> {
+        let dir = dir.canonicalize()?;
+        let mut ignores = Ignores::default();
+        if dir.join(".git").exists() {
+            return Ok(ignores);
+        }
+        let mut above = Vec::new();
+        for ancestor in dir.ancestors().skip(1) {
+            above.push(ancestor);
+            if ancestor.join(".git").exists() {
+                for ancestor in above.into_iter().rev() {
+                    ignores.load(ancestor)?;
+                }
+                break;
+            }
+        }
+        Ok(ignores)
+    }
+
+    /// Adds the ignore files of a directory given by its absolute path, if it has any
+    pub fn load(&mut self, dir: &Path) -> io::Result<()> {
+        for name in IGNORE_FILES {
+            match fs::read_to_string(dir.join(name)) {
+                Ok(contents) => self
+                    .files
+                    .push((dir.to_path_buf(), IgnoreFile::parse(&contents))),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// How many ignore files apply
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Whether no ignore file applies
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Forgets the ignore files loaded after the first `len`, when a walk leaves their directories
+    pub fn truncate(&mut self, len: usize) {
+        self.files.truncate(len);
+    }
+
+    /// Whether a path, given as an absolute path, is ignored
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.files
+            .iter()
+            .rev()
+            .find_map(|(dir, file)| {
+                let relative = path.strip_prefix(dir).ok()?;
+                let relative = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                file.matched(&relative, is_dir)
+            })
+            .unwrap_or(false)
+    }
+}
+
+/// The `.nufmtignore` file applying to a directory, the nearest one in it or its parents
+pub fn find_nufmtignore(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(NUFMTIGNORE_FILE))
+        .find(|path| path.is_file())
+}
+
+///
+/// # Whether the `.nufmtignore` file applying to a script ignores it
+///
+/// The script is ignored if it matches, or if one of the directories between it and the file
+/// does: as in git, a script of an ignored directory cannot be included again.
+///
+pub fn nufmt_ignored(path: &Path) -> io::Result<bool> {
+    // the script may not exist yet, e.g. a new file an editor formats through stdin
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => std::env::current_dir()?.join(path),
+    };
+    let ignore_file = match path.parent().and_then(find_nufmtignore) {
+        Some(ignore_file) => ignore_file,
+        None => return Ok(false),
+    };
+    let rules = IgnoreFile::parse(&fs::read_to_string(&ignore_file)?);
+    let dir = ignore_file.parent().unwrap_or_else(|| Path::new(""));
+    let components: Vec<_> = path
+        .strip_prefix(dir)
+        .unwrap_or(&path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect();
+    Ok((1..=components.len()).any(|len| {
+        let is_dir = len < components.len();
+        rules.matched(&components[..len].join("/"), is_dir) == Some(true)
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_rules() {
+        let file = IgnoreFile::parse("# comment\n\n*.log\n!keep.log\nbuild/\n/root.nu\n\\#hash\n");
+        assert_eq!(5, file.rules.len());
+        assert_eq!(Some(true), file.matched("logs/a.log", false));
+        assert_eq!(Some(false), file.matched("logs/keep.log", false));
+        assert_eq!(Some(true), file.matched("a/build", true));
+        assert_eq!(None, file.matched("a/build", false));
+        assert_eq!(Some(true), file.matched("root.nu", false));
+        assert_eq!(None, file.matched("a/root.nu", false));
+        assert_eq!(Some(true), file.matched("#hash", false));
+        assert_eq!(None, file.matched("other.nu", false));
+    }
+
+    #[test]
+    fn anchors_patterns_with_slashes() {
+        let file = IgnoreFile::parse("scripts/gen/\ndocs/**/*.nu\n");
+        assert_eq!(Some(true), file.matched("scripts/gen", true));
+        assert_eq!(None, file.matched("a/scripts/gen", true));
+        assert_eq!(Some(true), file.matched("docs/a/b/c.nu", false));
+        assert_eq!(Some(true), file.matched("docs/c.nu", false));
+    }
+
+    #[test]
+    fn deeper_files_win() {
+        let mut ignores = Ignores::default();
+        ignores
+            .files
+            .push((PathBuf::from("/repo"), IgnoreFile::parse("*.nu\n")));
+        ignores
+            .files
+            .push((PathBuf::from("/repo/src"), IgnoreFile::parse("!main.nu\n")));
+        assert!(ignores.is_ignored(Path::new("/repo/a.nu"), false));
+        assert!(ignores.is_ignored(Path::new("/repo/src/lib.nu"), false));
+        assert!(!ignores.is_ignored(Path::new("/repo/src/main.nu"), false));
+        assert!(!ignores.is_ignored(Path::new("/repo/a.txt"), false));
+
+        ignores.truncate(1);
+        assert!(ignores.is_ignored(Path::new("/repo/src/main.nu"), false));
+    }
+
+    #[test]
+    fn follows_nufmtignore() {
+        let root = std::env::temp_dir().join(format!("nufmt-nufmtignore-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("project/generated/nested")).unwrap();
+        fs::write(root.join(NUFMTIGNORE_FILE), "*.nu\n").unwrap();
+        fs::write(
+            root.join("project").join(NUFMTIGNORE_FILE),
+            "generated/\n!generated/nested/kept.nu\n*_gen.nu\n",
+        )
+        .unwrap();
+        let ignored = |file: &str| {
+            let path = root.join(file);
+            fs::write(&path, "ls\n").unwrap();
+            nufmt_ignored(&path).unwrap()
+        };
+
+        // only the nearest file applies
+        assert!(!ignored("project/a.nu"));
+        assert!(ignored("project/a_gen.nu"));
+        assert!(ignored("project/generated/b.nu"));
+        assert!(ignored("project/generated/nested/kept.nu"));
+        assert!(ignored("c.nu"));
+        assert!(nufmt_ignored(&root.join("project/generated/missing.nu")).unwrap());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+//!
+//! String interpolation, `$"total: ($x + 1)"` or `$'total: ($x + 1)'`.
+//!
+//! The subexpressions in parentheses are nu code and may contain quotes of their own, so the end
+//! of the string cannot be found by looking for the next unescaped quote.
+
+/// Finds the end of an interpolated string, fed one byte at a time after the opening quote
+#[derive(Debug, Default)]
+pub struct Interpolation {
+    depth: usize,
+    escaped: bool,
+    inner_quote: Option<u8>,
+    /// `$'...'`, closed by a single quote and without escapes
+    single_quoted: bool,
+}
+
+impl Interpolation {
+    /// The state of a string opened by `quote`, double quoted strings are the default
+    pub fn new(quote: u8) -> Interpolation {
+        Interpolation {
+            single_quoted: quote == b'\'',
+            ..Interpolation::default()
+        }
+    }
+
+    /// The quote opening and closing the string
+    pub fn quote(&self) -> u8 {
+        if self.single_quoted {
+            b'\''
+        } else {
+            b'"'
+        }
+    }
+
+    /// Whether the last byte was inside a subexpression
+    pub fn in_subexpression(&self) -> bool {
+        self.depth > 0
+    }
+
+    /// Whether the last byte was inside a string of a subexpression
+    pub fn in_inner_string(&self) -> bool {
+        self.inner_quote.is_some()
+    }
+
+    ///
+    /// # Feeds the next byte
+    ///
+    /// Returns `true` if it is the closing quote.
+    ///
+    pub fn push(&mut self, byte: u8) -> bool {
+        if self.escaped {
+            self.escaped = false;
+            return false;
+        }
+        if let Some(quote) = self.inner_quote {
+            if byte == b'\\' && quote == b'"' {
+                self.escaped = true;
+            } else if byte == quote {
+                self.inner_quote = None;
+            }
+            return false;
+        }
+
+        match byte {
+            b'\\' if self.depth == 0 && !self.single_quoted => self.escaped = true,
+            b'"' if self.depth == 0 && !self.single_quoted => return true,
+            b'\'' if self.depth == 0 && self.single_quoted => return true,
+            b'(' => self.depth += 1,
+            b')' if self.depth > 0 => self.depth -= 1,
+            b'"' | b'\'' | b'`' if self.depth > 0 => self.inner_quote = Some(byte),
+            _ => {}
+        }
+        false
+    }
+}
+
+///
+/// # Normalizes the spacing inside the subexpressions of an interpolated string
+///
+/// `content` is the text between the `quote`s. Whitespace runs in subexpressions are collapsed to a
+/// single space and removed next to their parentheses, strings inside them are kept as written.
+/// The literal text outside of the parentheses is left untouched byte for byte.
+///
+pub fn normalize_subexpressions(content: &str, quote: u8) -> String {
+    let mut normalized = Vec::with_capacity(content.len());
+    let mut state = Interpolation::new(quote);
+    let mut space_requested = false;
+
+    for &byte in content.as_bytes() {
+        let was_code = state.in_subexpression() && !state.in_inner_string();
+        state.push(byte);
+
+        if was_code && byte.is_ascii_whitespace() {
+            space_requested = normalized.last() != Some(&b'(');
+            continue;
+        }
+        if space_requested && byte != b')' {
+            normalized.push(b' ');
+        }
+        space_requested = false;
+        normalized.push(byte);
+    }
+
+    // only ASCII whitespace was removed, so this is still valid UTF-8
+    String::from_utf8(normalized).unwrap_or_else(|_| content.to_string())
+}
+
+///
+/// # Measures an interpolated string as it is written once formatted
+///
+/// `text` is the string from its opening quote, the `$` excluded, either kind of quote. With `normalize`, the spacing of
+/// its subexpressions is normalized first, see [normalize_subexpressions]. Only the part up to the
+/// first newline counts, the rest is on other lines.
+///
+pub fn rendered_width(text: &str, normalize: bool) -> usize {
+    let quote = text.bytes().next().unwrap_or(b'"');
+    let normalized = match text.len() {
+        len if normalize && len >= 2 && text.ends_with(char::from(quote)) => {
+            let content = normalize_subexpressions(&text[1..len - 1], quote);
+            format!("{}{}{}", char::from(quote), content, char::from(quote))
+        }
+        _ => text.to_string(),
+    };
+    let first_line = normalized.split('\n').next().unwrap_or_default();
+    crate::lexer::display_width(first_line)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn end_of(content: &str) -> Option<usize> {
+        let mut state = Interpolation::default();
+        content.bytes().position(|byte| state.push(byte))
+    }
+
+    #[test]
+    fn finds_the_closing_quote() {
+        assert_eq!(Some(6), end_of("a \\\" b\" rest"));
+        assert_eq!(Some(22), end_of("($x | str join \", \") b\""));
+        assert_eq!(Some(11), end_of("('a \" b') c\""));
+        assert_eq!(Some(4), end_of("it's\" rest"));
+    }
+
+    #[test]
+    fn single_quoted() {
+        let end_of = |content: &str| {
+            let mut state = Interpolation::new(b'\'');
+            content.bytes().position(|byte| state.push(byte))
+        };
+        assert_eq!(Some(3), end_of("a\\b' rest"));
+        assert_eq!(Some(19), end_of("($x | str join \"'\")' b'"));
+        assert_eq!(
+            "a  ($x | str join ' ')",
+            normalize_subexpressions("a  ( $x |  str join ' ' )", b'\'')
+        );
+    }
+
+    #[test]
+    fn escaped_parenthesis_is_literal() {
+        assert_eq!(Some(3), end_of("\\(x\" rest"));
+        assert_eq!(None, end_of("(x\" rest"));
+    }
+
+    #[test]
+    fn normalizes_only_subexpressions() {
+        assert_eq!(
+            "total:  ($x + 1) of ($items | length)",
+            normalize_subexpressions("total:  (  $x   +  1 ) of ($items  |  length )", b'"')
+        );
+    }
+
+    #[test]
+    fn keeps_inner_strings() {
+        assert_eq!(
+            "($x | str join \",  \")",
+            normalize_subexpressions("( $x  | str join \",  \" )", b'"')
+        );
+    }
+
+    #[test]
+    fn rendered_widths() {
+        assert_eq!(12, rendered_width("\"a (  $x  )\"", false));
+        assert_eq!(8, rendered_width("\"a (  $x  )\"", true));
+        assert_eq!(4, rendered_width("\"a (\n$x)\"", false));
+        assert_eq!(8, rendered_width("\"a (\n$x)\"", true));
+        assert_eq!(8, rendered_width("'a (  $x  )'", true));
+    }
+}
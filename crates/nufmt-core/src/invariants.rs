@@ -0,0 +1,530 @@
+//!
+//! Invariants the formatter must keep, checked by comparing a script before and after formatting.
+
+use crate::directive::OPTION_VALUES;
+use crate::hints::CONTINUATION_OPERATORS;
+use crate::lexer::{is_command_position, tokenize, TokenKind};
+use crate::{format_nu, Indentation};
+
+/// A broken invariant
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Violation {
+    /// The name of the broken invariant
+    pub invariant: &'static str,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = crate::lint::terminal_safe(&self.message);
+        write!(f, "invariant {} broken: {}", self.invariant, message)
+    }
+}
+
+impl std::error::Error for Violation {}
+
+/// Compares a script before and after formatting
+type Check = fn(&str, &str) -> Result<(), Violation>;
+
+/// Every invariant checked by [audit]
+const CHECKS: &[Check] = &[check_external_calls, check_element_order, check_variables];
+
+///
+/// # Checks every invariant
+///
+/// Returns the broken invariants, an empty list means the formatted script is safe to use.
+///
+pub fn audit(original: &str, formatted: &str) -> Vec<Violation> {
+    CHECKS
+        .iter()
+        .filter_map(|check| check(original, formatted).err())
+        .collect()
+}
+
+///
+/// # Lists every combination of the values of two options
+///
+/// The other options keep their default value, which covers how any two options play together
+/// without formatting a script for the whole product of all values. Each configuration is a
+/// directive comment, e.g. `# nufmt: indent=4 pipe_placement=leading ...`, which applies to a
+/// whole script when written on its first line.
+///
+pub fn configurations() -> Vec<String> {
+    let defaults: Vec<&str> = OPTION_VALUES.iter().map(|(_, values)| values[0]).collect();
+    let mut combinations = vec![defaults.clone()];
+    for (first, (_, first_values)) in OPTION_VALUES.iter().enumerate() {
+        for (second, (_, second_values)) in OPTION_VALUES.iter().enumerate().skip(first + 1) {
+            for first_value in first_values.iter() {
+                for second_value in second_values.iter() {
+                    let mut values = defaults.clone();
+                    values[first] = first_value;
+                    values[second] = second_value;
+                    if !combinations.contains(&values) {
+                        combinations.push(values);
+                    }
+                }
+            }
+        }
+    }
+
+    combinations
+        .iter()
+        .map(|values| {
+            let pairs: Vec<String> = OPTION_VALUES
+                .iter()
+                .zip(values)
+                .map(|((key, _), value)| format!("{}={}", key, value))
+                .collect();
+            format!("# nufmt: {}", pairs.join(" "))
+        })
+        .collect()
+}
+
+///
+/// # Checks every invariant under every configuration
+///
+/// Formats the script once per [configuration](configurations) and returns the configurations
+/// breaking an invariant. Formatting is also expected to be stable, formatting the result again
+/// must not change it.
+///
+pub fn audit_configurations(nu: &str) -> Vec<(String, Violation)> {
+    let mut violations = Vec::new();
+    for configuration in configurations() {
+        let original = format!("{}\n{}", configuration, nu);
+        let formatted = format_nu(&original, Indentation::Default);
+        for violation in audit(&original, &formatted) {
+            violations.push((configuration.clone(), violation));
+        }
+
+        let again = format_nu(&formatted, Indentation::Default);
+        if again != formatted {
+            let violation = Violation {
+                invariant: "stability",
+                message: format!("`{}` became `{}`", formatted, again),
+            };
+            violations.push((configuration, violation));
+        }
+    }
+    violations
+}
+
+///
+/// # Lists the arguments of every external command call
+///
+/// The arguments are split the way they are passed to the command: whitespace separates them and
+/// everything else, quotes included, is kept as written. Whitespace inside subexpressions and
+/// collections is not significant and is dropped, unless they are glued to a bare word like in
+/// `jsonpath={.items[*]}`.
+///
+pub fn external_calls(nu: &str) -> Vec<Vec<String>> {
+    let tokens = tokenize(nu);
+    let mut calls = Vec::new();
+    let mut enclosing = Vec::new();
+
+    for start in 0..tokens.len() {
+        match tokens[start].kind {
+            kind @ (TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen) => {
+                enclosing.push(kind)
+            }
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => {
+                enclosing.pop();
+            }
+            _ => {}
+        }
+        let is_external = tokens[start].text(nu).starts_with('^');
+        if !is_external || !is_command_position(nu, &tokens, start) {
+            continue;
+        }
+        // calls in parentheses go on after newlines and comment lines
+        let in_parentheses = enclosing.last() == Some(&TokenKind::OpenParen);
+
+        let mut arguments = vec![String::new()];
+        let mut depth = 0usize;
+        let mut glued = false;
+        for token in &tokens[start..] {
+            match token.kind {
+                TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen => {
+                    if depth == 0 {
+                        glued = arguments.last().is_some_and(|arg| !arg.is_empty());
+                    }
+                    depth += 1;
+                }
+                TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => {
+                    if depth == 0 {
+                        break;
+                    }
+                    depth -= 1;
+                }
+                TokenKind::Pipe | TokenKind::Semicolon if depth == 0 => break,
+                TokenKind::Comment | TokenKind::Newline if depth == 0 && !in_parentheses => break,
+                _ => {}
+            }
+
+            match token.kind {
+                TokenKind::Whitespace | TokenKind::Newline | TokenKind::Comment if depth == 0 => {
+                    if arguments.last().is_some_and(|arg| !arg.is_empty()) {
+                        arguments.push(String::new());
+                    }
+                }
+                TokenKind::Whitespace | TokenKind::Newline | TokenKind::Comment if !glued => {}
+                _ => arguments.last_mut().unwrap().push_str(token.text(nu)),
+            }
+        }
+
+        arguments.retain(|arg| !arg.is_empty());
+        calls.push(arguments);
+    }
+
+    calls
+}
+
+///
+/// # Checks that external command calls are unchanged
+///
+/// External commands receive their arguments as strings, so any change of them, even just of
+/// spacing, changes what the script does.
+///
+pub fn check_external_calls(original: &str, formatted: &str) -> Result<(), Violation> {
+    let before = external_calls(original);
+    let after = external_calls(formatted);
+    if before.len() != after.len() {
+        return Err(Violation {
+            invariant: "external-calls",
+            message: format!(
+                "{} external calls before formatting, {} after",
+                before.len(),
+                after.len()
+            ),
+        });
+    }
+
+    match before.iter().zip(&after).find(|(b, a)| b != a) {
+        Some((b, a)) => Err(Violation {
+            invariant: "external-calls",
+            message: format!("`{}` became `{}`", b.join(" "), a.join(" ")),
+        }),
+        None => Ok(()),
+    }
+}
+
+///
+/// # Checks that nothing was reordered, added or removed
+///
+/// Statements, collection elements and record entries must come in the same order, so the
+/// sequences of tokens other than whitespace must match. Whitespace inside strings and comments
+/// is ignored as some options normalize it, and so is the position of pipes and operators
+/// relative to comment lines as their placement options move them over these lines. Words are
+/// split at `=` as the spacing of default values is normalized, and commas before closing
+/// brackets are ignored as trailing commas may be added. No option reorders anything else
+/// yet, one that does (like sorting record keys) will have to be excluded from this check.
+///
+pub fn check_element_order(original: &str, formatted: &str) -> Result<(), Violation> {
+    let before = elements(original);
+    let after = elements(formatted);
+
+    let position = before.iter().zip(&after).position(|(b, a)| b != a);
+    let position = match position {
+        Some(position) => position,
+        None if before.len() == after.len() => return Ok(()),
+        None => before.len().min(after.len()),
+    };
+
+    let describe = |elements: &[String]| match elements.get(position) {
+        Some(element) => format!("`{}`", element),
+        None => "the end".to_string(),
+    };
+    Err(Violation {
+        invariant: "element-order",
+        message: format!(
+            "element {} was {} and is now {}",
+            position + 1,
+            describe(&before),
+            describe(&after)
+        ),
+    })
+}
+
+///
+/// # Lists the variables and their cell paths
+///
+/// A variable like `$in` or `$it` and its cell path, `$in."a b".0` for instance, are one token for
+/// nu and must never be split by whitespace.
+///
+pub fn variables(nu: &str) -> Vec<String> {
+    let tokens = tokenize(nu);
+    let mut variables: Vec<String> = Vec::new();
+    let mut in_variable = false;
+
+    for token in &tokens {
+        let text = token.text(nu);
+        match token.kind {
+            TokenKind::Word if text.starts_with('$') && text != "$" => {
+                variables.push(text.to_string());
+                in_variable = true;
+            }
+            TokenKind::Word | TokenKind::String if in_variable => {
+                variables.last_mut().unwrap().push_str(text);
+            }
+            _ => in_variable = false,
+        }
+    }
+
+    variables
+}
+
+///
+/// # Checks that variables keep their cell paths
+///
+pub fn check_variables(original: &str, formatted: &str) -> Result<(), Violation> {
+    let before = variables(original);
+    let after = variables(formatted);
+
+    let changed = before.iter().zip(&after).find(|(b, a)| b != a);
+    match changed {
+        Some((b, a)) => Err(Violation {
+            invariant: "variables",
+            message: format!("`{}` became `{}`", b, a),
+        }),
+        None if before.len() != after.len() => Err(Violation {
+            invariant: "variables",
+            message: format!(
+                "{} variables before formatting, {} after",
+                before.len(),
+                after.len()
+            ),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn elements(nu: &str) -> Vec<String> {
+    let mut elements = Vec::new();
+    // pipes and operators may move over comment lines, they are put right before what follows
+    let mut continuation = None;
+    let tokens = tokenize(nu);
+    let significant = |index: usize| {
+        tokens[index..]
+            .iter()
+            .find(|token| !matches!(token.kind, TokenKind::Whitespace | TokenKind::Newline))
+            .map(|token| token.kind)
+    };
+    let mut skip_pipe = false;
+    for (index, token) in tokens.iter().enumerate() {
+        let text = token.text(nu);
+        let element = match token.kind {
+            TokenKind::Whitespace | TokenKind::Newline => continue,
+            // closures without parameters may be written `{ ls }` or `{|| ls}`
+            TokenKind::Pipe if skip_pipe => {
+                skip_pipe = false;
+                continue;
+            }
+            TokenKind::Pipe
+                if elements.last().map(String::as_str) == Some("{")
+                    && continuation.is_none()
+                    && tokens.get(index + 1).map(|token| token.kind) == Some(TokenKind::Pipe)
+                    && !matches!(significant(index + 2), None | Some(TokenKind::CloseBrace)) =>
+            {
+                skip_pipe = true;
+                continue;
+            }
+            // default values of parameters get spaces around their =, `x=5` becomes `x = 5`
+            TokenKind::Word if text.contains('=') => {
+                elements.extend(continuation.take());
+                let parts =
+                    text.split_inclusive('=')
+                        .flat_map(|part| match part.strip_suffix('=') {
+                            Some(before) => vec![before, "="],
+                            None => vec![part],
+                        });
+                elements.extend(parts.filter(|part| !part.is_empty()).map(str::to_string));
+                continue;
+            }
+            // trailing_commas adds commas after the last elements, `[1, 2]` becomes `[1, 2,]`
+            TokenKind::Comma
+                if matches!(
+                    significant(index + 1),
+                    Some(TokenKind::CloseBracket | TokenKind::CloseBrace)
+                ) =>
+            {
+                continue;
+            }
+            TokenKind::Pipe => {
+                continuation = Some(text.to_string());
+                continue;
+            }
+            TokenKind::Word if CONTINUATION_OPERATORS.contains(&text) => {
+                continuation = Some(text.to_string());
+                continue;
+            }
+            TokenKind::String | TokenKind::Comment | TokenKind::Attribute => {
+                text.split_whitespace().collect()
+            }
+            _ => text.to_string(),
+        };
+        if token.kind != TokenKind::Comment {
+            elements.extend(continuation.take());
+        }
+        elements.push(element);
+    }
+    elements.extend(continuation);
+    elements
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Command lines as found in real scripts
+    const CORPUS: &[&str] = &[
+        "^git log --format=%H:%s -n 10",
+        "^git log --pretty=\"format:%h %an, %ar : %s\"",
+        "^git commit -m \"fix: handle a, b and c\"",
+        "^git commit -m 'fix: \"a\", {b} and [c]'",
+        "^curl -s -H \"Authorization: Bearer abc\" https://example.com/api?a=b,c",
+        "^ffmpeg -i in.mp4 -vf scale=1280:-1,fps=30 -c:v libx264 out.mp4",
+        "^docker run --rm -p 8080:80 -v ./data:/data -e \"A=b  c\" image:latest",
+        "^rsync -avz --exclude=*.tmp src/ host:/srv/dst/",
+        "^ssh -o StrictHostKeyChecking=no user@host:2222",
+        "^kubectl get pods -o jsonpath={.items[*].metadata.name}",
+        "^C:\\tools\\bin\\app.exe /flag:value,other",
+        "^awk -F, \"{ print $1 }\" file.csv",
+        "^cargo   build   --release   --features   a,b",
+        "^make -j8 CFLAGS=\"-O2  -g\" | lines | first 3",
+        "^echo a:b; ^echo c,d",
+        "^find . -name \"*.nu\" -newer ref.nu\n^echo done",
+    ];
+
+    #[test]
+    fn splits_arguments() {
+        let calls = external_calls("ls | ^git   log --format=\"%H %s\" | lines # comment");
+        assert_eq!(vec![vec!["^git", "log", "--format=\"%H %s\""]], calls);
+    }
+
+    #[test]
+    fn calls_in_parentheses_span_lines() {
+        let calls = external_calls("(^cargo build\n  # why\n  --release)\n^ls\n-a");
+        assert_eq!(
+            vec![vec!["^cargo", "build", "--release"], vec!["^ls"]],
+            calls
+        );
+    }
+
+    #[test]
+    fn glued_groups_keep_whitespace() {
+        let calls = external_calls("^echo [a  b] x=[a  b]");
+        assert_eq!(vec![vec!["^echo", "[ab]", "x=[a  b]"]], calls);
+    }
+
+    #[test]
+    fn detects_changed_arguments() {
+        let violation = check_external_calls("^a b:c", "^a b: c").unwrap_err();
+        assert_eq!("`^a b:c` became `^a b: c`", violation.message);
+    }
+
+    #[test]
+    fn detects_reordering() {
+        let violation = check_element_order("[1, 2]", "[2, 1]").unwrap_err();
+        assert_eq!("element 2 was `1` and is now `2`", violation.message);
+        let violation = check_element_order("ls\nps", "ls").unwrap_err();
+        assert_eq!("element 2 was `ps` and is now the end", violation.message);
+    }
+
+    #[test]
+    fn closures_without_parameters() {
+        assert_eq!(Ok(()), check_element_order("do { ls }", "do {|| ls}"));
+        assert!(check_element_order("do {||}", "do {}").is_err());
+        assert!(check_element_order("do {|x| ls}", "do { ls}").is_err());
+    }
+
+    #[test]
+    fn variables_with_cell_paths() {
+        let nu = "$in.\"a b\".0 | where $it.size > 1 | $\"($x)\"";
+        assert_eq!(vec!["$in.\"a b\".0", "$it.size"], variables(nu));
+        let violation = check_variables("$in.a", "$in .a").unwrap_err();
+        assert_eq!("`$in.a` became `$in`", violation.message);
+    }
+
+    #[test]
+    fn formatting_keeps_variables() {
+        let nu = "$in | each {|row| {name: $row.\"full name\", size: $in.0?.size}}";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert!(formatted.starts_with("$in | each"));
+        assert_eq!(Ok(()), check_variables(nu, &formatted));
+    }
+
+    #[test]
+    fn violations_are_terminal_safe() {
+        let violation = check_element_order("\"\u{1b}[31m\"", "\"\u{1b}[32m\"").unwrap_err();
+        assert_eq!(
+            "invariant element-order broken: element 1 was `\"\\u{1b}[31m\"` and is now `\"\\u{1b}[32m\"`",
+            violation.to_string()
+        );
+    }
+
+    #[test]
+    fn formatting_keeps_order() {
+        let scripts = [
+            "def f [x=5, y?: int=3, --flag (-f): string=\"a\"] {}",
+            "let a = 1\nlet b = [3, 1, 2]\nlet c = {z: 1, a: 2, m: [{b: 1}, {a: 2}]}",
+            "def main [] {\n  ls | sort-by size | first 3 # biggest\n  ps\n}",
+            "[[1, 2], [], {}, {\"k\": \"v  w\"}]",
+        ];
+        for nu in &scripts {
+            let formatted = format_nu(nu, Indentation::Default);
+            assert_eq!(Vec::<Violation>::new(), audit(nu, &formatted));
+        }
+    }
+
+    #[test]
+    fn corpus_is_unchanged_by_formatting() {
+        for nu in CORPUS {
+            assert!(!external_calls(nu).is_empty(), "no call in {}", nu);
+            let formatted = format_nu(nu, Indentation::Default);
+            if let Err(violation) = check_external_calls(nu, &formatted) {
+                panic!("{}: {}", violation.invariant, violation.message);
+            }
+        }
+    }
+
+    /// Scripts using the constructs every option is about
+    const MATRIX: &[&str] = &[
+        "def main [x: int, --flag (-f)] {\n  ls\n  | where size > 1kb # big\n  | length\n}",
+        "let total = ($a +\n  $b\n  - $c)\nprint $\"total: ( $total  + 1 )\"",
+        "ls |\n  each {|row| {name: $row.name, size: $row.size}} |\n  sort-by size",
+        "match $x {\n  [1, $y] => { $y }\n  _ => { 0 }\n}",
+        "if ($a and\n  $b) {\n  ^git log --format=%H:%s\n} else {\n  'a  b'\n}",
+        "# nufmt: embedded_code=false\nnu -c \"ls|length\"\n@example \"x\" { f }\ndef f [] {}",
+        "do { ls }\nls | each {|| $in.name } | do {||}\nlet f = { |x| $x }",
+        "let x = if $c { 1 } else { [1, 2] }\nlet y = if $c {\n  1\n} else { 2 }",
+        "{render: {|| date now | format date }, when: if $c { 1 } else { 2 }, p: {|x|\n$x}}",
+        "let shells = [bash, zsh, fish, nu, \"power shell\", elvish, xonsh, ion, 1.5, 0x1f, tcsh, dash]\ndef f [a, b] { [[1, 2], [3]] }",
+        "let out = (^cargo build src --release\n  --features a,b\n  # why\n  -j 2)\n(git log\n  -n 5 | lines)",
+        "# a\n\n# b\n# c\ndef f [] {}\nlet x = 1\n# d\ndef g [] {}",
+        "let t = [[name, size]; [a.nu, 10], [\"b c\", 2]]\n[[a b]; [1 2]\n  [3 4]] | to md",
+        "let config = {\n  name:    \"nufmt\", # the name\n  version: \"0.1\"    # semver\n}\nlet a   = 1\nlet bcd = 2",
+        "def f [\n  x: int, # the x\n  --flag (-f): string # what it does\n  y? # optional\n] {}",
+        "let page = (http get https://example.com/some/long/path/to/a/resource?query=1&sort=asc | from json)",
+    ];
+
+    #[test]
+    fn every_configuration_is_listed() {
+        let configurations = configurations();
+        assert_eq!(622, configurations.len());
+        assert!(configurations.contains(
+            &"# nufmt: indent=tab embedded_code=false interpolation_spacing=false pipe_placement=leading break_pipelines=false preserve_line_breaks=true operator_placement=keep item_spacing=false definition_blank_lines=keep flag_grouping=false closure_style=keep list_fill=false fill_max_items=none fill_min_item_width=0 comment_min_spaces_before=1 wrap_long_tokens=false table_alignment=false preserve_alignment=false signature_comment_alignment=false bracket_spacing=false brace_spacing=false expand_collections=false trailing_commas=false max_blank_lines=1 max_width=100 disable=none preset=none".to_string()
+        ));
+        assert!(!configurations.contains(
+            &"# nufmt: indent=tab embedded_code=true interpolation_spacing=false pipe_placement=leading break_pipelines=false preserve_line_breaks=true operator_placement=keep item_spacing=false definition_blank_lines=keep flag_grouping=false closure_style=keep list_fill=false fill_max_items=none fill_min_item_width=0 comment_min_spaces_before=1 wrap_long_tokens=false table_alignment=false preserve_alignment=false signature_comment_alignment=false bracket_spacing=false brace_spacing=false expand_collections=false trailing_commas=false max_blank_lines=1 max_width=100 disable=none preset=none".to_string()
+        ));
+    }
+
+    #[test]
+    fn configurations_keep_invariants() {
+        for nu in MATRIX {
+            let violations = audit_configurations(nu);
+            if let Some((configuration, violation)) = violations.first() {
+                panic!("{}\n{}: {}", configuration, nu, violation);
+            }
+        }
+    }
+}
@@ -0,0 +1,1445 @@
+//!
+//! A lightweight lexer splitting nu into tokens with their spans.
+//!
+//! It splits the source the same way the formatter does, so rules and diagnostics built on the
+//! tokens agree with the formatted output.
+
+use crate::interpolation::Interpolation;
+
+/// A byte range in the source
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum TokenKind {
+    /// Spaces and tabs
+    Whitespace,
+    Newline,
+    /// From `#` to the end of the line, newline excluded
+    Comment,
+    /// A double quoted string, quotes included, the `$` of an interpolated string is a word
+    String,
+    /// Commands, flags, numbers, variables, operators, single quoted strings...
+    Word,
+    /// An attribute of the def below it, `@example "adds" { add 1 2 }`, newline excluded
+    Attribute,
+    OpenBracket,
+    CloseBracket,
+    OpenBrace,
+    CloseBrace,
+    OpenParen,
+    CloseParen,
+    Comma,
+    Colon,
+    Pipe,
+    Semicolon,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+impl Token {
+    /// The text of the token in `source`
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.span.start..self.span.end]
+    }
+}
+
+///
+/// # Splits nu into tokens
+///
+/// Every byte of the input belongs to exactly one token, so the source can be rebuilt from the
+/// tokens. Unterminated strings run to the end of the input.
+///
+pub fn tokenize(nu: &str) -> Vec<Token> {
+    tokens(nu).collect()
+}
+
+///
+/// # Splits nu into tokens as they are read
+///
+/// Like [tokenize], without lexing the rest of the input once the caller stops reading: lookaheads
+/// from a position of a script only go as far as the value they measure.
+///
+pub fn tokens(nu: &str) -> Tokens<'_> {
+    Tokens {
+        bytes: nu.as_bytes(),
+        start: 0,
+        line_start: true,
+    }
+}
+
+/// The tokens of a script, see [tokens]
+#[derive(Debug, Clone)]
+pub struct Tokens<'a> {
+    bytes: &'a [u8],
+    start: usize,
+    // nothing but whitespace since the last newline, attributes start lines
+    line_start: bool,
+}
+
+impl Iterator for Tokens<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        let (bytes, start) = (self.bytes, self.start);
+        if start >= bytes.len() {
+            return None;
+        }
+        let attribute = bytes[start] == b'@' && self.line_start;
+        let end = match bytes[start] {
+            _ if attribute => scan_attribute(bytes, start),
+            b' ' | b'\t' | b'\r' => scan_while(bytes, start, |b| matches!(b, b' ' | b'\t' | b'\r')),
+            b'#' => scan_while(bytes, start, |b| b != b'\n'),
+            b'"' | b'\'' if start > 0 && bytes[start - 1] == b'$' => {
+                scan_interpolation(bytes, start)
+            }
+            b'"' => scan_string(bytes, start),
+            b'r' => match raw_string_len(&bytes[start..]) {
+                Some(len) => start + len,
+                None => scan_word(bytes, start),
+            },
+            b if is_word_byte(b) => scan_word(bytes, start),
+            _ => start + 1,
+        };
+        let kind = match bytes[start] {
+            _ if attribute => TokenKind::Attribute,
+            b' ' | b'\t' | b'\r' => TokenKind::Whitespace,
+            b'\n' => TokenKind::Newline,
+            b'#' => TokenKind::Comment,
+            b'"' => TokenKind::String,
+            b'\'' if start > 0 && bytes[start - 1] == b'$' => TokenKind::String,
+            b'[' => TokenKind::OpenBracket,
+            b']' => TokenKind::CloseBracket,
+            b'{' => TokenKind::OpenBrace,
+            b'}' => TokenKind::CloseBrace,
+            b'(' => TokenKind::OpenParen,
+            b')' => TokenKind::CloseParen,
+            b',' => TokenKind::Comma,
+            b':' => TokenKind::Colon,
+            b'|' => TokenKind::Pipe,
+            b';' => TokenKind::Semicolon,
+            _ => TokenKind::Word,
+        };
+
+        match kind {
+            TokenKind::Newline => self.line_start = true,
+            TokenKind::Whitespace => {}
+            _ => self.line_start = false,
+        }
+        self.start = end;
+        Some(Token {
+            kind,
+            span: Span { start, end },
+        })
+    }
+}
+
+///
+/// # Finds the top level statements of a script
+///
+/// Statements are separated by the newlines outside of any bracket which do not continue a
+/// pipeline, `ls |` or `| length` on the next line. Several statements on one line, `a; b`, make
+/// one span. The spans exclude the whitespace around the statements, blank lines have none.
+///
+pub fn statement_spans(nu: &str) -> Vec<Span> {
+    let tokens = tokenize(nu);
+    let significant =
+        |token: &&Token| !matches!(token.kind, TokenKind::Whitespace | TokenKind::Newline);
+    let pipe = |token: Option<&Token>| token.map(|token| token.kind) == Some(TokenKind::Pipe);
+
+    let mut spans = Vec::new();
+    let mut current: Option<Span> = None;
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen => depth += 1,
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => {
+                depth = depth.saturating_sub(1)
+            }
+            TokenKind::Newline if depth == 0 => {
+                let before = tokens[..i].iter().rev().find(&significant);
+                let after = tokens[i + 1..].iter().find(&significant);
+                if !pipe(before) && !pipe(after) {
+                    spans.extend(current.take());
+                }
+                continue;
+            }
+            _ => {}
+        }
+        if significant(&token) {
+            let span = current.get_or_insert(token.span);
+            span.end = token.span.end;
+        }
+    }
+    spans.extend(current);
+    spans
+}
+
+///
+/// # Checks whether a call in parentheses spans several lines
+///
+/// `rest` starts after the name of the command. The call ends at a pipe, a semicolon or the
+/// closing parenthesis. Calls holding comments are not considered, their lines cannot be joined.
+///
+pub fn is_multiline_call(rest: &str) -> bool {
+    let mut depth = 0usize;
+    let mut newline = false;
+    let mut multiline = false;
+    for token in tokens(rest) {
+        match token.kind {
+            TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen => depth += 1,
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => {
+                if depth == 0 {
+                    return multiline;
+                }
+                depth -= 1;
+            }
+            TokenKind::Pipe | TokenKind::Semicolon if depth == 0 => return multiline,
+            TokenKind::Newline if depth == 0 => newline = true,
+            TokenKind::Comment => return false,
+            _ => {}
+        }
+        // a newline only counts once the call continues after it
+        multiline |= newline && !matches!(token.kind, TokenKind::Whitespace | TokenKind::Newline);
+    }
+    false
+}
+
+///
+/// # Measures the rest of a value written on the current line
+///
+/// The value ends at a newline, a comma, a semicolon or a closing bracket which is not its own.
+/// Runs of whitespace count as one character, and interpolated strings as they are rendered, see
+/// [rendered_width](crate::interpolation::rendered_width). Returns `None` if the value spans
+/// several lines.
+///
+pub fn single_line_width(rest: &str, interpolation_spacing: bool) -> Option<usize> {
+    joined_line_width(rest, |_| false, interpolation_spacing)
+}
+
+///
+/// # Measures the rest of a value once some of its lines are joined
+///
+/// Like [single_line_width], the newlines at the offsets of `rest` which are `joined` counting as
+/// whitespace.
+///
+pub fn joined_line_width(
+    rest: &str,
+    joined: impl Fn(usize) -> bool,
+    interpolation_spacing: bool,
+) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut width = 0;
+    let mut space = false;
+    for token in tokens(rest) {
+        let kind = match token.kind {
+            TokenKind::Newline if joined(token.span.start) => TokenKind::Whitespace,
+            kind => kind,
+        };
+        match kind {
+            TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen => depth += 1,
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+            }
+            TokenKind::Comma | TokenKind::Semicolon if depth == 0 => break,
+            TokenKind::Newline if depth == 0 => break,
+            TokenKind::Newline => return None,
+            _ => {}
+        }
+        let interpolated = token.span.start > 0 && rest.as_bytes()[token.span.start - 1] == b'$';
+        width += match kind {
+            // runs of whitespace and joined newlines count as one character
+            TokenKind::Whitespace if space => 0,
+            TokenKind::Whitespace => 1,
+            TokenKind::String if interpolated => {
+                crate::interpolation::rendered_width(token.text(rest), interpolation_spacing)
+            }
+            _ => display_width(token.text(rest)),
+        };
+        space = kind == TokenKind::Whitespace;
+    }
+    Some(width)
+}
+
+///
+/// # Measures a collection once its lines are joined
+///
+/// `rest` starts after the bracket opening the collection, which is measured up to its closing
+/// bracket, both excluded. Runs of whitespace and newlines count as one space, except after an
+/// opening bracket and before a closing one, and commas and colons are followed by a space.
+/// Returns `None` if the collection holds a comment or is not closed in `rest`.
+///
+pub fn joined_width(rest: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut width = 0;
+    // whether a space is written before the next token
+    let mut space = false;
+    for token in tokens(rest) {
+        match token.kind {
+            TokenKind::Whitespace | TokenKind::Newline => {
+                space |= width > 0 && !rest[..token.span.start].ends_with(['[', '{', '(']);
+                continue;
+            }
+            TokenKind::Comment => return None,
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => {
+                space = false;
+                if depth == 0 {
+                    return Some(width);
+                }
+                depth -= 1;
+            }
+            TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen => depth += 1,
+            TokenKind::Comma | TokenKind::Colon => space = false,
+            _ => {}
+        }
+        width += usize::from(space) + display_width(token.text(rest));
+        space = matches!(token.kind, TokenKind::Comma | TokenKind::Colon);
+    }
+    None
+}
+
+///
+/// # Finds the end of the stage of a pipeline `rest` starts in
+///
+/// The offset of the pipe ending it, or of the end of its line or of the block holding the
+/// pipeline if there is none before, the length of `rest` if neither.
+///
+pub fn stage_end(rest: &str) -> usize {
+    let mut depth = 0usize;
+    for token in tokens(rest) {
+        match token.kind {
+            TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen => depth += 1,
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => {
+                if depth == 0 {
+                    return token.span.start;
+                }
+                depth -= 1;
+            }
+            TokenKind::Pipe | TokenKind::Newline if depth == 0 => return token.span.start,
+            _ => {}
+        }
+    }
+    rest.len()
+}
+
+/// Whether the body of a closure is a pipeline, `rest` starting after the `{` of the closure
+pub fn is_closure_pipeline(rest: &str) -> bool {
+    let mut depth = 0usize;
+    // the parameters are between the first two pipes
+    let mut pipes = 0;
+    for token in tokens(rest) {
+        match token.kind {
+            TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen => depth += 1,
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+            }
+            TokenKind::Pipe if depth == 0 => pipes += 1,
+            _ => {}
+        }
+    }
+    pipes > 2
+}
+
+///
+/// # Whether a list or record is only made of lists, records and values
+///
+/// `rest` starts after the `[` or `{` opening it. Records are told apart from blocks by their first
+/// key, `{a: 1}`, and the braces nested in the collection must open records too: closures and
+/// blocks have a layout of their own. Returns `false` if the collection is not closed in `rest`.
+///
+pub fn is_flat_collection(open: u8, rest: &str) -> bool {
+    let tokens = tokenize(rest);
+    if open == b'{' && !opens_record(&tokens) {
+        return false;
+    }
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::OpenBracket | TokenKind::OpenParen => depth += 1,
+            TokenKind::OpenBrace if opens_record(&tokens[i + 1..]) => depth += 1,
+            TokenKind::OpenBrace => return false,
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => {
+                if depth == 0 {
+                    return true;
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Whether the tokens after a `{` are those of a record, empty or starting with `key:`
+pub(crate) fn opens_record(tokens: &[Token]) -> bool {
+    let mut significant = tokens.iter().filter(|token| {
+        !matches!(
+            token.kind,
+            TokenKind::Whitespace | TokenKind::Newline | TokenKind::Comment
+        )
+    });
+    match significant.next().map(|token| token.kind) {
+        Some(TokenKind::CloseBrace) => true,
+        Some(TokenKind::Word | TokenKind::String) => {
+            significant.next().map(|token| token.kind) == Some(TokenKind::Colon)
+        }
+        _ => false,
+    }
+}
+
+/// What a `{` opens, see [brace_kinds]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum BraceKind {
+    /// `{a: 1}`, laid out an entry per line
+    Record,
+    /// `if $x { ls }`, laid out a statement per line
+    Block,
+    /// `{|x| $x + 1 }`, a block with parameters on the line of its brace
+    Closure,
+}
+
+///
+/// # Tells records, blocks and closures apart
+///
+/// Returns the offset of every `{` along with what it opens, by offset. A brace followed by
+/// parameters opens a closure. The braces of a statement using a keyword which takes blocks, like
+/// `if` or `try`, open blocks, see [BLOCK_KEYWORDS](crate::hints::BLOCK_KEYWORDS). The others
+/// open records if they are empty or start with a key, `{a: 1}`, and blocks otherwise.
+///
+pub fn brace_kinds(nu: &str) -> Vec<(usize, BraceKind)> {
+    let tokens = tokenize(nu);
+    // whether the current statement of each open bracket uses a block keyword, the script first
+    let mut keywords = vec![false];
+    let mut kinds = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::Word if crate::hints::BLOCK_KEYWORDS.contains(&token.text(nu)) => {
+                if let Some(keyword) = keywords.last_mut() {
+                    *keyword = true;
+                }
+            }
+            TokenKind::Newline | TokenKind::Semicolon | TokenKind::Pipe => {
+                if let Some(keyword) = keywords.last_mut() {
+                    *keyword = false;
+                }
+            }
+            TokenKind::OpenBrace => {
+                let kind = if next_significant(&tokens, i) == Some(TokenKind::Pipe) {
+                    BraceKind::Closure
+                } else if keywords.last() == Some(&true) {
+                    BraceKind::Block
+                } else if opens_record(&tokens[i + 1..]) {
+                    BraceKind::Record
+                } else {
+                    BraceKind::Block
+                };
+                kinds.push((token.span.start, kind));
+                keywords.push(false);
+            }
+            TokenKind::OpenBracket | TokenKind::OpenParen => keywords.push(false),
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen
+                if keywords.len() > 1 =>
+            {
+                keywords.pop();
+            }
+            _ => {}
+        }
+    }
+    kinds
+}
+
+///
+/// # Finds the record entries written after another one on its line
+///
+/// Returns the offsets of their keys, `b` in `{a: 1 b: 2}`, which start a line of their own once
+/// the record is laid out over several lines. The entries following a comma are not returned.
+///
+pub fn record_entries(nu: &str) -> Vec<usize> {
+    let tokens = tokenize(nu);
+    let kinds = brace_kinds(nu);
+    // whether each open bracket is a record, the script first
+    let mut records = vec![false];
+    let mut entries = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::OpenBrace => {
+                let record = kinds
+                    .binary_search_by_key(&token.span.start, |(offset, _)| *offset)
+                    .is_ok_and(|found| kinds[found].1 == BraceKind::Record);
+                records.push(record);
+            }
+            TokenKind::OpenBracket | TokenKind::OpenParen => records.push(false),
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen
+                if records.len() > 1 =>
+            {
+                records.pop();
+            }
+            TokenKind::Word | TokenKind::String
+                if records.last() == Some(&true)
+                    && next_significant(&tokens, i) == Some(TokenKind::Colon) =>
+            {
+                let previous = tokens[..i]
+                    .iter()
+                    .rev()
+                    .find(|token| token.kind != TokenKind::Whitespace)
+                    .map(|token| token.kind);
+                let after_value = !matches!(
+                    previous,
+                    None | Some(
+                        TokenKind::OpenBrace
+                            | TokenKind::Comma
+                            | TokenKind::Colon
+                            | TokenKind::Newline
+                            | TokenKind::Comment
+                    )
+                );
+                if after_value {
+                    entries.push(token.span.start);
+                }
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+///
+/// # Finds the lists and records missing a comma after their last item
+///
+/// Returns the offsets of their closing bracket. Only collections separating their items with
+/// commas are found, and not tables, whose rows end with a semicolon.
+///
+pub fn missing_trailing_commas(nu: &str) -> Vec<usize> {
+    let tokens = tokenize(nu);
+    let significant: Vec<Token> = tokens
+        .iter()
+        .filter(|token| {
+            !matches!(
+                token.kind,
+                TokenKind::Whitespace | TokenKind::Newline | TokenKind::Comment
+            )
+        })
+        .copied()
+        .collect();
+    // (whether a list or record, whether with commas, whether a table) of the open delimiters
+    let mut open: Vec<(bool, bool, bool)> = Vec::new();
+    let mut missing = Vec::new();
+    for (i, token) in significant.iter().enumerate() {
+        match token.kind {
+            TokenKind::OpenBracket => open.push((true, false, false)),
+            TokenKind::OpenBrace => open.push((opens_record(&significant[i + 1..]), false, false)),
+            TokenKind::OpenParen => open.push((false, false, false)),
+            TokenKind::Comma => {
+                if let Some((_, commas, _)) = open.last_mut() {
+                    *commas = true;
+                }
+            }
+            TokenKind::Semicolon => {
+                if let Some((_, _, table)) = open.last_mut() {
+                    *table = true;
+                }
+            }
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => {
+                let last = significant[..i].last().map(|token| token.kind);
+                if let Some((true, true, false)) = open.pop() {
+                    if last != Some(TokenKind::Comma) {
+                        missing.push(token.span.start);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    missing
+}
+
+///
+/// # Finds the line breaks between the stages of pipelines
+///
+/// Returns the offsets of the newlines right after a pipe, `ls |`, or right before one,
+/// `| length` on the next line. The pipes around the parameters of closures do not count, and
+/// line breaks next to comments are not returned as the lines could not be joined. Neither are
+/// those of pipelines with a stage spanning several lines, `each {|x|` followed by its body.
+///
+pub fn pipeline_line_breaks(nu: &str) -> Vec<usize> {
+    let tokens = tokenize(nu);
+    let blank = |token: &&Token| matches!(token.kind, TokenKind::Whitespace | TokenKind::Newline);
+    // the pipes of a pipeline, rather than those around parameters
+    let mut stage_pipes = vec![false; tokens.len()];
+    let mut parameters = false;
+    for (i, token) in tokens.iter().enumerate() {
+        if token.kind != TokenKind::Pipe {
+            continue;
+        }
+        let before = tokens[..i].iter().rev().find(|token| !blank(token));
+        if parameters {
+            parameters = false;
+        } else if before.map(|token| token.kind) == Some(TokenKind::OpenBrace) {
+            // `{|x|` and `{||` alike, the next pipe closes the parameters
+            parameters = true;
+        } else {
+            stage_pipes[i] = true;
+        }
+    }
+    // (the line breaks of the current pipeline, whether one of its stages spans lines, whether
+    // the bracket spans lines) of the open brackets, the script first
+    let mut open = vec![(Vec::new(), false, false)];
+    let mut breaks = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen => {
+                open.push((Vec::new(), false, false))
+            }
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen
+                if open.len() > 1 =>
+            {
+                let mut bracket = open.pop().unwrap_or_default();
+                end_pipeline(&mut bracket, &mut breaks);
+                if let Some((_, multiline, spans)) = open.last_mut() {
+                    *multiline |= bracket.2;
+                    *spans |= bracket.2;
+                }
+            }
+            TokenKind::Semicolon => {
+                if let Some(pipeline) = open.last_mut() {
+                    end_pipeline(pipeline, &mut breaks);
+                }
+            }
+            TokenKind::Newline => {
+                let before = tokens[..i].iter().rposition(|token| !blank(&token));
+                let after = tokens[i + 1..]
+                    .iter()
+                    .position(|token| !blank(&token))
+                    .map(|j| i + 1 + j);
+                let stage = |index: Option<usize>| index.is_some_and(|index| stage_pipes[index]);
+                let code = |index: Option<usize>| {
+                    index.is_some_and(|index| tokens[index].kind != TokenKind::Comment)
+                };
+                let joined = (stage(before) || stage(after)) && code(before) && code(after);
+                if let Some(pipeline) = open.last_mut() {
+                    if joined {
+                        pipeline.0.push(token.span.start);
+                    } else {
+                        pipeline.2 = true;
+                        end_pipeline(pipeline, &mut breaks);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    for pipeline in &mut open {
+        end_pipeline(pipeline, &mut breaks);
+    }
+    breaks.sort_unstable();
+    breaks
+}
+
+/// Adds the line breaks of a pipeline to `breaks`, unless one of its stages spans lines: the
+/// layout of its blocks then decides where it is broken, and its line breaks are kept
+fn end_pipeline(pipeline: &mut (Vec<usize>, bool, bool), breaks: &mut Vec<usize>) {
+    let (pipeline_breaks, multiline, spans) = pipeline;
+    if *multiline {
+        *spans |= !pipeline_breaks.is_empty();
+        pipeline_breaks.clear();
+    } else {
+        breaks.append(pipeline_breaks);
+    }
+    *multiline = false;
+}
+
+/// A list holding only scalars, see [scalar_list]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ScalarList<'a> {
+    pub items: Vec<&'a str>,
+    /// Whether the items are separated by commas rather than whitespace only
+    pub commas: bool,
+    /// The offset of the closing `]`
+    pub end: usize,
+}
+
+///
+/// # Splits a list of scalars into its items
+///
+/// `rest` starts after the `[`. Returns `None` unless the list only holds strings and bare words
+/// like numbers, all separated by commas or all by whitespace only.
+///
+pub fn scalar_list(rest: &str) -> Option<ScalarList<'_>> {
+    let mut tokens = tokens(rest).peekable();
+    let mut items = Vec::new();
+    let mut commas = 0;
+    while let Some(token) = tokens.next() {
+        match token.kind {
+            TokenKind::Whitespace | TokenKind::Newline => {}
+            TokenKind::Comma => commas += 1,
+            TokenKind::String | TokenKind::Word => {
+                // glued tokens like `a:b` or `$"..."` are no scalars
+                let glued = tokens.peek().is_some_and(|next| {
+                    !matches!(
+                        next.kind,
+                        TokenKind::Whitespace
+                            | TokenKind::Newline
+                            | TokenKind::Comma
+                            | TokenKind::CloseBracket
+                    )
+                });
+                if glued {
+                    return None;
+                }
+                items.push(token.text(rest));
+            }
+            TokenKind::CloseBracket if !items.is_empty() => {
+                // trailing commas are kept where they are written, so lists with one are left alone
+                let separated = commas == 0 || commas == items.len() - 1;
+                return separated.then_some(ScalarList {
+                    items,
+                    commas: commas > 0,
+                    end: token.span.start,
+                });
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// A table literal holding only scalars, see [table_literal]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TableLiteral<'a> {
+    /// The column names, then the cells of every row
+    pub rows: Vec<Vec<&'a str>>,
+    /// Whether the cells are separated by commas rather than whitespace only
+    pub commas: bool,
+    /// Whether the rows are separated by commas
+    pub row_commas: bool,
+    /// The offset of the closing `]`
+    pub end: usize,
+}
+
+///
+/// # Splits a table literal into its rows
+///
+/// `rest` starts after the outer `[`, e.g. `[a b]; [1 2]]`. Returns `None` unless the column names
+/// and the rows are lists of scalars with as many cells as there are columns, all separated the
+/// same way, see [scalar_list].
+///
+pub fn table_literal(rest: &str) -> Option<TableLiteral<'_>> {
+    let skip = |offset: usize| {
+        rest[offset..]
+            .find(|c: char| !c.is_ascii_whitespace())
+            .map_or(rest.len(), |skipped| offset + skipped)
+    };
+    let mut offset = skip(0);
+    let mut rows: Vec<Vec<&str>> = Vec::new();
+    let mut commas = false;
+    let mut row_commas = 0;
+    loop {
+        match rest.as_bytes().get(offset)? {
+            b'[' => {
+                let row = scalar_list(&rest[offset + 1..])?;
+                if let Some(header) = rows.first() {
+                    if row.items.len() != header.len() || row.commas != commas {
+                        return None;
+                    }
+                }
+                commas = row.commas;
+                rows.push(row.items);
+                offset = skip(offset + 1 + row.end + 1);
+                if rows.len() == 1 {
+                    // the column names end with a semicolon
+                    offset = skip(offset + rest[offset..].strip_prefix(';').map(|_| 1)?);
+                }
+            }
+            b',' if rows.len() > 1 => {
+                row_commas += 1;
+                offset = skip(offset + 1);
+            }
+            b']' if rows.len() > 1 => {
+                let separated = row_commas == 0 || row_commas == rows.len() - 2;
+                return separated.then_some(TableLiteral {
+                    rows,
+                    commas,
+                    row_commas: row_commas > 0,
+                    end: offset,
+                });
+            }
+            _ => return None,
+        }
+    }
+}
+
+///
+/// # Measures the width of text in a terminal
+///
+/// Wide characters, e.g. CJK ideographs and most emoji, take two columns and combining marks
+/// none, every other character takes one.
+///
+pub fn display_width(text: &str) -> usize {
+    text.chars()
+        .map(|c| match u32::from(c) {
+            0x0300..=0x036F | 0x200B..=0x200F | 0xFE00..=0xFE0F => 0,
+            0x1100..=0x115F
+            | 0x2E80..=0x303E
+            | 0x3041..=0x33FF
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xA000..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x1F300..=0x1F64F
+            | 0x1F900..=0x1F9FF
+            | 0x20000..=0x3FFFD => 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+///
+/// # Lists the tokens of a script, one per line
+///
+/// Each line holds the span, the kind and the text of a token. Whitespace is made visible, spaces
+/// as `·`, tabs as `→` and newlines as `↵`, which helps when reporting layout bugs.
+///
+pub fn debug_tokens(nu: &str) -> String {
+    let tokens = tokenize(nu);
+    let spans: Vec<String> = tokens
+        .iter()
+        .map(|token| format!("{}..{}", token.span.start, token.span.end))
+        .collect();
+    let width = spans.iter().map(String::len).max().unwrap_or(0);
+
+    let mut output = String::new();
+    for (token, span) in tokens.iter().zip(spans) {
+        let text: String = token
+            .text(nu)
+            .chars()
+            .map(|c| match c {
+                ' ' => '·',
+                '\t' => '→',
+                '\n' => '↵',
+                c => c,
+            })
+            .collect();
+        let kind = format!("{:?}", token.kind);
+        output.push_str(&format!(
+            "{:width$}  {:12}  {}\n",
+            span,
+            kind,
+            text,
+            width = width
+        ));
+    }
+    output
+}
+
+/// Bytes that are part of bare words such as commands and flags
+///
+/// Like in nu, only ASCII characters separate words, so the bytes of other characters always belong
+/// to a word and multi-byte names are never split.
+pub fn is_word_byte(byte: u8) -> bool {
+    !byte.is_ascii_whitespace() && !b"[]{}()|;,:\"#".contains(&byte)
+}
+
+///
+/// # Checks whether a colon is the one of a Windows drive
+///
+/// `word` is the bare word before the colon and `next` the byte following it, as in `C:\Users`.
+/// Backslashes are plain characters of bare words, so the path is a single word.
+///
+pub fn is_drive_colon(word: &[u8], next: Option<u8>) -> bool {
+    matches!(word, [letter] if letter.is_ascii_alphabetic()) && next == Some(b'\\')
+}
+
+///
+/// # Checks whether the token at `index` is in command position
+///
+/// That is the first word of a statement, of a pipeline element or of a block. Keywords
+/// following `export` and the `else`/`catch` following a block are in command position too.
+///
+pub fn is_command_position(nu: &str, tokens: &[Token], index: usize) -> bool {
+    if tokens[index].kind != TokenKind::Word {
+        return false;
+    }
+    let external = tokens[index].text(nu).starts_with('^');
+    if !external && next_significant(tokens, index) == Some(TokenKind::Colon) {
+        // record keys are followed by a colon, external calls can be paths like ^C:\bin\ls
+        return false;
+    }
+
+    let previous = tokens[..index]
+        .iter()
+        .rposition(|token| !matches!(token.kind, TokenKind::Whitespace | TokenKind::Comment));
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return true,
+    };
+
+    let word = tokens[index].text(nu).to_lowercase();
+    match tokens[previous].kind {
+        TokenKind::Newline
+        | TokenKind::Semicolon
+        | TokenKind::Pipe
+        | TokenKind::OpenBrace
+        | TokenKind::OpenParen => true,
+        TokenKind::CloseBrace => word == "else" || word == "catch",
+        TokenKind::Word => {
+            tokens[previous].text(nu).eq_ignore_ascii_case("export")
+                && is_command_position(nu, tokens, previous)
+        }
+        _ => false,
+    }
+}
+
+/// Finds the end of a statement kept as written, fed one byte at a time
+#[derive(Debug, Default)]
+pub struct StatementEnd {
+    depth: usize,
+    quote: Option<u8>,
+    escaped: bool,
+    in_comment: bool,
+}
+
+impl StatementEnd {
+    ///
+    /// # Feeds the next byte
+    ///
+    /// Returns `true` if it ends the statement: a newline or a `;` outside of brackets and strings,
+    /// or the closing bracket of the enclosing block. The arguments of an attribute for instance
+    /// may span several lines inside of brackets.
+    ///
+    pub fn push(&mut self, byte: u8) -> bool {
+        if let Some(quote) = self.quote {
+            if self.escaped {
+                self.escaped = false;
+            } else if byte == b'\\' && quote == b'"' {
+                self.escaped = true;
+            } else if byte == quote {
+                self.quote = None;
+            }
+            return false;
+        }
+        if self.in_comment && byte != b'\n' {
+            return false;
+        }
+
+        match byte {
+            b'\n' => {
+                self.in_comment = false;
+                return self.depth == 0;
+            }
+            b';' | b']' | b'}' | b')' if self.depth == 0 => return true,
+            b'#' => self.in_comment = true,
+            b'"' | b'\'' | b'`' => self.quote = Some(byte),
+            b'[' | b'{' | b'(' => self.depth += 1,
+            b']' | b'}' | b')' => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
+        false
+    }
+}
+
+pub(crate) fn at_line_start(tokens: &[Token]) -> bool {
+    tokens
+        .iter()
+        .rev()
+        .find(|token| token.kind != TokenKind::Whitespace)
+        .is_none_or(|token| token.kind == TokenKind::Newline)
+}
+
+///
+/// # Finds a delimiter without its counterpart
+///
+/// Returns the offset of the first closing bracket, brace or parenthesis which closes nothing or
+/// another kind of delimiter, or else of the innermost one left open.
+///
+pub fn unbalanced_delimiter(nu: &str) -> Option<usize> {
+    let mut open = Vec::new();
+    for token in tokenize(nu) {
+        let closing = match token.kind {
+            TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen => {
+                open.push(token);
+                continue;
+            }
+            TokenKind::CloseBracket => TokenKind::OpenBracket,
+            TokenKind::CloseBrace => TokenKind::OpenBrace,
+            TokenKind::CloseParen => TokenKind::OpenParen,
+            _ => continue,
+        };
+        match open.pop() {
+            Some(opening) if opening.kind == closing => {}
+            _ => return Some(token.span.start),
+        }
+    }
+    open.last().map(|token| token.span.start)
+}
+
+///
+/// # Measures the raw string starting `rest`
+///
+/// Raw strings are written `r#'...'#`, with as many `#` around the quotes as needed for the
+/// content to hold `'#`. Returns `None` if `rest` does not start with a raw string, an unterminated
+/// one runs to the end of `rest`.
+///
+pub fn raw_string_len(rest: &[u8]) -> Option<usize> {
+    let hashes = rest.iter().skip(1).take_while(|b| **b == b'#').count();
+    if rest.first() != Some(&b'r') || hashes == 0 || rest.get(1 + hashes) != Some(&b'\'') {
+        return None;
+    }
+    let content = 2 + hashes;
+    let closer = (content..rest.len()).find(|&i| {
+        rest[i] == b'\''
+            && rest[i + 1..]
+                .iter()
+                .take(hashes)
+                .filter(|b| **b == b'#')
+                .count()
+                == hashes
+    });
+    Some(closer.map_or(rest.len(), |i| i + 1 + hashes))
+}
+
+fn scan_attribute(bytes: &[u8], start: usize) -> usize {
+    let mut state = StatementEnd::default();
+    bytes[start + 1..]
+        .iter()
+        .position(|b| state.push(*b))
+        .map_or(bytes.len(), |offset| start + 1 + offset)
+}
+
+fn next_significant(tokens: &[Token], index: usize) -> Option<TokenKind> {
+    tokens[index + 1..]
+        .iter()
+        .map(|token| token.kind)
+        .find(|kind| *kind != TokenKind::Whitespace)
+}
+
+/// The display width of the word or string starting `rest`, which cannot be split, interpolated
+/// strings are measured as they are rendered, see
+/// [rendered_width](crate::interpolation::rendered_width)
+pub fn token_width(rest: &[u8], interpolation_spacing: bool) -> usize {
+    let end = match rest {
+        [b'"', ..] => scan_string(rest, 0),
+        [b'$', b'"' | b'\'', ..] => {
+            let end = scan_interpolation(rest, 1);
+            let text = String::from_utf8_lossy(&rest[1..end]);
+            return 1 + crate::interpolation::rendered_width(&text, interpolation_spacing);
+        }
+        [] => 0,
+        [b'r', b'#', ..] if raw_string_len(rest).is_some() => raw_string_len(rest).unwrap_or(1),
+        _ => {
+            // colons followed by more of the word are part of it, `https://x`
+            let mut end = scan_word(rest, 0);
+            while rest.get(end) == Some(&b':')
+                && rest.get(end + 1).is_some_and(|&b| is_word_byte(b))
+            {
+                end = scan_word(rest, end + 1);
+            }
+            end
+        }
+    };
+    display_width(&String::from_utf8_lossy(&rest[..end]))
+}
+
+fn scan_while(bytes: &[u8], start: usize, predicate: impl Fn(u8) -> bool) -> usize {
+    bytes[start..]
+        .iter()
+        .position(|b| !predicate(*b))
+        .map_or(bytes.len(), |offset| start + offset)
+}
+
+/// Single quoted and backtick parts of a word, like in `'a b'`, `foo'a b'` or `` `a b` ``, are kept
+/// together
+fn scan_word(bytes: &[u8], start: usize) -> usize {
+    let mut end = start;
+    while end < bytes.len()
+        // `$'...'` is an interpolated string
+        && !(bytes[end] == b'\'' && &bytes[start..end] == b"$")
+        && (is_word_byte(bytes[end])
+            || bytes[end] == b':'
+                && is_drive_colon(&bytes[start..end], bytes.get(end + 1).copied()))
+    {
+        if matches!(bytes[end], b'\'' | b'`') {
+            let quote = bytes[end];
+            end = bytes[end + 1..]
+                .iter()
+                .position(|b| *b == quote)
+                .map_or(bytes.len() - 1, |offset| end + 1 + offset);
+        }
+        end += 1;
+    }
+    end
+}
+
+fn scan_string(bytes: &[u8], start: usize) -> usize {
+    let mut escaped = false;
+    for (i, b) in bytes.iter().enumerate().skip(start + 1) {
+        match b {
+            b'"' if !escaped => return i + 1,
+            b'\\' if !escaped => escaped = true,
+            _ => escaped = false,
+        }
+    }
+    bytes.len()
+}
+
+fn scan_interpolation(bytes: &[u8], start: usize) -> usize {
+    let mut state = Interpolation::new(bytes[start]);
+    bytes[start + 1..]
+        .iter()
+        .position(|b| state.push(*b))
+        .map_or(bytes.len(), |offset| start + 1 + offset + 1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds(nu: &str) -> Vec<TokenKind> {
+        tokenize(nu).iter().map(|token| token.kind).collect()
+    }
+
+    #[test]
+    fn tokens_cover_the_source() {
+        let nu = "ls | where size > 1kb # big\n{a: \"b\\\"c\"}";
+        let rebuilt: String = tokenize(nu).iter().map(|token| token.text(nu)).collect();
+        assert_eq!(nu, rebuilt);
+    }
+
+    #[test]
+    fn token_kinds() {
+        use TokenKind::*;
+        assert_eq!(
+            vec![OpenBrace, Word, Colon, Whitespace, String, Comma, Word, CloseBrace],
+            kinds("{a: \"x y\",b}")
+        );
+        assert_eq!(
+            vec![Word, Whitespace, Comment, Newline, Word],
+            kinds("ls # c\nps")
+        );
+    }
+
+    #[test]
+    fn interpolation_with_inner_quotes() {
+        use TokenKind::*;
+        assert_eq!(
+            vec![Word, String, Whitespace, Word],
+            kinds("$\"(\"a\" + 'b')\" ls")
+        );
+    }
+
+    #[test]
+    fn attributes() {
+        use TokenKind::*;
+        let nu = "@example \"adds\" {\n  add 1 2\n} # it's\n@category math\ndef add [] {}\necho @a";
+        let tokens = tokenize(nu);
+        let attributes: Vec<&str> = tokens
+            .iter()
+            .filter(|token| token.kind == Attribute)
+            .map(|token| token.text(nu))
+            .collect();
+        assert_eq!(
+            vec!["@example \"adds\" {\n  add 1 2\n} # it's", "@category math"],
+            attributes
+        );
+        assert_eq!(Word, tokens.last().unwrap().kind);
+        assert_eq!(
+            vec![OpenBrace, Newline, Attribute, CloseBrace],
+            kinds("{\n@a [1]}")
+        );
+    }
+
+    #[test]
+    fn single_quotes() {
+        use TokenKind::*;
+        assert_eq!(
+            vec![Word, Whitespace, Word],
+            kinds("echo 'a, b: \"c\" # d'")
+        );
+        assert_eq!(vec![Word], kinds("foo'a b'bar"));
+        assert_eq!(vec![Word], kinds("'abc"));
+        assert_eq!(vec![Word, Whitespace, Word], kinds("ls `my dir/[a], b`"));
+    }
+
+    #[test]
+    fn single_quoted_interpolation() {
+        use TokenKind::*;
+        assert_eq!(
+            vec![Word, String, Whitespace, Word],
+            kinds("$'a (\"'\" | str length)' b")
+        );
+    }
+
+    #[test]
+    fn unbalanced_delimiters() {
+        assert_eq!(None, unbalanced_delimiter("{a: [1, (2)]} | \"[\" ')'"));
+        assert_eq!(Some(5), unbalanced_delimiter("[1, 2)"));
+        assert_eq!(Some(0), unbalanced_delimiter(")"));
+        assert_eq!(Some(0), unbalanced_delimiter("{a: {b}"));
+        assert_eq!(Some(6), unbalanced_delimiter("{a: [b}"));
+    }
+
+    #[test]
+    fn top_level_statements() {
+        let nu = "let a = 1; ls\n\ndef f [] {\n  ls\n}\n  ls |\n  length # n\n";
+        let texts: Vec<&str> = statement_spans(nu)
+            .iter()
+            .map(|span| &nu[span.start..span.end])
+            .collect();
+        assert_eq!(
+            vec!["let a = 1; ls", "def f [] {\n  ls\n}", "ls |\n  length # n"],
+            texts
+        );
+    }
+
+    #[test]
+    fn raw_strings() {
+        use TokenKind::*;
+        assert_eq!(
+            vec![Word, Whitespace, Word],
+            kinds("echo r#'a # \"b\" [c'#")
+        );
+        assert_eq!(Some(12), raw_string_len(b"r##'it'#s'## x"));
+        assert_eq!(Some(6), raw_string_len(b"r#'abc"));
+        assert_eq!(None, raw_string_len(b"r#abc"));
+        assert_eq!(None, raw_string_len(b"rm"));
+    }
+
+    #[test]
+    fn unterminated_string() {
+        assert_eq!(vec![TokenKind::String], kinds("\"abc"));
+    }
+
+    #[test]
+    fn command_position() {
+        let nu = "if true { let x = {else: 1} } else { ls | get x }";
+        let tokens = tokenize(nu);
+        let commands: Vec<&str> = (0..tokens.len())
+            .filter(|i| is_command_position(nu, &tokens, *i))
+            .map(|i| tokens[i].text(nu))
+            .collect();
+        assert_eq!(vec!["if", "let", "else", "ls", "get"], commands);
+    }
+
+    #[test]
+    fn debug_output() {
+        assert_eq!(
+            "0..2   Word          ls\n2..4   Whitespace    ·→\n4..5   Pipe          |\n5..6   Newline       ↵\n6..11  Comment       #·a·b\n",
+            debug_tokens("ls \t|\n# a b")
+        );
+    }
+
+    #[test]
+    fn multiline_calls() {
+        assert!(is_multiline_call(" build\n  --release) | ignore"));
+        assert!(!is_multiline_call(" build --release\n  | ignore)"));
+        assert!(!is_multiline_call(" build {\n}) | ignore"));
+        assert!(!is_multiline_call(" build # why\n  --release)"));
+        assert!(!is_multiline_call(" build\n  --release"));
+    }
+
+    #[test]
+    fn single_line_widths() {
+        assert_eq!(
+            Some(20),
+            single_line_width(" $c { 1 } else {   2 }\nls", false)
+        );
+        assert_eq!(Some(9), single_line_width(" $c { 1 }; ls\n", false));
+        assert_eq!(Some(11), single_line_width("{|| [1, 2]}, b: 2}", false));
+        assert_eq!(None, single_line_width(" $c {\n  1\n}", false));
+        assert_eq!(None, single_line_width(" $c { 1 # one\n}", false));
+        assert_eq!(Some(15), single_line_width(" $c { $\"( $x )\" }", true));
+    }
+
+    #[test]
+    fn joined_line_widths() {
+        let nu = "ls \n  | get a\n| b\n| c";
+        assert_eq!(Some(3), joined_line_width(nu, |_| false, false));
+        assert_eq!(Some(10), joined_line_width(nu, |offset| offset == 3, false));
+        let joined = |offset| offset == 3 || offset == 13;
+        assert_eq!(Some(14), joined_line_width(nu, joined, false));
+        assert_eq!(None, joined_line_width("(ls\n| b)", |_| false, false));
+        assert_eq!(
+            Some(8),
+            joined_line_width("(ls\n| b)", |offset| offset == 3, false)
+        );
+    }
+
+    #[test]
+    fn joined_widths() {
+        assert_eq!(Some(4), joined_width("1,2] | length"));
+        assert_eq!(
+            Some(37),
+            joined_width("\n  string -> table,\n  nothing -> list<any>\n]")
+        );
+        assert_eq!(Some(16), joined_width("x:int, y: [a b]]"));
+        assert_eq!(None, joined_width("\n  x: int # the x\n]"));
+        assert_eq!(None, joined_width("x: int"));
+    }
+
+    #[test]
+    fn pipeline_breaks() {
+        let nu = "ls |\n get a\n| b # c\n| d\nls | each {|x|\n$x\n}\n| e\n{||\nls}";
+        assert_eq!(vec![4, 11], pipeline_line_breaks(nu));
+        assert_eq!(vec![5, 12], pipeline_line_breaks("(ls |\nget a)\n| b"));
+    }
+
+    #[test]
+    fn stages() {
+        assert_eq!(15, stage_end("each {|x| $x } | sort"));
+        assert_eq!(4, stage_end("sort"));
+        assert_eq!(4, stage_end("sort\nls | sort"));
+        assert_eq!(5, stage_end("sort } | each"));
+        assert!(is_closure_pipeline("|x| $x | get a } | sort"));
+        assert!(!is_closure_pipeline("|x| $x.a } | sort"));
+    }
+
+    #[test]
+    fn braces() {
+        let nu =
+            "let r = {a: 1} | if $x { b: 2 } else {} | each {|x| $x } | do { ls }\n{\n  c: 3\n}";
+        let kinds = brace_kinds(nu);
+        let expected = vec![
+            (8, BraceKind::Record),
+            (23, BraceKind::Block),
+            (37, BraceKind::Block),
+            (47, BraceKind::Closure),
+            (62, BraceKind::Block),
+            (69, BraceKind::Record),
+        ];
+        assert_eq!(expected, kinds);
+
+        let nu = "{a: 1 b: {c: 2 d: 3}, e: [f: 4]\ng: 5 'h': 6}\nif $x { i: 7 j: 8 }";
+        assert_eq!(vec![6, 15, 37], record_entries(nu));
+    }
+
+    #[test]
+    fn trailing_commas() {
+        assert_eq!(vec![5], missing_trailing_commas("[1, 2]"));
+        assert_eq!(vec![15, 16], missing_trailing_commas("{a: 1, b: [2,\n3]}"));
+        assert!(
+            missing_trailing_commas("[1, 2,] [1 2] (1, 2) { ls; ps } [[a]; [1]] {}").is_empty()
+        );
+        assert_eq!(
+            1,
+            missing_trailing_commas("def f [a, b] { each {|x, y| $x } }").len()
+        );
+    }
+
+    #[test]
+    fn scalar_lists() {
+        let list = ScalarList {
+            items: vec!["1", "\"a, b\"", "c"],
+            commas: true,
+            end: 15,
+        };
+        assert_eq!(Some(list), scalar_list("1, \"a, b\"\n,  c ] | x"));
+        assert_eq!(None, scalar_list("1, 2,]"));
+        assert!(scalar_list("1 2 3]").is_some_and(|list| !list.commas));
+        assert_eq!(None, scalar_list("1, 2 3]"));
+        assert_eq!(None, scalar_list("1, [2]]"));
+        assert_eq!(None, scalar_list("a:b]"));
+        assert_eq!(None, scalar_list("$\"(1)\"]"));
+        assert_eq!(None, scalar_list("1 # one\n]"));
+        assert_eq!(None, scalar_list("]"));
+    }
+
+    #[test]
+    fn table_literals() {
+        let table = TableLiteral {
+            rows: vec![vec!["name", "size"], vec!["a", "1"], vec!["\"b c\"", "2"]],
+            commas: false,
+            row_commas: false,
+            end: 33,
+        };
+        assert_eq!(
+            Some(table),
+            table_literal("[name size];\n  [a 1]\n  [\"b c\" 2]\n] | x")
+        );
+        let table = table_literal("[a, b]; [1, 2], [3, 4]]").unwrap();
+        assert!(table.commas && table.row_commas);
+        assert_eq!(None, table_literal("[a b]; [1 2], [3 4] [5 6]]"));
+        assert_eq!(None, table_literal("[a b]; [1]]"));
+        assert_eq!(None, table_literal("[a b]; [1, 2]]"));
+        assert_eq!(None, table_literal("[a b] [1 2]]"));
+        assert_eq!(None, table_literal("[a b];]"));
+        assert_eq!(None, table_literal("[a b]; [1 2] # one\n]"));
+        assert_eq!(None, table_literal("[1 2] [3 4]]"));
+    }
+
+    #[test]
+    fn flat_collections() {
+        assert!(is_flat_collection(b'[', "1, 2, [3 4]] | length"));
+        assert!(is_flat_collection(
+            b'{',
+            "a: 1, \"b c\": [2 {d: ($x + 1)}]}"
+        ));
+        assert!(is_flat_collection(b'{', "a:1}"));
+        assert!(is_flat_collection(b'{', "}"));
+        assert!(!is_flat_collection(b'{', "ls }"));
+        assert!(!is_flat_collection(b'{', "|x| $x }"));
+        assert!(!is_flat_collection(b'[', "{|x| $x}]"));
+        assert!(!is_flat_collection(b'[', "1 {ls}]"));
+        assert!(!is_flat_collection(b'[', "1 2"));
+    }
+
+    #[test]
+    fn windows_paths() {
+        let nu = "cd C:\\Users\\me; ls D:\\ {a:\\b}";
+        let words: Vec<&str> = tokenize(nu)
+            .iter()
+            .filter(|token| token.kind == TokenKind::Word)
+            .map(|token| token.text(nu))
+            .collect();
+        assert_eq!(vec!["cd", "C:\\Users\\me", "ls", "D:\\", "a:\\b"], words);
+    }
+
+    #[test]
+    fn non_ascii_words() {
+        let nu = "grüße --größe 3 | длина; let 名前 = \"値\"";
+        let words: Vec<&str> = tokenize(nu)
+            .iter()
+            .filter(|token| token.kind == TokenKind::Word)
+            .map(|token| token.text(nu))
+            .collect();
+        assert_eq!(
+            vec!["grüße", "--größe", "3", "длина", "let", "名前", "="],
+            words
+        );
+    }
+
+    #[test]
+    fn token_widths() {
+        assert_eq!(18, token_width(b"https://nushell.sh | get x", false));
+        assert_eq!(5, token_width(b"\"a b\" c", false));
+        assert_eq!(9, token_width(b"$\"a(\"b\")\" c", false));
+        assert_eq!(3, token_width(b"C:\\ d", false));
+        assert_eq!(1, token_width(b"a: b", false));
+        assert_eq!(8, token_width(b"r#'a b'# c", false));
+        assert_eq!(10, token_width(b"$\"a ( $x )\nb\" c", false));
+        assert_eq!(9, token_width(b"$\"a ( $x )\" c", true));
+    }
+
+    #[test]
+    fn display_widths() {
+        assert_eq!(5, display_width("grüße"));
+        assert_eq!(4, display_width("名前"));
+        assert_eq!(1, display_width("e\u{301}"));
+        assert_eq!(Some(10), single_line_width("[名前, 値]\n", false));
+    }
+}
@@ -0,0 +1,3886 @@
+//!
+//! nufmt is a library for formatting nu.
+//!
+//! It does not do anything more than that, which makes it so fast.
+//!
+//! This is the core of the `nufmt` crate, which re-exports it. Without features it has no
+//! dependency, and embedders pick what they need: `config-file` reads `nufmt.toml` files with
+//! `config::load_config` (serde and toml), `daemon` serves format requests over TCP (serde and
+//! serde_json).
+//!
+//! [format()], [Config], [Error] and [Outcome] are the stable API, new options and errors do not
+//! break code using them: the config is built with [ConfigBuilder] and the types which may grow
+//! are `#[non_exhaustive]`. The other entry points and modules serve the `nufmt` command and
+//! editor integrations, and may change with them.
+
+pub mod cache;
+pub mod capabilities;
+pub mod compact;
+pub mod config;
+#[cfg(feature = "daemon")]
+pub mod daemon;
+pub mod diff;
+mod directive;
+pub mod documents;
+pub mod file;
+pub mod folding;
+pub mod generated;
+pub mod header;
+pub mod hints;
+pub mod ignore;
+pub mod interpolation;
+pub mod invariants;
+pub mod lexer;
+pub mod lint;
+pub mod migrate;
+pub mod nu_module;
+pub mod range;
+pub mod reproducible;
+mod signature;
+pub mod stats;
+pub mod style_diff;
+pub mod support;
+pub mod tree;
+
+pub use capabilities::{capabilities, Capabilities};
+pub use compact::format_nu_compact;
+pub use config::{Config, ConfigBuilder};
+pub use file::{format_file, format_tree};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::{BufReader, BufWriter, Read, Write};
+
+///
+/// Set the indentation used for the formatting.
+///
+/// Note: It is *not* recommended to set indentation to anything oder than some spaces or some tabs,
+/// but nothing is stopping you from doing that.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum Indentation<'a> {
+    /// Use the default indentation, which is two spaces
+    #[default]
+    Default,
+    /// Use a custom indentation String
+    #[deprecated(note = "use `Spaces` or `Tabs`, which do not borrow the indentation")]
+    Custom(&'a str),
+    /// Use this many spaces
+    Spaces(u8),
+    /// Use this many tabs
+    Tabs(u8),
+}
+
+impl<'a> Indentation<'a> {
+    /// The indentation written `indent`, owned if it is only spaces or only tabs
+    #[allow(deprecated)]
+    pub fn of(indent: &'a str) -> Indentation<'a> {
+        let count = |c: char| match u8::try_from(indent.len()) {
+            Ok(count) if !indent.is_empty() && indent.chars().all(|other| other == c) => {
+                Some(count)
+            }
+            _ => None,
+        };
+        match (count(' '), count('\t')) {
+            (Some(spaces), _) => Indentation::Spaces(spaces),
+            (_, Some(tabs)) => Indentation::Tabs(tabs),
+            _ => Indentation::Custom(indent),
+        }
+    }
+
+    /// The width of one level of indentation, a tab counting as one
+    #[allow(deprecated)]
+    fn width(self) -> usize {
+        match self {
+            Indentation::Default => 2,
+            Indentation::Custom(indent) => indent.len(),
+            Indentation::Spaces(spaces) => spaces.into(),
+            Indentation::Tabs(tabs) => tabs.into(),
+        }
+    }
+
+    /// Writes one level of indentation
+    #[allow(deprecated)]
+    fn write<W: Write>(self, writer: &mut W) -> std::io::Result<()> {
+        match self {
+            Indentation::Default => writer.write_all(b"  "),
+            Indentation::Custom(indent) => writer.write_all(indent.as_bytes()),
+            Indentation::Spaces(spaces) => writer.write_all(" ".repeat(spaces.into()).as_bytes()),
+            Indentation::Tabs(tabs) => writer.write_all("\t".repeat(tabs.into()).as_bytes()),
+        }
+    }
+}
+
+/// Where in the nu an [Error] happened
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub struct Location {
+    /// Byte offset in the nu
+    pub offset: usize,
+    /// 1-based line
+    pub line: usize,
+    /// 1-based column, in characters
+    pub column: usize,
+}
+
+impl Location {
+    /// The location of a byte `offset` of `nu`
+    pub fn new(nu: &str, offset: usize) -> Self {
+        let (line, column) = lint::line_column(nu, offset);
+        Location {
+            offset,
+            line,
+            column,
+        }
+    }
+}
+
+/// Why formatting failed, see [try_format_nu] and [format_nu_buffered]
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Reading the nu or writing the formatted nu failed
+    Io(std::io::Error),
+    /// The formatted nu is not valid UTF-8
+    InvalidUtf8(std::string::FromUtf8Error),
+    /// A bracket, brace or parenthesis is not closed, or closes nothing
+    UnbalancedDelimiter(Location),
+    /// A config file could not be read, see [config]
+    InvalidConfig {
+        path: std::path::PathBuf,
+        message: String,
+    },
+    /// Formatting panicked, which is a bug of nufmt, see [file::catch_panic]
+    Panicked(String),
+    /// The nu is larger than the [max input size](ConfigBuilder::max_input_size) of the config
+    InputTooLarge { size: usize, limit: usize },
+}
+
+impl Error {
+    /// Where in the nu the error is, if it is about the nu itself
+    pub fn location(&self) -> Option<Location> {
+        match self {
+            Error::UnbalancedDelimiter(location) => Some(*location),
+            Error::Io(_)
+            | Error::InvalidUtf8(_)
+            | Error::InvalidConfig { .. }
+            | Error::Panicked(_)
+            | Error::InputTooLarge { .. } => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "could not write the formatted nu: {}", err),
+            Error::InvalidUtf8(err) => write!(f, "the formatted nu is not UTF-8: {}", err),
+            Error::UnbalancedDelimiter(location) => write!(
+                f,
+                "unbalanced delimiter at line {}, column {}",
+                location.line, location.column
+            ),
+            Error::InvalidConfig { path, message } => {
+                write!(f, "invalid config {}: {}", path.display(), message)
+            }
+            Error::Panicked(message) => write!(f, "nufmt panicked, please report it: {}", message),
+            Error::InputTooLarge { size, limit } => write!(
+                f,
+                "the nu is {} bytes, more than the limit of {} bytes, \
+                 format it from a reader to a writer with format_nu_buffered instead",
+                size, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::InvalidUtf8(err) => Some(err),
+            Error::UnbalancedDelimiter(_)
+            | Error::InvalidConfig { .. }
+            | Error::Panicked(_)
+            | Error::InputTooLarge { .. } => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<std::string::FromUtf8Error> for Error {
+    fn from(err: std::string::FromUtf8Error) -> Self {
+        Error::InvalidUtf8(err)
+    }
+}
+
+/// What [format()] did
+#[derive(Debug, Clone, Default, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub struct Outcome {
+    /// The formatted nu
+    pub formatted: String,
+    /// Whether formatting changed the nu
+    pub changed: bool,
+}
+
+///
+/// # Formats nu with a [Config]
+///
+/// The entry point of the stable API. As with [try_format_nu_with_config], scripts with
+/// unbalanced delimiters and nu larger than the max input size of the config are refused.
+///
+pub fn format(nu: &str, config: &Config) -> Result<Outcome, Error> {
+    let formatted = try_format_nu_with_config(nu, config)?;
+    let changed = formatted != nu;
+    Ok(Outcome { formatted, changed })
+}
+
+///
+/// # Formats a nu string
+///
+/// The indentation can be set to any value using [Indentation](nufmt::Indentation)
+/// The default value is two spaces
+/// The default indentation is faster than a custom one
+///
+/// Panics where [try_format_nu] fails, unbalanced delimiters excepted, they are formatted anyway.
+///
+pub fn format_nu(nu: &str, indentation: Indentation) -> String {
+    let mut reader = BufReader::new(nu.as_bytes());
+    let mut writer = BufWriter::new(Vec::new());
+
+    format_nu_buffered(&mut reader, &mut writer, indentation).unwrap();
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
+
+///
+/// # Formats a nu string, failing instead of panicking
+///
+/// Unlike [format_nu], scripts with unbalanced delimiters are refused, the formatter would
+/// indent everything after them wrongly.
+///
+pub fn try_format_nu(nu: &str, indentation: Indentation) -> Result<String, Error> {
+    if let Some(offset) = lexer::unbalanced_delimiter(nu) {
+        return Err(Error::UnbalancedDelimiter(Location::new(nu, offset)));
+    }
+    let mut reader = BufReader::new(nu.as_bytes());
+    let mut writer = BufWriter::new(Vec::new());
+
+    format_buffered(&mut reader, &mut writer, &config(indentation), false)?;
+    let formatted = writer.into_inner().map_err(|err| err.into_error())?;
+    Ok(String::from_utf8(formatted)?)
+}
+
+///
+/// # Formats a nu expression or snippet
+///
+/// Unlike [format_nu_with_config], the result is meant to be embedded in other code, e.g. by an
+/// editor or a REPL, so it neither starts nor ends with a newline, whatever the snippet is
+/// surrounded with.
+///
+pub fn format_expression(nu: &str, config: &Config) -> String {
+    format_nu_with_config(nu.trim(), config)
+        .trim_end()
+        .to_string()
+}
+
+///
+/// # Normalizes the spacing of REPL history entries
+///
+/// Every entry is formatted on its own and stays on one line, blocks and collections are not
+/// expanded, e.g. to pretty-print the history or a session export. Entries spanning several lines
+/// are returned as they are.
+///
+pub fn format_history(entries: &[&str]) -> Vec<String> {
+    entries
+        .iter()
+        .map(|entry| {
+            if entry.contains('\n') {
+                return entry.to_string();
+            }
+            let mut reader = BufReader::new(entry.trim().as_bytes());
+            let mut writer = BufWriter::new(Vec::new());
+            format_buffered(&mut reader, &mut writer, &Config::default(), true).unwrap();
+            String::from_utf8(writer.into_inner().unwrap()).unwrap()
+        })
+        .collect()
+}
+
+///
+/// # Formats a nu string
+///
+/// The indentation can be set to any value using [Indentation](nufmt::Indentation)
+/// The default value is two spaces
+/// The default indentation is faster than a custom one
+///
+pub fn format_nu_buffered<R, W>(
+    reader: &mut BufReader<R>,
+    writer: &mut BufWriter<W>,
+    indentation: Indentation,
+) -> Result<(), Error>
+where
+    R: Read,
+    W: Write,
+{
+    Ok(format_buffered(
+        reader,
+        writer,
+        &config(indentation),
+        false,
+    )?)
+}
+
+///
+/// # Formats a nu string with a [Config]
+///
+/// The options of the config apply to the whole nu, the directives written in it still override
+/// them from where they are written.
+///
+pub fn format_nu_with_config(nu: &str, config: &Config) -> String {
+    let mut reader = BufReader::new(nu.as_bytes());
+    let mut writer = BufWriter::new(Vec::new());
+
+    format_buffered(&mut reader, &mut writer, config, false).unwrap();
+    String::from_utf8(writer.into_inner().unwrap()).unwrap()
+}
+
+///
+/// # Formats a nu string with a [Config], failing instead of panicking
+///
+/// As [try_format_nu], scripts with unbalanced delimiters are refused, and so is nu larger than
+/// the [max input size](ConfigBuilder::max_input_size) of the config.
+///
+pub fn try_format_nu_with_config(nu: &str, config: &Config) -> Result<String, Error> {
+    check_input_size(nu, config)?;
+    if let Some(offset) = lexer::unbalanced_delimiter(nu) {
+        return Err(Error::UnbalancedDelimiter(Location::new(nu, offset)));
+    }
+    let mut reader = BufReader::new(nu.as_bytes());
+    let mut writer = BufWriter::new(Vec::new());
+
+    format_buffered(&mut reader, &mut writer, config, false)?;
+    let formatted = writer.into_inner().map_err(|err| err.into_error())?;
+    Ok(String::from_utf8(formatted)?)
+}
+
+/// Fails if nu is larger than the max input size of a config
+pub(crate) fn check_input_size(nu: &str, config: &Config) -> Result<(), Error> {
+    match config.max_input_size {
+        Some(limit) if nu.len() > limit => Err(Error::InputTooLarge {
+            size: nu.len(),
+            limit,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Whether nu is already formatted with a config, i.e. formatting it would not change it
+pub fn check_nu(nu: &str, config: &Config) -> bool {
+    format_nu_with_config(nu, config) == nu
+}
+
+///
+/// # The changes formatting would make, as a unified diff
+///
+/// `None` if nu is already formatted. The file headers name `stdin.nu`, see
+/// [unified_diff](diff::unified_diff) to name another file.
+///
+pub fn diff_nu(original: &str, config: &Config) -> Option<String> {
+    diff::unified_diff(
+        original,
+        &format_nu_with_config(original, config),
+        "stdin.nu",
+    )
+}
+
+/// The config of the entry points only taking an indentation
+fn config(indentation: Indentation) -> Config {
+    Config::builder().indentation(indentation).build()
+}
+
+/// Formats nu, keeping every block and collection on its line if `one_line`
+fn format_buffered<R, W>(
+    reader: &mut BufReader<R>,
+    writer: &mut BufWriter<W>,
+    config: &Config,
+    one_line: bool,
+) -> std::io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let indentation = config.indentation;
+    let mut escaped = false;
+    let mut in_string = false;
+    let mut indent_level = 0usize;
+    let mut newline_requested = false; // invalidated if next character is ] or }
+    let mut in_comment = false;
+    let mut comment = Vec::new(); // text of the current comment, checked for directives
+    let mut newline_forced = false; // the requested newline ends a line, kept before ] or }
+    let mut overrides = vec![(0, config.options.clone())]; // (block level, options)
+    let mut word = Vec::new(); // the bare word being read
+    let mut word_quote: Option<u8> = None; // quote of the part of the word kept as written
+    let mut command: Option<String> = None; // the first word of the current pipeline element
+    let mut last_word = String::new(); // the previous bare word, e.g. a flag
+    let mut embedded_code: Option<Vec<u8>> = None; // content of a string holding nu code
+    let mut glued_depth = 0usize; // nesting of brackets glued to an external command argument
+    let mut line_start = true; // nothing but indentation written on the current line
+    let mut space_requested = false; // collapsed whitespace, written before the next token
+    let mut interpolation: Option<interpolation::Interpolation> = None; // inside $"..."
+    let mut interpolated: Option<Vec<u8>> = None; // content of $"..." with spacing to normalize
+    let mut after_open_brace = false; // the previous token opened a block, maybe a closure
+    let mut closure_params: Option<Vec<u8>> = None; // parameters of a closure, between the pipes
+    let mut word_prefix = Prefix::Nothing; // written with the word once it is finished
+    let mut delimiters: Vec<(u8, bool)> = Vec::new(); // open brackets, whether they indent
+    let mut verbatim: Option<(lexer::StatementEnd, Vec<u8>)> = None; // line kept as written
+    let mut pattern: Option<usize> = None; // delimiters open in a destructuring pattern
+    let mut after_inline_open = false; // the previous token opened a pattern's bracket
+    let mut match_pending = false; // a match whose arms have not been opened yet
+    let mut match_arms: Vec<(usize, bool)> = Vec::new(); // (delimiters open, in a pattern)
+    let mut signature_pending = false; // a def whose parameters have not been read yet
+    let mut signature: Option<usize> = None; // number of delimiters open in a def's parameters
+    let mut signature_types = false; // the parameters of a def are closed, its types may follow
+    let mut type_depth = 0usize; // nesting of type parameters like record<a: int, b: string>
+    let mut pending: Option<(bool, String)> = None; // pipe or operator maybe moved to the next line
+    let mut after_comment = false; // the last line ended with a comment
+    let mut last_significant = 0u8; // the last byte which is not whitespace
+    let mut previous_item: Option<bool> = None; // whether the last top level statement is an item
+    let mut previous_definition = false; // the last top level statement defines a command or alias
+    let mut word_statement = false; // the word starts a top level statement
+    let mut word_definition_lines: Option<usize> = None; // blank lines above the word's definition
+    let mut grouped_call: Option<usize> = None; // delimiters open in a call with grouped flags
+    let mut skip_until = 0usize; // the bytes before are dropped from the output
+    let mut filled: Option<(Vec<String>, bool)> = None; // items of a list, whether with commas
+    let mut table: Option<lexer::TableLiteral> = None; // rows of a table literal
+    let mut compact: Option<usize> = None; // delimiters open at a value kept on one line
+    let mut flat: Option<usize> = None; // delimiters open at a list or record kept on one line
+    let mut word_after_colon = false; // the word follows the colon of a record key
+    let mut broken_pipelines: Vec<usize> = Vec::new(); // delimiters open at pipelines broken by stage
+    let mut pipeline_indents: Vec<usize> = Vec::new(); // delimiters open at pipelines indented once
+
+    // read upfront, some rules look ahead, e.g. to find out whether a call spans several lines
+    let mut input = Vec::new();
+    reader.read_to_end(&mut input)?;
+    let writer = &mut Columns {
+        inner: writer,
+        line: Vec::new(),
+        held: None,
+    };
+    // validated once, the lookaheads read the text from a position on, or nothing if it is not
+    // UTF-8
+    let text = std::str::from_utf8(&input).unwrap_or_default();
+    let aligned = aligned_tokens(&input);
+    let missing_commas = lexer::missing_trailing_commas(text);
+    let pipeline_breaks = lexer::pipeline_line_breaks(text);
+    let brace_kinds = lexer::brace_kinds(text);
+    let record_entries = lexer::record_entries(text);
+
+    for (position, &char) in input.iter().enumerate() {
+        if position < skip_until {
+            continue;
+        }
+        // if we're in a comment, ignore and write everything until a newline
+        if in_comment {
+            if char == b'\n' {
+                in_comment = false;
+                after_comment = true;
+                line_start = true;
+                newline_requested = true;
+                newline_forced = true;
+
+                command = None;
+                last_word.clear();
+                // like any other newline, the one ending a comment ends a pipeline broken by stage
+                // and a value kept on one line
+                if broken_pipelines.last() == Some(&delimiters.len()) {
+                    broken_pipelines.pop();
+                }
+                if compact == Some(delimiters.len()) {
+                    compact = None;
+                }
+
+                let text = String::from_utf8_lossy(&comment);
+                if let Some(directive) = directive::parse_directive(&text) {
+                    let mut options = active_options(&overrides).clone();
+                    for (key, value) in directive {
+                        options.apply(key, value);
+                    }
+                    overrides.push((indent_level, options));
+                }
+                comment.clear();
+            } else {
+                writer.write_all(&[char])?;
+                comment.push(char);
+            }
+            continue;
+        }
+        if let Some(state) = interpolation.as_mut() {
+            if state.push(char) {
+                if let Some(content) = interpolated.take() {
+                    let raw = String::from_utf8_lossy(&content);
+                    let normalized = interpolation::normalize_subexpressions(&raw, state.quote());
+                    writer.write_all(normalized.as_bytes())?;
+                }
+                writer.write_all(&[char])?;
+                interpolation = None;
+            } else if let Some(content) = interpolated.as_mut() {
+                content.push(char);
+            } else {
+                writer.write_all(&[char])?;
+            }
+            continue;
+        }
+        if let Some((state, text)) = verbatim.as_mut() {
+            if !state.push(char) {
+                text.push(char);
+                continue;
+            }
+            // the end of the statement is formatted as usual
+            writer.write_all(String::from_utf8_lossy(text).trim_end().as_bytes())?;
+            verbatim = None;
+        }
+        if let Some(quote) = word_quote {
+            word.push(char);
+            word_quote = word_quote.filter(|_| char != quote);
+            continue;
+        }
+        if let Some(params) = closure_params.as_mut() {
+            if char == b'|' {
+                let params = String::from_utf8_lossy(params);
+                writer.write_all(params.trim().as_bytes())?;
+                writer.write_all(&[char])?;
+                closure_params = None;
+                // the body of a compact closure stays on the line of its parameters
+                newline_requested = compact.is_none() && !one_line;
+                space_requested = compact.is_some() || one_line;
+            } else {
+                params.push(char);
+            }
+            continue;
+        }
+        if char == b'#' && word == b"r" {
+            if let Some(len) = lexer::raw_string_len(&input[position - 1..]) {
+                // raw strings are kept as written, `r#'a 'quoted' # string'#`
+                skip_until = position - 1 + len;
+                word.extend_from_slice(&input[position..skip_until]);
+                continue;
+            }
+        }
+        if in_string {
+            let mut escape_here = false;
+            match char {
+                b'"' if !escaped => in_string = false,
+                b'\\' if !escaped => escape_here = true,
+                _ => {}
+            }
+            match embedded_code.as_mut() {
+                Some(code) if in_string => code.push(char),
+                Some(code) => {
+                    let raw = String::from_utf8_lossy(code);
+                    match hints::normalize_embedded_code(&raw) {
+                        Some(normalized) => writer.write_all(normalized.as_bytes())?,
+                        None => writer.write_all(code)?,
+                    }
+                    writer.write_all(&[char])?;
+                    embedded_code = None;
+                }
+                None => writer.write_all(&[char])?,
+            }
+            escaped = escape_here;
+        } else if char == b'@' && line_start && word.is_empty() {
+            // attributes are kept as written, on their own line above their def
+            let definition_lines = match delimiters.is_empty() {
+                true => {
+                    definition_blank_lines(&input, position, &mut previous_definition, &overrides)
+                }
+                false => None,
+            };
+            if newline_requested {
+                let mut prefix = Prefix::Newline {
+                    level: indent_level,
+                    continuation: None,
+                    after_comment,
+                    blank_lines: blank_lines_before(&input, position, &overrides),
+                };
+                // attributes stay with their def, like comments
+                if delimiters.is_empty()
+                    && active_options(&overrides).item_spacing
+                    && leading_blank_line(&input, position, &mut previous_item)
+                {
+                    prefix.add_blank_line();
+                }
+                if let Some(lines) = definition_lines {
+                    prefix.set_blank_lines(lines);
+                }
+                write_prefix(writer, &prefix, &overrides, indentation)?;
+            }
+            verbatim = Some((lexer::StatementEnd::default(), vec![char]));
+            newline_requested = false;
+            newline_forced = false;
+            space_requested = false;
+        } else if char == b':' && lexer::is_drive_colon(&word, input.get(position + 1).copied()) {
+            // Windows paths are bare words, `C:\Users`
+            word.push(char);
+        } else if char == b'|' && after_open_brace {
+            let implicit = active_options(&overrides).closure_style
+                == Some(directive::ClosureStyle::Implicit)
+                && input.get(position + 1) == Some(&b'|')
+                && !matches!(
+                    next_significant_byte(&input, position + 2),
+                    None | Some(b'}')
+                )
+                // compact closures are only told apart from records by their parameters
+                && compact.is_none_or(|level| level + 1 != delimiters.len());
+            if implicit {
+                // `{|| ls}` is written `{ ls }`, but `{||}` is no empty record
+                skip_until = position + 2;
+            } else {
+                // closure parameters stay on the line of their brace
+                writer.write_all(&[char])?;
+                closure_params = Some(Vec::new());
+                newline_requested = false;
+            }
+            after_open_brace = false;
+            command = None;
+            last_word.clear();
+        } else {
+            let after_word = !word.is_empty();
+            // arguments of external commands are passed on as they are written, including
+            // brackets glued to them like in jsonpath={.items[*]}
+            let external = match &command {
+                Some(command) => command.starts_with('^'),
+                None => word.first() == Some(&b'^'),
+            };
+            // `$'...'` is interpolated, not a single quoted part of the `$` word
+            let word_byte = lexer::is_word_byte(char) && !(char == b'\'' && word == b"$");
+            if word_byte {
+                word.push(char);
+                // single quoted and backtick parts, `'a b'` or `` `my dir` ``
+                word_quote = Some(char).filter(|char| matches!(char, b'\'' | b'`'));
+            } else if !word.is_empty() {
+                let finished = String::from_utf8_lossy(&word).into_owned();
+                let mut prefix = std::mem::replace(&mut word_prefix, Prefix::Nothing);
+                if word_statement {
+                    if active_options(&overrides).item_spacing {
+                        let item = hints::ITEM_KEYWORDS.contains(&finished.as_str());
+                        let blank_line =
+                            previous_item == Some(true) || (item && previous_item.is_some());
+                        if blank_line {
+                            prefix.add_blank_line();
+                        }
+                        previous_item = Some(item);
+                    }
+                    if let Some(lines) = word_definition_lines.take() {
+                        prefix.set_blank_lines(lines);
+                    }
+                    word_statement = false;
+                }
+                // newlines only continue expressions inside parentheses
+                let operator = matches!(delimiters.last(), Some((b'(', _)))
+                    && hints::CONTINUATION_OPERATORS.contains(&finished.as_str());
+                let arrow =
+                    finished == "=>" && match_arms.last() == Some(&(delimiters.len(), true));
+                if arrow {
+                    match_arms.last_mut().unwrap().1 = false;
+                }
+                match (
+                    operator,
+                    active_options(&overrides).operator_placement,
+                    prefix,
+                ) {
+                    (true, Some(directive::Placement::Leading), Prefix::Space) => {
+                        pending = Some((true, finished.clone()));
+                    }
+                    (true, Some(directive::Placement::Leading), Prefix::Nothing) => {
+                        pending = Some((false, finished.clone()));
+                    }
+                    (
+                        true,
+                        Some(directive::Placement::Trailing),
+                        Prefix::Newline {
+                            continuation: None,
+                            after_comment: false,
+                            ..
+                        },
+                    ) => {
+                        writer.write_all(b" ")?;
+                        writer.write_all(&word)?;
+                        newline_requested = true;
+                    }
+                    (
+                        _,
+                        _,
+                        Prefix::Newline {
+                            after_comment: false,
+                            ..
+                        },
+                    ) if arrow => {
+                        // `=>` stays on the line of the last pattern token
+                        writer.write_all(b" =>")?;
+                    }
+                    (_, _, mut prefix) => {
+                        let in_parameters = signature == Some(delimiters.len());
+                        if in_parameters && finished.starts_with('?') {
+                            // optional parameters, `x?: int`
+                            prefix = Prefix::Nothing;
+                        }
+                        write_prefix(writer, &prefix, &overrides, indentation)?;
+                        if in_parameters {
+                            // default values, `x = 5`
+                            let (parameter, spaced) = signature::parameter(&finished);
+                            writer.write_all(parameter.as_bytes())?;
+                            space_requested |= spaced;
+                        } else {
+                            writer.write_all(&word)?;
+                        }
+                    }
+                }
+                let statement_start =
+                    matches!(command.as_deref(), None | Some("export" | "overlay"));
+                if statement_start && hints::PARSE_TIME_KEYWORDS.contains(&finished.as_str()) {
+                    // the arguments of parse time keywords are kept as written
+                    let mut state = lexer::StatementEnd::default();
+                    if !state.push(char) {
+                        verbatim = Some((state, vec![char]));
+                        command = Some(finished);
+                        word.clear();
+                        continue;
+                    }
+                }
+                if command.is_none() && finished == "match" {
+                    match_pending = true;
+                }
+                let defines = matches!(command.as_deref(), None | Some("export"));
+                if defines && matches!(finished.as_str(), "def" | "extern") {
+                    signature_pending = true;
+                }
+                // `let x = if $c { 1 } else { 2 }` is a value, it stays on one line if written so
+                let assigned =
+                    last_word == "=" && matches!(command.as_deref(), Some("let" | "mut" | "const"));
+                let record_value = word_after_colon && matches!(delimiters.last(), Some((b'{', _)));
+                // record entries go on their own line, `key: if`
+                let before = if record_value {
+                    lexer::display_width(&last_word) + 4
+                } else {
+                    line_prefix_width(&input, position)
+                };
+                if finished == "if"
+                    && (assigned || record_value)
+                    && compact.is_none()
+                    && pattern.is_none()
+                    && fits_inline(
+                        text,
+                        position,
+                        indent_level,
+                        active_indentation(&overrides, indentation),
+                        before,
+                        active_options(&overrides).interpolation_spacing,
+                        active_max_width(&overrides),
+                    )
+                {
+                    compact = Some(delimiters.len());
+                }
+                type_depth = signature::type_parameter_depth(type_depth, &finished);
+                if command.is_none() {
+                    let grouped = matches!(delimiters.last(), Some((b'(', _)))
+                        && active_options(&overrides).flag_grouping
+                        && hints::layout_hint(&finished).is_some_and(|hint| hint.group_flags);
+                    if grouped && text.get(position..).is_some_and(lexer::is_multiline_call) {
+                        grouped_call = Some(delimiters.len());
+                    }
+                    command = Some(finished.clone());
+                }
+                last_word = finished;
+                word.clear();
+            }
+            let grouping = grouped_call == Some(delimiters.len());
+            let flag = word == b"-"
+                && input
+                    .get(position + 1)
+                    .is_some_and(|next| next.is_ascii_alphabetic() || *next == b'-');
+            if grouping && flag {
+                // every flag of a grouped call starts a line, along with its values
+                newline_requested = true;
+            }
+            let mut auto_push = true;
+            let mut request_newline = false;
+            // the blocks of a compact if are written `{ 1 }`
+            let compact_block = match char {
+                b'{' => compact == Some(delimiters.len()),
+                b'}' => compact.is_some_and(|level| delimiters.len() == level + 1),
+                _ => false,
+            };
+            let request_space = compact_block
+                && char == b'{'
+                && !matches!(
+                    next_significant_byte(&input, position + 1),
+                    Some(b'|' | b'}')
+                );
+            if newline_requested && !matches!(char, b' ' | b'\t' | b'\n') {
+                // parentheses indent their content once it spans several lines
+                if let Some((b'(', indented @ false)) = delimiters.last_mut() {
+                    *indented = true;
+                    indent_level += 1;
+                }
+            }
+            // the lines of a pipeline starting with a pipe are indented once, `  | get name`, up
+            // to the first line which does not continue it
+            if pipeline_indents.last() == Some(&delimiters.len())
+                && newline_forced
+                && !matches!(char, b' ' | b'\t' | b'\r' | b'\n')
+                && !continues_pipeline(&input, position)
+            {
+                indent_level = indent_level.saturating_sub(1);
+                pipeline_indents.pop();
+            }
+            let placement = active_options(&overrides).pipe_placement;
+            // comments above the stage go with it
+            let leads_stage = char == b'#' && starts_with_pipe(&input, position);
+            if (char == b'|' || leads_stage)
+                && newline_forced
+                && glued_depth == 0
+                && pipeline_indents.last() != Some(&delimiters.len())
+                && indents_pipeline(&delimiters, &match_arms)
+                && (placement != Some(directive::Placement::Trailing) || after_comment)
+            {
+                indent_level += 1;
+                pipeline_indents.push(delimiters.len());
+            }
+            let old_level = indent_level;
+            let glued = match char {
+                b'[' | b'{' if external && (after_word || glued_depth > 0) => {
+                    glued_depth += 1;
+                    true
+                }
+                b']' | b'}' if glued_depth > 0 => {
+                    glued_depth -= 1;
+                    true
+                }
+                _ => glued_depth > 0,
+            };
+            if char == b'(' && signature == Some(delimiters.len()) && last_word.starts_with("--") {
+                // short flags, `--flag (-f)`
+                space_requested = true;
+            }
+            let depth = delimiters.len();
+            let closes_parameters = char == b']' && signature == Some(depth);
+            // destructuring patterns stay on one line, `let [x, y] = ...` or `{a, b} => ...`
+            let destructures = matches!(last_word.as_str(), "let" | "mut")
+                && command.as_deref() == Some(last_word.as_str());
+            let arm_pattern = match_arms.last().is_some_and(|(level, in_pattern)| {
+                *level == depth && (*in_pattern || newline_requested)
+            });
+            let opens_pattern =
+                !glued && matches!(char, b'[' | b'{') && (destructures || arm_pattern);
+            let stage_ends = char == b'|' && broken_pipelines.last() == Some(&depth);
+            if compact == Some(depth)
+                && (matches!(char, b'\n' | b',' | b';' | b')' | b']' | b'}') || stage_ends)
+            {
+                compact = None;
+            }
+            // short closures written on one line stay on it, `each {|it| $it + 1 }`, and on the
+            // line of their key, `{render: {|| date now}}`
+            let closure =
+                char == b'{' && !glued && next_significant_byte(&input, position + 1) == Some(b'|');
+            let closure_value =
+                closure && last_significant == b':' && matches!(delimiters.last(), Some((b'{', _)));
+            let before = if closure_value {
+                lexer::display_width(&last_word) + 2
+            } else if !newline_requested {
+                // the line as written, which may have been broken before, `  | each`
+                let indent = indent_level * active_indentation(&overrides, indentation).width();
+                writer.width().saturating_sub(indent) + 1
+            } else {
+                line_prefix_width(&input, position) + 1
+            };
+            // the next stages of a pipeline broken by stage go on lines of their own
+            let end = match closure && broken_pipelines.last() == Some(&depth) {
+                true => text
+                    .get(position..)
+                    .map_or(text.len(), |rest| position + lexer::stage_end(rest)),
+                false => text.len(),
+            };
+            // unless every stage gets a line of its own
+            let staged = closure
+                && active_options(&overrides).break_pipelines
+                && text
+                    .get(position + 1..)
+                    .is_some_and(lexer::is_closure_pipeline);
+            if closure
+                && !staged
+                && compact.is_none()
+                && flat.is_none()
+                && fits_inline(
+                    text.get(..end).unwrap_or_default(),
+                    position,
+                    indent_level,
+                    active_indentation(&overrides, indentation),
+                    before,
+                    active_options(&overrides).interpolation_spacing,
+                    active_max_width(&overrides),
+                )
+            {
+                compact = Some(depth);
+            }
+            let inline_before = one_line
+                || pattern.is_some()
+                || opens_pattern
+                || compact.is_some()
+                || flat.is_some();
+            // lists and records written on one line stay on it if they fit, `[1 2 3]` or `{a: 1}`
+            if matches!(char, b'[' | b'{')
+                && !glued
+                && !inline_before
+                && signature.is_none()
+                && !active_options(&overrides).expand_collections
+            {
+                let line_end = input[position..]
+                    .iter()
+                    .position(|&byte| byte == b'\n')
+                    .map_or(input.len(), |end| position + end);
+                let line = text.get(..line_end).unwrap_or_default();
+                // blocks may look like records, `if $x { a: 1 }`
+                let block = char == b'{'
+                    && brace_kinds
+                        .binary_search_by_key(&position, |(offset, _)| *offset)
+                        .is_ok_and(|found| brace_kinds[found].1 != lexer::BraceKind::Record);
+                let flat_collection = !block
+                    && line
+                        .get(position + 1..)
+                        .is_some_and(|rest| lexer::is_flat_collection(char, rest));
+                // the input and output types of a def are joined on one line if they fit,
+                // `]: [string -> table, nothing -> table]`
+                let output_types = char == b'[' && signature_types && last_significant == b':';
+                let column = writer.width() + usize::from(space_requested);
+                // and so are the parameters, which are only laid out one per line if they do not
+                let parameters = char == b'[' && signature_pending;
+                if output_types || parameters {
+                    let rest = text.get(position + 1..);
+                    let max_width = active_max_width(&overrides);
+                    if rest
+                        .is_some_and(|rest| signature::joins(rest, parameters, column, max_width))
+                    {
+                        flat = Some(depth);
+                    }
+                } else if flat_collection
+                    && fits_inline(
+                        line,
+                        position,
+                        indent_level,
+                        active_indentation(&overrides, indentation),
+                        line_prefix_width(&input, position),
+                        active_options(&overrides).interpolation_spacing,
+                        active_max_width(&overrides),
+                    )
+                {
+                    flat = Some(depth);
+                }
+            }
+            let inline = inline_before || flat.is_some();
+            // the entries of a record laid out over several lines get a line each, `{a: 1 b: 2}`
+            if !inline && record_entries.binary_search(&position).is_ok() {
+                newline_requested = true;
+            }
+            // subexpressions too long for their line are laid out over several, like blocks
+            let breaks_subexpression = char == b'('
+                && !glued
+                && !inline
+                && signature.is_none()
+                && text
+                    .get(position + 1..)
+                    .and_then(|rest| {
+                        let interpolation_spacing =
+                            active_options(&overrides).interpolation_spacing;
+                        lexer::single_line_width(rest, interpolation_spacing)
+                    })
+                    .is_some_and(|width| {
+                        // the newline before the subexpression is not written yet
+                        let column = match newline_requested {
+                            true => {
+                                indent_level * active_indentation(&overrides, indentation).width()
+                            }
+                            false => writer.width() + usize::from(space_requested),
+                        };
+                        column + width + 2 > active_max_width(&overrides)
+                    });
+            let subexpression = matches!(delimiters.last(), Some((b'(', true)));
+            // the line breaks of pipelines which are joined, none if they are preserved
+            let joined: &[usize] = match active_options(&overrides).preserve_line_breaks {
+                Some(false) => &pipeline_breaks,
+                _ => &[],
+            };
+            // a pipeline too long for its line has a stage per line, each starting with its pipe
+            let breaks_pipeline = char == b'|'
+                && !glued
+                && !inline
+                && !newline_forced
+                && (indents_pipeline(&delimiters, &match_arms) || subexpression)
+                && !matches!(
+                    next_byte(&input, position, joined),
+                    None | Some(b'\n' | b'#')
+                )
+                && (broken_pipelines.last() == Some(&depth)
+                    || active_options(&overrides).break_pipelines
+                    || text
+                        .get(position..)
+                        .and_then(|rest| {
+                            let joined =
+                                |offset| joined.binary_search(&(position + offset)).is_ok();
+                            let interpolation_spacing =
+                                active_options(&overrides).interpolation_spacing;
+                            lexer::joined_line_width(rest, joined, interpolation_spacing)
+                        })
+                        .is_some_and(|width| {
+                            writer.width() + 1 + width > active_max_width(&overrides)
+                        }));
+            // collections on one line may be padded, `[ 1 2 ]` or `{ a: 1 }`
+            let padded = inline
+                && !glued
+                && !compact_block
+                && match char {
+                    b'[' | b']' => active_options(&overrides).bracket_spacing,
+                    b'{' | b'}' => active_options(&overrides).brace_spacing || one_line,
+                    _ => false,
+                };
+            // the body of a closure is spaced out after its parameters
+            let mut request_space = request_space
+                || (padded
+                    && matches!(char, b'[' | b'{')
+                    && !matches!(
+                        next_significant_byte(&input, position + 1),
+                        Some(b'|' | b']' | b'}')
+                    ));
+            // `do { ls }` is written `do {|| ls}` where a closure is expected anyway
+            let explicit_closure = char == b'{'
+                && !glued
+                && active_options(&overrides).closure_style
+                    == Some(directive::ClosureStyle::Explicit)
+                && command.as_deref() == Some(last_word.as_str())
+                && hints::layout_hint(&last_word).is_some_and(|hint| hint.closure_argument)
+                && !matches!(
+                    next_significant_byte(&input, position + 1),
+                    None | Some(b'|' | b'}')
+                );
+            // newlines inside parentheses do not end the call
+            let continues = char == b'\n' && matches!(delimiters.last(), Some((b'(', _)));
+            if !glued && !continues && matches!(char, b'|' | b';' | b'\n' | b'(' | b'[' | b'{') {
+                command = None;
+                last_word.clear();
+            }
+            let mut closes_block = matches!(char, b']' | b'}');
+            if !glued {
+                match char {
+                    b'(' => delimiters.push((char, breaks_subexpression)),
+                    b'[' | b'{' => delimiters.push((char, true)),
+                    b')' => closes_block = delimiters.pop() == Some((b'(', true)),
+                    b']' | b'}' => {
+                        delimiters.pop();
+                    }
+                    _ => {}
+                }
+                if opens_pattern && pattern.is_none() {
+                    pattern = Some(delimiters.len());
+                }
+                if pattern.is_some_and(|level| delimiters.len() < level) {
+                    pattern = None;
+                }
+                if compact.is_some_and(|level| delimiters.len() < level) {
+                    compact = None;
+                }
+                if flat.is_some_and(|level| delimiters.len() <= level) {
+                    flat = None;
+                }
+                if char == b'[' && signature_pending {
+                    signature = Some(delimiters.len());
+                }
+                if signature.is_some_and(|level| delimiters.len() < level) {
+                    signature = None;
+                }
+                if matches!(char, b'[' | b'{' | b';' | b'\n') {
+                    signature_pending = false;
+                }
+                if char == b'{' && match_pending {
+                    match_arms.push((delimiters.len(), false));
+                }
+                if matches!(char, b'{' | b';' | b'\n') {
+                    match_pending = false;
+                }
+                if match_arms
+                    .last()
+                    .is_some_and(|(level, _)| delimiters.len() < *level)
+                {
+                    match_arms.pop();
+                }
+                while broken_pipelines.last().is_some_and(|&level| {
+                    delimiters.len() < level
+                        || (delimiters.len() == level && char == b';')
+                        || (delimiters.len() == level
+                            && char == b'\n'
+                            && joined.binary_search(&position).is_err())
+                }) {
+                    broken_pipelines.pop();
+                }
+                while pipeline_indents.last().is_some_and(|&level| {
+                    delimiters.len() < level || (delimiters.len() == level && char == b';')
+                }) {
+                    indent_level = indent_level.saturating_sub(1);
+                    pipeline_indents.pop();
+                }
+                if grouped_call.is_some_and(|level| delimiters.len() < level)
+                    || (grouping && matches!(char, b'|' | b';'))
+                {
+                    grouped_call = None;
+                }
+            }
+            let mut continuation = None;
+            // comment lines between the stages of a pipeline are skipped over
+            let comment_line = char == b'#' && newline_forced;
+            if !matches!(char, b' ' | b'\t' | b'\n') && !comment_line {
+                if let Some((space, text)) = pending.take() {
+                    // it stays where it is if the pipeline does not continue on a new line
+                    if newline_forced && !closes_block {
+                        continuation = Some(text);
+                    } else {
+                        if space {
+                            writer.write_all(b" ")?;
+                        }
+                        writer.write_all(text.as_bytes())?;
+                    }
+                }
+            }
+
+            match char {
+                _ if glued => {}
+                b'#' => in_comment = true,
+                // subexpressions of $"..." may hold quotes, their end is tracked separately
+                b'"' | b'\'' if after_word && last_word == "$" => {
+                    interpolation = Some(interpolation::Interpolation::new(char));
+                    // arguments of external commands are kept as written
+                    if active_options(&overrides).interpolation_spacing && !external {
+                        interpolated = Some(Vec::new());
+                    }
+                    last_word.clear();
+                }
+                b'"' => {
+                    in_string = true;
+                    let holds_code = command
+                        .as_deref()
+                        .and_then(hints::layout_hint)
+                        .is_some_and(|hint| hint.embedded_code_flags.contains(&last_word.as_str()));
+                    if active_options(&overrides).embedded_code && holds_code {
+                        embedded_code = Some(Vec::new());
+                    }
+                    last_word.clear();
+                }
+                // the arguments of a grouped call are laid out again
+                b'\n' if grouping => {
+                    space_requested = true;
+                    continue;
+                }
+                b'\n' if inline => {
+                    space_requested = !after_inline_open;
+                    continue;
+                }
+                b'\n' if joined.binary_search(&position).is_ok() => {
+                    space_requested = true;
+                    continue;
+                }
+                b'\n' => {
+                    // newlines end statements, only runs of them are collapsed
+                    type_depth = 0;
+                    newline_requested = true;
+                    newline_forced = true;
+                    line_start = true;
+                    space_requested = false;
+                    continue;
+                }
+                b' ' | b'\t' => {
+                    // collapse runs of whitespace, dropping it at the start of a line
+                    space_requested |= !line_start && !after_inline_open;
+                    continue;
+                }
+                b'(' if breaks_subexpression => {
+                    indent_level += 1;
+                    request_newline = true;
+                }
+                b'[' | b'{' => {
+                    indent_level += 1;
+                    request_newline = !inline;
+                    let parameters = signature == Some(delimiters.len());
+                    let rest = text.get(position + 1..).unwrap_or_default();
+                    let table_literal = (char == b'['
+                        && !inline
+                        && !parameters
+                        && active_options(&overrides).table_alignment)
+                        .then(|| lexer::table_literal(rest))
+                        .flatten();
+                    if let Some(literal) = table_literal {
+                        // the rows are written along with the bracket, up to the `]`
+                        skip_until = position + 1 + literal.end;
+                        table = Some(literal);
+                        request_newline = false;
+                    } else if char == b'['
+                        && !inline
+                        && !parameters
+                        && active_options(&overrides).list_fill
+                    {
+                        let short = |list: &lexer::ScalarList| {
+                            list.items
+                                .iter()
+                                .all(|item| lexer::display_width(item) <= FILL_ITEM_WIDTH)
+                        };
+                        if let Some(list) = lexer::scalar_list(rest).filter(short) {
+                            // the items are written along with the bracket, up to the `]`
+                            let items = list.items.into_iter().map(str::to_string).collect();
+                            filled = Some((items, list.commas));
+                            skip_until = position + 1 + list.end;
+                            request_newline = false;
+                        }
+                    }
+                }
+                b']' | b'}' => {
+                    // the last item gets a comma like the others, `  3,`
+                    if active_options(&overrides).trailing_commas
+                        && !inline
+                        && !after_comment
+                        && !closes_parameters
+                        && !matches!(last_significant, b'[' | b'{')
+                        && missing_commas.binary_search(&position).is_ok()
+                    {
+                        writer.write_all(b",")?;
+                    }
+                    indent_level = indent_level.saturating_sub(1);
+                    space_requested = (compact_block && last_significant != b'{')
+                        || (padded && !matches!(last_significant, b'[' | b'{'));
+                    // directives only apply until the end of their block
+                    overrides.retain(|(level, _)| *level <= indent_level);
+                    if !inline && (!newline_requested || newline_forced) {
+                        // see comment below about newline_requested
+                        writer.write_all(b"\n")?;
+                        let indentation = active_indentation(&overrides, indentation);
+                        indent_buffered(writer, indent_level, indentation)?;
+                    }
+                }
+                // b'[' => {
+                //     indent_level += 1;
+                //     request_newline = true;
+                // }
+                // b'{' => {
+                //     indent_level += 1;
+                //     request_newline = true;
+                // }
+                // b'}' | b']' => {
+                //     indent_level = indent_level.saturating_sub(1);
+                //     if !newline_requested {
+                //         // see comment below about newline_requested
+                //         writer.write_all(&[b'\n'])?;
+                //         indent_buffered(writer, indent_level, indentation)?;
+                //     }
+                // }
+                b')' if closes_block => {
+                    // a multiline parenthesis is closed on its own line, like a block
+                    indent_level = indent_level.saturating_sub(1);
+                    space_requested = false;
+                    overrides.retain(|(level, _)| *level <= indent_level);
+                    writer.write_all(b"\n")?;
+                    let indentation = active_indentation(&overrides, indentation);
+                    indent_buffered(writer, indent_level, indentation)?;
+                }
+                b':' if !external => {
+                    // written after the newline of a colon starting its line, `ls\n:x`
+                    space_requested = false;
+                    // `a:b` and `http://x` are bare words, only keys and names are spaced out
+                    let key = matches!(delimiters.last(), Some((b'{', _)))
+                        && (command.is_none()
+                            || (after_word && starts_element(&input, position - last_word.len())));
+                    let annotation = type_depth > 0
+                        || signature == Some(depth)
+                        || (after_word && declares(&input, position - last_word.len()));
+                    request_space = (key || annotation)
+                        && active_options(&overrides).enabled(directive::Rule::ColonSpacing);
+                }
+                // the fields of record<...> and table<...> types stay on one line
+                b',' if !external && (type_depth > 0 || inline) => {
+                    auto_push = false;
+                    writer.write_all(&[char])?;
+                    space_requested = true;
+                }
+                b',' if !external => {
+                    space_requested = false;
+                    request_newline = true;
+                }
+                // the pipes of trailing placement end the lines of the stages
+                b'|' if breaks_pipeline && placement == Some(directive::Placement::Trailing) => {
+                    auto_push = false;
+                    if broken_pipelines.last() != Some(&depth) {
+                        broken_pipelines.push(depth);
+                    }
+                    writer.write_all(b" |")?;
+                    space_requested = false;
+                    request_newline = true;
+                }
+                b'|' if breaks_pipeline => {
+                    auto_push = false;
+                    if broken_pipelines.last() != Some(&depth) {
+                        broken_pipelines.push(depth);
+                    }
+                    // the stages of a subexpression line up with its first, as written over lines
+                    if pipeline_indents.last() != Some(&depth) && !subexpression {
+                        indent_level += 1;
+                        pipeline_indents.push(depth);
+                    }
+                    writer.write_all(b"\n")?;
+                    let indentation = active_indentation(&overrides, indentation);
+                    indent_buffered(writer, indent_level, indentation)?;
+                    writer.write_all(b"|")?;
+                    space_requested = true;
+                }
+                b'|' if !glued => match active_options(&overrides).pipe_placement {
+                    Some(directive::Placement::Leading) => {
+                        // the pipe moves to the start of the next line
+                        let continued =
+                            matches!(next_byte(&input, position, joined), Some(b'\n' | b'#'));
+                        if continued
+                            && pipeline_indents.last() != Some(&depth)
+                            && indents_pipeline(&delimiters, &match_arms)
+                        {
+                            indent_level += 1;
+                            pipeline_indents.push(depth);
+                        }
+                        pending = Some((space_requested, "|".to_string()));
+                        space_requested = false;
+                        continue;
+                    }
+                    // a pipe cannot be moved after a comment
+                    Some(directive::Placement::Trailing) if newline_forced && !after_comment => {
+                        auto_push = false;
+                        writer.write_all(b" |")?;
+                        newline_requested = false;
+                        space_requested = false;
+                        request_newline = true;
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+
+            // the comment describing a parameter or an entry stays on its line, `x: int, # the x`
+            // or `a: 1, # one`
+            if char == b'#'
+                && !newline_forced
+                && last_significant == b','
+                && matches!(delimiters.last(), Some((b'[' | b'{', _)))
+            {
+                newline_requested = false;
+                space_requested = true;
+            }
+            let prefix = if newline_requested && !closes_block {
+                // newline only happens after { [ and ,
+                // this means we can safely assume that it being followed up by } or ]
+                // means an empty object/array
+                let mut level = old_level;
+                match match_arms.last_mut() {
+                    // the lines of a match arm's pattern after the first one are indented
+                    Some((arms, true)) if *arms == depth => level += 1,
+                    Some((arms, in_pattern)) if *arms == depth && char != b'#' => {
+                        *in_pattern = true
+                    }
+                    _ => {}
+                }
+                let blank_lines = match continuation {
+                    None => blank_lines_before(&input, position, &overrides),
+                    Some(_) => 0,
+                };
+                Prefix::Newline {
+                    level,
+                    continuation,
+                    after_comment,
+                    blank_lines,
+                }
+            } else if space_requested && auto_push && !newline_requested {
+                Prefix::Space
+            } else {
+                Prefix::Nothing
+            };
+
+            // a token too long for its line goes on a line of its own, `(http get <url>)`
+            let token_start = char == b'"' || (word_byte && word.len() == 1);
+            let interpolation_spacing =
+                active_options(&overrides).interpolation_spacing && !external;
+            let wraps = token_start
+                && matches!(prefix, Prefix::Space)
+                && active_options(&overrides).wrap_long_tokens
+                && !inline
+                && !glued
+                && !grouping
+                && signature != Some(depth)
+                && matches!(delimiters.last(), Some((b'(' | b'[', _)))
+                // the line is broken before the token which makes it too long, not after
+                && writer.width() < active_max_width(&overrides)
+                && writer.width() + 1 + lexer::token_width(&input[position..], interpolation_spacing)
+                    > active_max_width(&overrides);
+            let prefix = if wraps {
+                if let Some((b'(', indented @ false)) = delimiters.last_mut() {
+                    *indented = true;
+                    indent_level += 1;
+                }
+                Prefix::Newline {
+                    level: indent_level,
+                    continuation: None,
+                    after_comment: false,
+                    blank_lines: 0,
+                }
+            } else {
+                prefix
+            };
+            // tokens lined up with the lines around keep their column, relative to the indentation
+            let mut prefix = match (prefix, aligned.get(&position)) {
+                (Prefix::Space, Some(&(column, narrowest)))
+                    if (token_start || char == b'#')
+                        && active_options(&overrides).preserve_alignment =>
+                {
+                    // comments are moved together to leave them their spaces
+                    let spaces = active_options(&overrides).comment_min_spaces_before;
+                    let shift = match char {
+                        b'#' => spaces.unwrap_or(1).saturating_sub(narrowest),
+                        _ => 0,
+                    };
+                    let target = writer.indentation_width() + column + shift;
+                    Prefix::Padding(target.saturating_sub(writer.width()).max(1))
+                }
+                // the spaces around operators are kept as written when their rule is disabled
+                (Prefix::Space, _)
+                    if !active_options(&overrides).enabled(directive::Rule::OperatorSpacing)
+                        && around_operator(&input, position) =>
+                {
+                    Prefix::Padding(written_spaces(&input, position).max(1))
+                }
+                (prefix, _) => prefix,
+            };
+
+            // a new line at the top level which does not continue a pipeline
+            let starts_statement = line_start
+                && depth == 0
+                && char != b'|'
+                && last_significant != b'|'
+                && matches!(
+                    prefix,
+                    Prefix::Nothing
+                        | Prefix::Newline {
+                            level: 0,
+                            continuation: None,
+                            ..
+                        }
+                );
+            if word_byte {
+                // bare words are written once finished, operators may move to another line
+                if word.len() == 1 {
+                    word_prefix = prefix;
+                    word_statement = starts_statement;
+                    word_definition_lines = match starts_statement {
+                        true => definition_blank_lines(
+                            &input,
+                            position,
+                            &mut previous_definition,
+                            &overrides,
+                        ),
+                        false => None,
+                    };
+                    word_after_colon = last_significant == b':';
+                }
+            } else {
+                if starts_statement && active_options(&overrides).item_spacing {
+                    let blank_line = if char == b'#' {
+                        leading_blank_line(&input, position, &mut previous_item)
+                    } else {
+                        let blank_line = previous_item == Some(true);
+                        previous_item = Some(false);
+                        blank_line
+                    };
+                    if blank_line {
+                        prefix.add_blank_line();
+                    }
+                }
+                if starts_statement {
+                    let lines = definition_blank_lines(
+                        &input,
+                        position,
+                        &mut previous_definition,
+                        &overrides,
+                    );
+                    if let Some(lines) = lines {
+                        prefix.set_blank_lines(lines);
+                    }
+                }
+                if char == b'#' && matches!(prefix, Prefix::Space) {
+                    // comments at the end of a line of code
+                    let options = active_options(&overrides);
+                    if options.enabled(directive::Rule::CommentSpacing) {
+                        let spaces = options.comment_min_spaces_before.unwrap_or(1);
+                        writer.write_all(" ".repeat(spaces).as_bytes())?;
+                    } else {
+                        let written = input[..position]
+                            .iter()
+                            .rev()
+                            .take_while(|&&byte| matches!(byte, b' ' | b'\t'))
+                            .count();
+                        writer.write_all(&input[position - written.max(1)..position])?;
+                    }
+                } else {
+                    write_prefix(writer, &prefix, &overrides, indentation)?;
+                }
+                if auto_push {
+                    writer.write_all(&[char])?;
+                }
+                if explicit_closure {
+                    writer.write_all(b"||")?;
+                }
+                if let Some((items, commas)) = filled.take() {
+                    let indentation = active_indentation(&overrides, indentation);
+                    write_filled(
+                        writer,
+                        &items,
+                        commas,
+                        indent_level,
+                        indentation,
+                        &overrides,
+                    )?;
+                }
+                if let Some(literal) = table.take() {
+                    let indentation = active_indentation(&overrides, indentation);
+                    let max_width = active_max_width(&overrides);
+                    write_table(writer, &literal, indent_level, indentation, max_width)?;
+                }
+                // the parameters are held back until their comments can be aligned
+                let options = active_options(&overrides);
+                if char == b'['
+                    && signature == Some(delimiters.len())
+                    && options.signature_comment_alignment
+                    && options.enabled(directive::Rule::CommentSpacing)
+                {
+                    writer.hold();
+                }
+                if signature.is_none() {
+                    writer.release(options.comment_min_spaces_before.unwrap_or(1))?;
+                }
+            }
+            if auto_push {
+                space_requested = request_space;
+            }
+
+            line_start = false;
+            last_significant = char;
+            newline_requested = request_newline;
+            newline_forced = false;
+            after_open_brace = char == b'{' && !glued;
+            after_inline_open = inline && matches!(char, b'[' | b'{');
+            signature_types = closes_parameters || (signature_types && char == b':');
+            after_comment = false;
+        }
+    }
+
+    // the input may end before the token being read does, with a comment, string or closure
+    // parameters left open: unterminated comments and strings were written as they were read,
+    // what is held back to be normalized once it ends is written as it is
+    if let Some(content) = interpolated {
+        writer.write_all(&content)?;
+    }
+    if let Some(code) = embedded_code {
+        writer.write_all(&code)?;
+    }
+    if let Some(params) = closure_params {
+        writer.write_all(&params)?;
+    }
+    if let Some((_, text)) = verbatim {
+        writer.write_all(String::from_utf8_lossy(&text).trim_end().as_bytes())?;
+    }
+    if !word.is_empty() {
+        write_prefix(writer, &word_prefix, &overrides, indentation)?;
+        writer.write_all(&word)?;
+    }
+    if let Some((space, text)) = pending {
+        if space {
+            writer.write_all(b" ")?;
+        }
+        writer.write_all(text.as_bytes())?;
+    }
+
+    if newline_forced {
+        writer.write_all(b"\n")?;
+    }
+    writer.release(
+        active_options(&overrides)
+            .comment_min_spaces_before
+            .unwrap_or(1),
+    )?;
+
+    Ok(())
+}
+
+/// The width up to which values are kept on the line of their key or variable, unless a
+/// `max_width` is set
+const INLINE_WIDTH: usize = 100;
+
+///
+/// # Checks whether the value starting at `position` is kept on one line
+///
+/// It must be written on one line of `nu` and fit in `max_width` after the indentation and
+/// `before` other characters, once the subexpressions of interpolated strings are normalized if
+/// `interpolation_spacing`.
+///
+fn fits_inline(
+    nu: &str,
+    position: usize,
+    level: usize,
+    indentation: Indentation,
+    before: usize,
+    interpolation_spacing: bool,
+    max_width: usize,
+) -> bool {
+    let indent = level * indentation.width();
+    match nu.get(position..) {
+        Some(rest) => lexer::single_line_width(rest, interpolation_spacing)
+            .is_some_and(|width| indent + before + width <= max_width),
+        None => false,
+    }
+}
+
+/// The longest item of a list written several items per line
+const FILL_ITEM_WIDTH: usize = 20;
+
+/// The most spaces the cells of a table literal are padded with to align its columns
+const TABLE_PADDING_WIDTH: usize = 20;
+
+///
+/// # Writes the items of a list several per line
+///
+/// Each line starts after a newline and the indentation, and holds as many items as fit in
+/// `max_width`, up to `fill_max_items`. The items are separated by commas or by spaces only, and
+/// padded to `fill_min_item_width` along with their comma unless they end their line.
+///
+fn write_filled<W>(
+    writer: &mut W,
+    items: &[String],
+    commas: bool,
+    level: usize,
+    indentation: Indentation,
+    overrides: &[(usize, directive::Options)],
+) -> std::io::Result<()>
+where
+    W: Write,
+{
+    let options = active_options(overrides);
+    let max_width = active_max_width(overrides);
+    let max_items = options.fill_max_items.unwrap_or(usize::MAX);
+    let min_item_width = options.fill_min_item_width.unwrap_or(0);
+    let indent = level * indentation.width();
+    let mut width = 0;
+    let mut line_items = 0;
+    let mut padding = 0; // spaces after the previous item if the item goes on its line
+    for (i, item) in items.iter().enumerate() {
+        let item_width = lexer::display_width(item);
+        // the comma following every item but the last counts too
+        let comma = usize::from(commas && i + 1 < items.len());
+        if i == 0 || line_items == max_items || width + padding + 1 + item_width + comma > max_width
+        {
+            if i > 0 && commas {
+                writer.write_all(b",")?;
+            }
+            writer.write_all(b"\n")?;
+            indent_buffered(writer, level, indentation)?;
+            width = indent;
+            line_items = 0;
+        } else {
+            if commas {
+                writer.write_all(b",")?;
+            }
+            writer.write_all(" ".repeat(padding + 1).as_bytes())?;
+            width += padding + 1 + usize::from(commas);
+        }
+        writer.write_all(item.as_bytes())?;
+        width += item_width;
+        line_items += 1;
+        padding = min_item_width.saturating_sub(item_width + comma);
+    }
+    Ok(())
+}
+
+/// A writer keeping track of the line being written
+struct Columns<'a, W: Write> {
+    inner: &'a mut W,
+    line: Vec<u8>,
+    /// The output held back to align its comments, from the start of the line it began on, along
+    /// with the length of that line already written
+    held: Option<(usize, Vec<u8>)>,
+}
+
+impl<W: Write> Columns<'_, W> {
+    /// The display width of the line written so far
+    fn width(&self) -> usize {
+        lexer::display_width(&String::from_utf8_lossy(&self.line))
+    }
+
+    /// The width of the indentation of the line written so far
+    fn indentation_width(&self) -> usize {
+        self.line
+            .iter()
+            .take_while(|byte| matches!(byte, b' ' | b'\t'))
+            .count()
+    }
+
+    /// Holds back what is written next, until [release](Columns::release)
+    fn hold(&mut self) {
+        if self.held.is_none() {
+            self.held = Some((self.line.len(), self.line.clone()));
+        }
+    }
+
+    /// Writes the output held back, with the comments at the end of its lines aligned
+    fn release(&mut self, spaces: usize) -> std::io::Result<()> {
+        if let Some((written, held)) = self.held.take() {
+            match String::from_utf8(held) {
+                Ok(text) => self
+                    .inner
+                    .write_all(&align_comments(&text, spaces).as_bytes()[written..])?,
+                Err(err) => self.inner.write_all(&err.as_bytes()[written..])?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Columns<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = match self.held.as_mut() {
+            Some((_, held)) => {
+                held.extend_from_slice(buf);
+                buf.len()
+            }
+            None => self.inner.write(buf)?,
+        };
+        let buf = &buf[..written];
+        match buf.iter().rposition(|&byte| byte == b'\n') {
+            Some(newline) => {
+                self.line.clear();
+                self.line.extend_from_slice(&buf[newline + 1..]);
+            }
+            None => self.line.extend_from_slice(buf),
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+///
+/// # Lines up the comments at the end of lines
+///
+/// Comments following code on their line are moved to the same column, `spaces` after the widest
+/// code, comments on a line of their own are left where they are.
+///
+fn align_comments(text: &str, spaces: usize) -> String {
+    // (offset of the whitespace before the comment, offset of the comment, width of the code)
+    let mut trailing = Vec::new();
+    let mut line_start = 0;
+    let tokens = lexer::tokenize(text);
+    for (i, token) in tokens.iter().enumerate() {
+        match token.kind {
+            lexer::TokenKind::Comment if i > 0 => {
+                let before = tokens[i - 1];
+                if before.kind == lexer::TokenKind::Whitespace && before.span.start > line_start {
+                    let width = lexer::display_width(&text[line_start..before.span.start]);
+                    trailing.push((before.span.start, token.span.start, width));
+                }
+            }
+            // strings may end lines too
+            _ => {
+                if let Some(newline) = token.text(text).rfind('\n') {
+                    line_start = token.span.start + newline + 1;
+                }
+            }
+        }
+    }
+
+    let column = trailing
+        .iter()
+        .map(|(_, _, width)| *width)
+        .max()
+        .unwrap_or(0)
+        + spaces;
+    let mut aligned = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (whitespace, comment, width) in trailing {
+        aligned.push_str(&text[copied..whitespace]);
+        aligned.push_str(&" ".repeat(column - width));
+        copied = comment;
+    }
+    aligned.push_str(&text[copied..]);
+    aligned
+}
+
+///
+/// # Writes the rows of a table literal
+///
+/// Each row starts after a newline and the indentation. The cells are padded so the columns line
+/// up, unless the rows would not fit in `max_width` or a cell would need more padding than
+/// [TABLE_PADDING_WIDTH], they are then separated by single spaces.
+///
+fn write_table<W>(
+    writer: &mut W,
+    table: &lexer::TableLiteral,
+    level: usize,
+    indentation: Indentation,
+    max_width: usize,
+) -> std::io::Result<()>
+where
+    W: Write,
+{
+    let indent = level * indentation.width();
+    let separator = if table.commas { ", " } else { " " };
+    let mut widths = vec![0; table.rows[0].len()];
+    for row in &table.rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(lexer::display_width(cell));
+        }
+    }
+    // the brackets and the semicolon or comma ending the row
+    let row_width = widths.iter().sum::<usize>() + separator.len() * (widths.len() - 1) + 3;
+    let padding = table.rows.iter().flat_map(|row| {
+        row.iter()
+            .zip(&widths)
+            .map(|(cell, width)| width - lexer::display_width(cell))
+    });
+    let aligned = indent + row_width <= max_width && padding.max() <= Some(TABLE_PADDING_WIDTH);
+
+    for (i, row) in table.rows.iter().enumerate() {
+        writer.write_all(b"\n")?;
+        indent_buffered(writer, level, indentation)?;
+        writer.write_all(b"[")?;
+        for (j, cell) in row.iter().enumerate() {
+            writer.write_all(cell.as_bytes())?;
+            if j + 1 < row.len() {
+                writer.write_all(separator.trim_end().as_bytes())?;
+                let padding = if aligned {
+                    widths[j] - lexer::display_width(cell)
+                } else {
+                    0
+                };
+                writer.write_all(" ".repeat(padding + 1).as_bytes())?;
+            }
+        }
+        writer.write_all(b"]")?;
+        if i == 0 {
+            writer.write_all(b";")?;
+        } else if table.row_commas && i + 1 < table.rows.len() {
+            writer.write_all(b",")?;
+        }
+    }
+    Ok(())
+}
+
+///
+/// # Finds the tokens lined up by padding
+///
+/// A token follows a gap when whitespace separates it from the previous token on its line. Gaps
+/// of consecutive lines at the same column, counted from the indentation, are lined up if one of
+/// them is wider than a space. Lines holding only a comment are skipped. Returns the column of the
+/// tokens after such gaps, along with the narrowest gap of their group, by their offset.
+///
+fn aligned_tokens(input: &[u8]) -> HashMap<usize, (usize, usize)> {
+    let mut aligned = HashMap::new();
+    let Ok(nu) = std::str::from_utf8(input) else {
+        return aligned;
+    };
+
+    // the gaps of every line, (column, width, offset of the token), and whether it is a comment
+    let mut lines = vec![(Vec::new(), false)];
+    let mut content_start: Option<usize> = None;
+    let tokens = lexer::tokenize(nu);
+    for (i, token) in tokens.iter().enumerate() {
+        let next = tokens.get(i + 1);
+        match token.kind {
+            lexer::TokenKind::Whitespace => {
+                // comments after a comma are moved to their own line
+                let after_comma = i > 0 && tokens[i - 1].kind == lexer::TokenKind::Comma;
+                if let (Some(start), Some(next)) = (content_start, next) {
+                    if next.kind != lexer::TokenKind::Newline
+                        && !(after_comma && next.kind == lexer::TokenKind::Comment)
+                    {
+                        let column = lexer::display_width(&nu[start..next.span.start]);
+                        let width = token.span.end - token.span.start;
+                        lines
+                            .last_mut()
+                            .unwrap()
+                            .0
+                            .push((column, width, next.span.start));
+                    }
+                }
+            }
+            _ => {
+                if content_start.is_none() && token.kind == lexer::TokenKind::Comment {
+                    lines.last_mut().unwrap().1 = true;
+                }
+                content_start.get_or_insert(token.span.start);
+                // strings and comments may end lines too
+                for _ in token.text(nu).matches('\n') {
+                    lines.push((Vec::new(), false));
+                    content_start = None;
+                }
+            }
+        }
+    }
+
+    let mut finish = |column: usize, group: Vec<(usize, usize)>| {
+        let narrowest = group.iter().map(|(width, _)| *width).min().unwrap_or(1);
+        if group.len() > 1 && group.iter().any(|(width, _)| *width > 1) {
+            for (_, offset) in group {
+                aligned.insert(offset, (column, narrowest));
+            }
+        }
+    };
+    let mut groups: HashMap<usize, Vec<(usize, usize)>> = HashMap::new();
+    for (gaps, comment) in lines {
+        // comments may be moved to their own line, they do not end the groups around them
+        if comment {
+            continue;
+        }
+        let mut continued = HashMap::new();
+        for (column, width, offset) in gaps {
+            let mut group = groups.remove(&column).unwrap_or_default();
+            group.push((width, offset));
+            continued.insert(column, group);
+        }
+        for (column, group) in groups {
+            finish(column, group);
+        }
+        groups = continued;
+    }
+    for (column, group) in groups {
+        finish(column, group);
+    }
+    aligned
+}
+
+/// The width of what precedes `position` on its line, indentation excluded
+fn line_prefix_width(input: &[u8], position: usize) -> usize {
+    let line_start = input[..position]
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    let line = String::from_utf8_lossy(&input[line_start..position]);
+    lexer::display_width(line.trim_start())
+}
+
+/// The first byte from `start` on which is not whitespace
+fn next_significant_byte(input: &[u8], start: usize) -> Option<u8> {
+    input
+        .get(start..)?
+        .iter()
+        .copied()
+        .find(|byte| !byte.is_ascii_whitespace())
+}
+
+///
+/// # Reads the run of comment and attribute lines around `position`
+///
+/// Such lines are kept together with the statement right below them. Returns whether the line of
+/// `position` starts the run, and the first line of that statement, `None` if the run is followed
+/// by a blank line or ends the input.
+///
+fn leading_lines(input: &[u8], position: usize) -> (bool, Option<String>) {
+    let leads = |line: &[u8]| matches!(line.trim_ascii_start().first(), Some(b'#' | b'@'));
+    let line_start = input[..position]
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    let first = line_start == 0
+        || !input[..line_start - 1]
+            .split(|&byte| byte == b'\n')
+            .next_back()
+            .is_some_and(leads);
+    let statement = input[line_start..]
+        .split(|&byte| byte == b'\n')
+        .find(|line| !leads(line))
+        .map(<[u8]>::trim_ascii)
+        .filter(|line| !line.is_empty())
+        .map(|line| String::from_utf8_lossy(line).into_owned());
+    (first, statement)
+}
+
+/// Whether the run of comments and attributes at `position` is preceded by a blank line
+fn leading_blank_line(input: &[u8], position: usize, previous_item: &mut Option<bool>) -> bool {
+    match leading_lines(input, position) {
+        (false, Some(_)) => false,
+        // the run is part of the statement below it
+        (true, Some(statement)) => {
+            let end = statement
+                .bytes()
+                .position(|byte| !lexer::is_word_byte(byte))
+                .unwrap_or(statement.len());
+            let item = hints::ITEM_KEYWORDS.contains(&&statement[..end]);
+            let blank_line = *previous_item == Some(true) || (item && previous_item.is_some());
+            *previous_item = None;
+            blank_line
+        }
+        // a run of its own is laid out like a plain statement
+        (_, None) => {
+            let blank_line = *previous_item == Some(true);
+            *previous_item = Some(false);
+            blank_line
+        }
+    }
+}
+
+///
+/// # The blank lines separating the top level statement at `position` from a definition above it
+///
+/// Only consecutive definitions, see [hints::DEFINITION_KEYWORDS], are separated by the
+/// `definition_blank_lines` option, `None` if the statement is laid out like any other. The
+/// comments and attributes above a definition are part of it.
+///
+fn definition_blank_lines(
+    input: &[u8],
+    position: usize,
+    previous_definition: &mut bool,
+    overrides: &[(usize, directive::Options)],
+) -> Option<usize> {
+    let (first, statement) = leading_lines(input, position);
+    if !first {
+        return None;
+    }
+    let definition = statement.as_deref().is_some_and(hints::is_definition);
+    let follows_definition = std::mem::replace(previous_definition, definition);
+    active_options(overrides)
+        .definition_blank_lines
+        .filter(|_| follows_definition && definition)
+}
+
+///
+/// The number of spaces and tabs written before the token at `start`
+fn written_spaces(input: &[u8], start: usize) -> usize {
+    input[..start]
+        .iter()
+        .rev()
+        .take_while(|byte| matches!(byte, b' ' | b'\t'))
+        .count()
+}
+
+/// Whether the token at `start` is an operator or follows one, `1 + 2`
+fn around_operator(input: &[u8], start: usize) -> bool {
+    let operator = |word: &[u8]| {
+        hints::CONTINUATION_OPERATORS
+            .iter()
+            .chain(hints::COMPARISON_OPERATORS)
+            .any(|operator| operator.as_bytes() == word)
+    };
+    let spaced = |byte: &u8| byte.is_ascii_whitespace();
+    let before = &input[..start - written_spaces(input, start)];
+    let previous = before
+        .iter()
+        .rposition(spaced)
+        .map_or(before, |space| &before[space + 1..]);
+    let next = &input[start..];
+    let next = next
+        .iter()
+        .position(spaced)
+        .map_or(next, |space| &next[..space]);
+    operator(previous) || operator(next)
+}
+
+/// # The blank lines kept above the token at `start`
+///
+/// Only a token starting its line keeps the blank lines written above it, up to the
+/// `max_blank_lines` option. Those at the start of the nu or of a block are dropped, and so are
+/// those inside of a pipeline, between a pipe and the next command.
+///
+fn blank_lines_before(
+    input: &[u8],
+    start: usize,
+    overrides: &[(usize, directive::Options)],
+) -> usize {
+    let line_start = input[..start]
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    let starts_line = input[line_start..start]
+        .iter()
+        .all(|byte| matches!(byte, b' ' | b'\t' | b'\r'));
+    if line_start == 0 || !starts_line {
+        return 0;
+    }
+    let blank_lines = input[..line_start - 1]
+        .split(|&byte| byte == b'\n')
+        .rev()
+        .take_while(|line| line.trim_ascii().is_empty())
+        .count();
+    let last = input[..line_start]
+        .iter()
+        .rev()
+        .find(|byte| !byte.is_ascii_whitespace());
+    match last {
+        None | Some(b'{' | b'[' | b'(') => 0,
+        Some(_) if continues_pipeline(input, line_start) => 0,
+        Some(_) => blank_lines.min(active_options(overrides).max_blank_lines.unwrap_or(1)),
+    }
+}
+
+///
+/// # Whether the line of `start` continues a pipeline
+///
+/// Comment lines are skipped over: the code line above must end with a pipe, or the code line
+/// from this one on start with one.
+///
+fn continues_pipeline(input: &[u8], start: usize) -> bool {
+    let line_start = input[..start]
+        .iter()
+        .rposition(|&byte| byte == b'\n')
+        .map_or(0, |newline| newline + 1);
+    let previous = input[..line_start.saturating_sub(1)]
+        .split(|&byte| byte == b'\n')
+        .rev()
+        .find(|line| is_code_line(line))
+        .map(<[u8]>::trim_ascii);
+    previous.is_some_and(|line| line.ends_with(b"|")) || starts_with_pipe(input, line_start)
+}
+
+/// The first byte after `position` which is neither whitespace nor one of the `joined` newlines
+fn next_byte(input: &[u8], position: usize, joined: &[usize]) -> Option<u8> {
+    input[position + 1..]
+        .iter()
+        .enumerate()
+        .find(|&(offset, byte)| match byte {
+            b' ' | b'\t' | b'\r' => false,
+            b'\n' => joined.binary_search(&(position + 1 + offset)).is_err(),
+            _ => true,
+        })
+        .map(|(_, &byte)| byte)
+}
+
+/// Whether the first code line from `line_start` on starts with a pipe, skipping comment lines
+fn starts_with_pipe(input: &[u8], line_start: usize) -> bool {
+    input[line_start..]
+        .split(|&byte| byte == b'\n')
+        .find(|line| is_code_line(line))
+        .is_some_and(|line| line.trim_ascii().starts_with(b"|"))
+}
+
+/// Whether a line holds code, rather than nothing or only a comment
+fn is_code_line(line: &[u8]) -> bool {
+    !matches!(line.trim_ascii().first(), None | Some(b'#'))
+}
+
+/// Whether the stages of a pipeline are indented once in the delimiters open, those of
+/// parentheses already are and a pipe in a match separates the alternatives of a pattern
+fn indents_pipeline(delimiters: &[(u8, bool)], match_arms: &[(usize, bool)]) -> bool {
+    matches!(delimiters.last(), None | Some((b'{', _)))
+        && match_arms
+            .last()
+            .is_none_or(|(level, _)| *level != delimiters.len())
+}
+
+/// Whether the token starting at `start` is the first of a record entry or a statement
+fn starts_element(input: &[u8], start: usize) -> bool {
+    let before = input[..start]
+        .iter()
+        .rev()
+        .find(|byte| !matches!(byte, b' ' | b'\t'));
+    matches!(
+        before,
+        None | Some(b'{' | b'[' | b'(' | b',' | b';' | b'|' | b'\n' | b'\r')
+    )
+}
+
+/// Whether the word ending at `end` is the name of a variable, `let x`
+fn declares(input: &[u8], end: usize) -> bool {
+    let before = input[..end].trim_ascii_end();
+    ["let", "mut", "const"].iter().any(|keyword| {
+        before.ends_with(keyword.as_bytes())
+            && before.len() < end
+            && (before.len() == keyword.len()
+                || !lexer::is_word_byte(before[before.len() - keyword.len() - 1]))
+    })
+}
+
+/// What is written before a token
+#[derive(Debug)]
+enum Prefix {
+    Nothing,
+    Space,
+    /// Spaces lining the token up with the lines around
+    Padding(usize),
+    /// A newline and the indentation, followed by a pipe or operator moved to the new line
+    Newline {
+        level: usize,
+        continuation: Option<String>,
+        /// The previous line ended with a comment, nothing can be moved to its end
+        after_comment: bool,
+        /// Empty lines written before the newline
+        blank_lines: usize,
+    },
+}
+
+impl Prefix {
+    /// Separates a new line from the lines above it by at least one blank line
+    fn add_blank_line(&mut self) {
+        if let Prefix::Newline { blank_lines, .. } = self {
+            *blank_lines = (*blank_lines).max(1);
+        }
+    }
+
+    /// Separates a new line from the lines above it by exactly `lines` blank lines
+    fn set_blank_lines(&mut self, lines: usize) {
+        if let Prefix::Newline { blank_lines, .. } = self {
+            *blank_lines = lines;
+        }
+    }
+}
+
+fn write_prefix<W>(
+    writer: &mut W,
+    prefix: &Prefix,
+    overrides: &[(usize, directive::Options)],
+    indentation: Indentation,
+) -> std::io::Result<()>
+where
+    W: Write,
+{
+    match prefix {
+        Prefix::Nothing => {}
+        Prefix::Space => writer.write_all(b" ")?,
+        Prefix::Padding(spaces) => writer.write_all(" ".repeat(*spaces).as_bytes())?,
+        Prefix::Newline {
+            level,
+            continuation,
+            blank_lines,
+            ..
+        } => {
+            writer.write_all("\n".repeat(blank_lines + 1).as_bytes())?;
+            indent_buffered(writer, *level, active_indentation(overrides, indentation))?;
+            if let Some(continuation) = continuation {
+                writer.write_all(continuation.as_bytes())?;
+                writer.write_all(b" ")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The options set by the innermost directive
+fn active_options(overrides: &[(usize, directive::Options)]) -> &directive::Options {
+    static DEFAULT: directive::Options = directive::Options {
+        indent: None,
+        embedded_code: false,
+        interpolation_spacing: false,
+        pipe_placement: None,
+        break_pipelines: false,
+        preserve_line_breaks: None,
+        operator_placement: None,
+        item_spacing: false,
+        definition_blank_lines: None,
+        flag_grouping: false,
+        closure_style: None,
+        list_fill: false,
+        fill_max_items: None,
+        fill_min_item_width: None,
+        comment_min_spaces_before: None,
+        max_blank_lines: None,
+        wrap_long_tokens: false,
+        table_alignment: false,
+        preserve_alignment: false,
+        signature_comment_alignment: false,
+        bracket_spacing: false,
+        brace_spacing: false,
+        expand_collections: false,
+        trailing_commas: false,
+        max_width: None,
+        disabled: Vec::new(),
+    };
+    overrides.last().map_or(&DEFAULT, |(_, options)| options)
+}
+
+/// The width set by the innermost directive, [INLINE_WIDTH] if none
+fn active_max_width(overrides: &[(usize, directive::Options)]) -> usize {
+    active_options(overrides).max_width.unwrap_or(INLINE_WIDTH)
+}
+
+/// The indentation set by the innermost directive, if any
+fn active_indentation<'a>(
+    overrides: &[(usize, directive::Options)],
+    indentation: Indentation<'a>,
+) -> Indentation<'a> {
+    active_options(overrides).indent.unwrap_or(indentation)
+}
+
+fn indent_buffered<W>(writer: &mut W, level: usize, indent_str: Indentation) -> std::io::Result<()>
+where
+    W: std::io::Write,
+{
+    for _ in 0..level {
+        indent_str.write(writer)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Formats within the width of 80 the tests of layouts depending on the width were written for
+    fn format_at_80(nu: &str) -> String {
+        format_nu_with_config(nu, &Config::builder().max_width(80).build())
+    }
+
+    #[test]
+    fn ignore_comments() {
+        let nu = "# this is a comment";
+        let expected = "# this is a comment";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn echoes_primitive() {
+        let nu = "1.35";
+        assert_eq!(nu, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn ignore_whitespace_in_string() {
+        let nu = "\" hallo \"";
+        assert_eq!(nu, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn ignore_whitespace_in_single_quoted_string() {
+        let nu = "print 'a  [b]  {c} | d, e: \"f\"  # g' 'C:\\dir\\'\nls | each {|x| 'a  }  b' }";
+        let expected =
+            "print 'a  [b]  {c} | d, e: \"f\"  # g' 'C:\\dir\\'\nls | each {|x| 'a  }  b' }";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(
+            "$x + 1",
+            format_expression("\n  $x   + 1\n\n", &Config::default())
+        );
+        assert_eq!(
+            "ls | each {|f| $f.name }",
+            format_expression("ls | each {|f| $f.name }\n", &Config::default())
+        );
+        assert_eq!(
+            "ls # files",
+            format_expression("ls # files\n", &Config::default())
+        );
+        let config = Config::builder()
+            .indentation(Indentation::Tabs(1))
+            .option("bracket_spacing", "true")
+            .build();
+        assert_eq!(
+            "if $x {\n\t[ 1 2 ]\n}",
+            format_expression("if $x {\n[1 2]}\n", &config)
+        );
+    }
+
+    #[test]
+    fn ignore_whitespace_in_backtick_string() {
+        let nu = "ls `my  dir/[a]` | get `x: y, z`";
+        assert_eq!(nu, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn ignore_whitespace_in_raw_string() {
+        let nu = "print r#'a  [b] # \"c\"'#  x\nlet s = r##'it'#s  {\n  d'##";
+        let expected = "print r#'a  [b] # \"c\"'# x\nlet s = r##'it'#s  {\n  d'##";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn history_entries() {
+        let entries = [
+            "ls | each { |x|   $x.name }",
+            " let x = {a:1,b:[1 , 2]} ",
+            "def f [x: int,--flag (-f)] { if $x {ls} else { ps } } # f",
+            "ls\n| length",
+        ];
+        let expected = [
+            "ls | each {|x| $x.name }",
+            "let x = { a: 1, b: [1, 2] }",
+            "def f [x: int, --flag (-f)] { if $x { ls } else { ps } } # f",
+            "ls\n| length",
+        ];
+        assert_eq!(expected.to_vec(), format_history(&entries));
+    }
+
+    #[test]
+    fn owned_indentation() {
+        let nu = "def f [] {\nls\n}";
+        assert_eq!("def f [] {\n\t\tls\n}", format_nu(nu, Indentation::Tabs(2)));
+        assert_eq!(
+            "def f [] {\n   ls\n}",
+            format_nu(nu, Indentation::Spaces(3))
+        );
+        assert_eq!(Indentation::Spaces(4), Indentation::of("    "));
+        assert_eq!(Indentation::Tabs(1), Indentation::of("\t"));
+        #[allow(deprecated)]
+        let custom = Indentation::Custom(" \t");
+        assert_eq!(custom, Indentation::of(" \t"));
+        assert_eq!("def f [] {\n \tls\n}", format_nu(nu, custom));
+    }
+
+    #[test]
+    fn format_with_config() {
+        let config = Config::builder()
+            .indentation(Indentation::Spaces(4))
+            .max_width(20)
+            .option("list_fill", "true")
+            .build();
+        let nu = "let a = [1 2 3 4 5 6 7 8 9 10]\ndef f [] {\n# nufmt: indent=1\nls\n}";
+        let expected = "let a = [
+    1 2 3 4 5 6 7 8
+    9 10
+]
+def f [] {
+    # nufmt: indent=1
+ ls
+}";
+        assert_eq!(expected, format_nu_with_config(nu, &config));
+        assert_eq!(
+            format_nu(nu, Indentation::Default),
+            format_nu_with_config(nu, &Config::default())
+        );
+    }
+
+    #[test]
+    fn check() {
+        let config = Config::default();
+        assert!(check_nu("def f [] {\n  ls -a\n}", &config));
+        assert!(!check_nu("def f [] {\n  ls   -a\n}", &config));
+        let wide = Config::builder()
+            .indentation(Indentation::Spaces(4))
+            .build();
+        assert!(!check_nu("def f [] {\n  ls -a\n}", &wide));
+    }
+
+    #[test]
+    fn diff() {
+        let config = Config::default();
+        assert_eq!(None, diff_nu("ls -a\n", &config));
+        let expected = "--- a/stdin.nu\n+++ b/stdin.nu\n@@ -1 +1 @@\n-ls   -a\n+ls -a\n";
+        assert_eq!(Some(expected.to_string()), diff_nu("ls   -a\n", &config));
+    }
+
+    #[test]
+    fn try_format() {
+        let nu = "ls|each {|x| $x}";
+        assert_eq!(
+            format_nu(nu, Indentation::Default),
+            try_format_nu(nu, Indentation::Default).unwrap()
+        );
+        let err = try_format_nu("def f [] {\n  ls\n", Indentation::Default).unwrap_err();
+        let location = Location {
+            offset: 9,
+            line: 1,
+            column: 10,
+        };
+        assert!(matches!(err, Error::UnbalancedDelimiter(at) if at == location));
+        assert_eq!(Some(location), err.location());
+        assert_eq!("unbalanced delimiter at line 1, column 10", err.to_string());
+    }
+
+    #[test]
+    fn limit_input_size() {
+        let nu = "ls|each {|x| $x}";
+        let config = Config::builder().max_input_size(nu.len()).build();
+        assert_eq!(
+            format_nu_with_config(nu, &config),
+            try_format_nu_with_config(nu, &config).unwrap()
+        );
+
+        let config = config.to_builder().max_input_size(8).build();
+        let err = try_format_nu_with_config(nu, &config).unwrap_err();
+        assert!(matches!(err, Error::InputTooLarge { size: 16, limit: 8 }));
+        assert!(err.to_string().contains("format_nu_buffered"));
+        assert!(matches!(
+            try_format_nu_with_config("(", &Config::default()),
+            Err(Error::UnbalancedDelimiter(_))
+        ));
+    }
+
+    #[test]
+    fn stable_api() {
+        let config = Config::builder().indentation(Indentation::Tabs(1)).build();
+        let outcome = format("def f [] {ls}", &config).unwrap();
+        assert_eq!("def f [] {\n\tls\n}", outcome.formatted);
+        assert!(outcome.changed);
+        assert!(!format(&outcome.formatted, &config).unwrap().changed);
+        assert!(matches!(
+            format("{", &config),
+            Err(Error::UnbalancedDelimiter(_))
+        ));
+    }
+
+    #[test]
+    fn remove_leading_whitespace() {
+        let nu = "   0";
+        let expected = "0";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn collapse_whitespace() {
+        let nu = "echo   a \t  b";
+        let expected = "echo a b";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn empty_collections() {
+        let nu = "[ ]";
+        assert_eq!("[]", format_nu(nu, Indentation::Default));
+        let nu = "{}";
+        assert_eq!("{}", format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn handle_escaped_strings() {
+        let nu = "  \" hallo \\\" \" ";
+        let expected = "\" hallo \\\" \"";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn simple_object() {
+        let nu = "{\"a\":0}";
+        assert_eq!("{\"a\": 0}", format_nu(nu, Indentation::Default));
+
+        let nu = "{\"a\":0\n}";
+        let expected = "{
+  \"a\": 0
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn simple_array() {
+        let nu = "[1,2,null]";
+        let expected = "[1, 2, null]";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+
+        let nu = "[1,2,null\n]";
+        let expected = "[
+  1,
+  2,
+  null
+]";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn array_of_object() {
+        let nu = "[{\"a\": 0}, {}, {\"a\": null}]";
+        let expected = "[{\"a\": 0}, {}, {\"a\": null}]";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+
+        let nu = "[\n{\"a\": 0}, {}, {\"a\": null\n}]";
+        let expected = "[
+  {\"a\": 0},
+  {},
+  {
+    \"a\": null
+  }
+]";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn flat_collections() {
+        let nu = "let a = [1,2,3]\nlet r = {a:1, b: [x y]}\nlet long = [alpha, beta, gamma, delta, epsilon, zeta, eta, theta, iota, kappa, lambda, mu, nu, xi, omicron]";
+        let expected = "let a = [1, 2, 3]
+let r = {a: 1, b: [x y]}
+let long = [
+  alpha,
+  beta,
+  gamma,
+  delta,
+  epsilon,
+  zeta,
+  eta,
+  theta,
+  iota,
+  kappa,
+  lambda,
+  mu,
+  nu,
+  xi,
+  omicron
+]";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+
+        let nu = "# nufmt: max_width=40\nlet r = {name: \"nufmt\", version: \"0.1\", edition: 2018}\nlet a = [1, 2, 3]";
+        let expected = "# nufmt: max_width=40
+let r = {
+  name: \"nufmt\",
+  version: \"0.1\",
+  edition: 2018
+}
+let a = [1, 2, 3]";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+
+        let nu = "# nufmt: expand_collections=true\nlet a = [1, 2]";
+        let expected = "# nufmt: expand_collections=true\nlet a = [\n  1,\n  2\n]";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn already_formatted() {
+        let expected = "[
+  {
+    \"a\": 0
+  },
+  {},
+  {
+    \"a\": null
+  }
+]";
+
+        assert_eq!(expected, format_nu(expected, Indentation::Default));
+    }
+
+    #[test]
+    fn file_level_directive() {
+        let nu = "# nufmt: indent=4\n[1, 2]";
+        let expected = "# nufmt: indent=4\n[1, 2]";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+
+        let nu = "# nufmt: indent=4\ndef f [] { ls }";
+        let expected = "# nufmt: indent=4
+def f [] {
+    ls
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn block_level_directive() {
+        let nu = "[[1], [# nufmt: indent=tab\n[2]], [3]]";
+        let expected = "[
+  [1],
+  [
+    # nufmt: indent=tab
+\t\t[2]
+  ],
+  [3]
+]";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn unterminated_input() {
+        // the final newline is kept as written
+        assert_eq!("ls", format_nu("ls", Indentation::Default));
+        assert_eq!("ls\n", format_nu("ls\n", Indentation::Default));
+        let unterminated = [
+            "ls # a comment  ",
+            "echo \"a  b  ",
+            "echo \"a\\",
+            "echo 'a  b  ",
+            "echo `a  b  ",
+            "echo r#'a  b  ",
+            "echo $\"a  (ls",
+            "each {|x,  y",
+            "# nufmt: interpolation_spacing=true\necho $\"a (  ls",
+            "# nufmt: embedded_code=true\nnu -c \"ls  |  length",
+        ];
+        for nu in unterminated {
+            assert_eq!(nu, format_nu(nu, Indentation::Default));
+            let newline = format!("{}\n", nu);
+            assert_eq!(newline, format_nu(&newline, Indentation::Default));
+        }
+    }
+
+    #[test]
+    fn keep_statement_newlines() {
+        let nu = "ls\n\n   ps  \n";
+        let expected = "ls\n\nps\n";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn blank_lines() {
+        let nu = "ls\n\n\n\nps\ndef f [] {\n\n  ls\n\n  # sizes\n  ps |\n\n  get size\n\n}\nlet r = {\n  a: 1\n\n  b: 2\n}\n";
+        let expected = "ls\n\nps\ndef f [] {\n  ls\n\n  # sizes\n  ps |\n  get size\n}\nlet r = {\n  a: 1\n\n  b: 2\n}\n";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+
+        let nu = "# nufmt: max_blank_lines=2\nls\n\n\n\nps\n\n\nlet x = 1";
+        let expected = "# nufmt: max_blank_lines=2\nls\n\n\nps\n\n\nlet x = 1";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+        let nu = "# nufmt: max_blank_lines=0\nls\n\nps";
+        let expected = "# nufmt: max_blank_lines=0\nls\nps";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn embedded_code_is_opt_in() {
+        let nu = "nu -c \"ls  |  length\"";
+        assert_eq!(nu, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn embedded_code_spacing() {
+        let nu = "# nufmt: embedded_code=true\n^nu -c \" ls  |  where name == 'a  b' \"";
+        let expected = "# nufmt: embedded_code=true\n^nu -c \"ls | where name == 'a  b'\"";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn closure_in_record_in_closure() {
+        let nu =
+            "$env.config = {hooks: {pre_prompt: [{||\nls | each {|x|\n{a: $x, b: {c: 1}}}\n}]}}";
+        let expected = "$env.config = {
+  hooks: {
+    pre_prompt: [
+      {||
+        ls | each {|x|
+          {a: $x, b: {c: 1}}
+        }
+      }
+    ]
+  }
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+        assert_eq!(expected, format_nu(expected, Indentation::Default));
+    }
+
+    #[test]
+    fn short_closures() {
+        let nu = "ls | each { |it, i| $it + $i } # sum\nlet h = [1, 2\n]\nls | each {|it|\n$it}\nls | each {|it| $it.name | str upcase | str trim | str replace \"a\" \"b\" | str length }\nls | where {|r| $r.size > 1kb } | each {|x| $x.name } | sort-by name | first 10 | str join";
+        let expected = "ls | each {|it, i| $it + $i } # sum
+let h = [
+  1,
+  2
+]
+ls | each {|it|
+  $it
+}
+ls
+  | each {|it|
+    $it.name | str upcase | str trim | str replace \"a\" \"b\" | str length
+  }
+ls
+  | where {|r| $r.size > 1kb }
+  | each {|x| $x.name }
+  | sort-by name
+  | first 10
+  | str join";
+        let formatted = format_at_80(nu);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_at_80(&formatted));
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn closure_params_are_trimmed() {
+        let nu = "{| a, b |\n$a}";
+        assert_eq!("{|a, b|\n  $a\n}", format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn pipes_kept_by_default() {
+        let nu = "ls\n  | where size > 1 |\n  first 3";
+        assert_eq!(nu, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn leading_pipes() {
+        let nu =
+            "# nufmt: pipe_placement=leading\nls |\nwhere size > 1 | first 3 |  # big\nget name |";
+        let expected =
+            "# nufmt: pipe_placement=leading\nls\n  | where size > 1 | first 3 | # big\nget name |";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn trailing_pipes() {
+        let nu = "# nufmt: pipe_placement=trailing\nls\n  | where size > 1 # big\n| first 3";
+        let expected = "# nufmt: pipe_placement=trailing\nls |\nwhere size > 1 # big\n  | first 3";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn comments_between_pipeline_stages() {
+        let nu = "def f [] {\nls |\n    # only big ones\n  where size > 1 # inline\n# then\n| first 3\n}";
+        let expected = "def f [] {
+  ls |
+  # only big ones
+  where size > 1 # inline
+    # then
+    | first 3
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn leading_pipes_skip_comment_lines() {
+        let nu =
+            "# nufmt: pipe_placement=leading\nls |\n# only big ones\n\n# really\nwhere size > 1";
+        let expected =
+            "# nufmt: pipe_placement=leading\nls\n  # only big ones\n  # really\n  | where size > 1";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+        assert!(invariants::audit(nu, expected).is_empty());
+    }
+
+    #[test]
+    fn multiline_parenthesis() {
+        let nu = "def f [] {\nlet x = (ls\n| where size > 1\n  | length)\n}";
+        let expected = "def f [] {
+  let x = (ls
+    | where size > 1
+    | length
+  )
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+        assert_eq!(expected, format_nu(expected, Indentation::Default));
+    }
+
+    #[test]
+    fn single_line_parenthesis() {
+        let nu = "let n = (ls | each {|f|\n$f.size} | math sum)";
+        let expected = "let n = (ls | each {|f|\n  $f.size\n} | math sum)";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    /// Chains of commands taking closures, as written in scripts
+    const CHAINS: &[&str] = &[
+        "1..100 | each {|i| $i * 2 } | where $it > 10",
+        "1..10\n| each {|i| {n: $i}}\n# only\n| where n > 2",
+        "generate {|i| if $i < 3 {{out: $i, next: ($i + 1)}}} 0 | each {|x| $x}",
+        "(1..10 | each {|i|\n$i} |\nmath sum)",
+        "seq 1 5 | reduce --fold 0 {|it, acc| $acc + $it} | into string",
+        "0..<$n | par-each {||\nsleep 1sec} | length",
+    ];
+
+    #[test]
+    fn chain_layout() {
+        let nu = "1..10 | each {|i| {n: $i}} | where n > 2 | get n";
+        let expected = "1..10 | each {|i| {n: $i} } | where n > 2 | get n";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+
+        let nu = "1..10 | each {|i|\n{n: $i}} | where n > 2 | get n";
+        let expected = "1..10 | each {|i|
+  {n: $i}
+} | where n > 2 | get n";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn long_pipelines() {
+        let nu = "ls | where size > 10kb | sort-by modified | reverse | first 10 | get name | str join\nls | length\ndef f [] {\n  open data.json | get items | where active | each {|it| $it.name } | sort | uniq\n}\nlet n = (ls | where type == dir | get name | each {|n| $n | str upcase } | str join)";
+        let expected = "ls
+  | where size > 10kb
+  | sort-by modified
+  | reverse
+  | first 10
+  | get name
+  | str join
+ls | length
+def f [] {
+  open data.json
+    | get items
+    | where active
+    | each {|it| $it.name }
+    | sort
+    | uniq
+}
+let n = (
+  ls | where type == dir | get name | each {|n| $n | str upcase } | str join
+)";
+        let formatted = format_at_80(nu);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_at_80(&formatted));
+        assert!(invariants::audit(nu, &formatted).is_empty());
+
+        let nu = "# nufmt: max_width=20 pipe_placement=trailing\nls | where size > 1kb | length";
+        let expected =
+            "# nufmt: max_width=20 pipe_placement=trailing\nls |\nwhere size > 1kb |\nlength";
+        let formatted = format_at_80(nu);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_at_80(&formatted));
+
+        let nu = "# nufmt: max_width=20\nls | where a | get b # c\nls | length";
+        let expected = "# nufmt: max_width=20\nls\n  | where a\n  | get b # c\nls | length";
+        assert_eq!(expected, format_at_80(nu));
+    }
+
+    #[test]
+    fn long_subexpressions() {
+        let nu = "let x = (1 + 2)\nlet y = (\nls\n)\nprint $\"(ls | where size > 10kb | get name | sort | uniq | str join) and (ls | length) more\"\ndef f [] {\n  let total = (open data.json | get items | where enabled | each {|it| $it.size } | math sum)\n  if ($total > 100 and $total < 1000 and ($env.LIMIT? | default 10) > 5 and $verbose) {\n    print $total\n  }\n}";
+        let expected = "let x = (1 + 2)
+let y = (
+  ls
+)
+print $\"(ls | where size > 10kb | get name | sort | uniq | str join) and (ls | length) more\"
+def f [] {
+  let total = (
+    open data.json
+    | get items
+    | where enabled
+    | each {|it| $it.size }
+    | math sum
+  )
+  if (
+    $total > 100 and $total < 1000 and ($env.LIMIT? | default 10) > 5 and $verbose
+  ) {
+    print $total
+  }
+}";
+        let formatted = format_at_80(nu);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_at_80(&formatted));
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn diff_friendly_preset() {
+        let nu = "# nufmt: preset=diff-friendly\nlet rec = {a: 1, b: [2, 3]}\nlet noted = [1, 2 # last\n]\ndef f [a, b] { $a }\nls | each {|x| $x | get a } | sort";
+        let expected = "# nufmt: preset=diff-friendly
+let rec = {
+  a: 1,
+  b: [
+    2,
+    3,
+  ],
+}
+let noted = [
+  1,
+  2 # last
+]
+def f [
+  a,
+  b
+] {
+  $a
+}
+ls
+  | each {|x|
+    $x
+      | get a
+  }
+  | sort";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+        assert!(invariants::audit(nu, &formatted).is_empty());
+
+        let nu = "# nufmt: preset=diff-friendly break_pipelines=false\nls | sort";
+        assert_eq!(nu, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn joined_pipelines() {
+        let nu = "# nufmt: preserve_line_breaks=false\nls |\n  where size > 1kb |\n  get name\nls\n| length # n\n| describe\n(ls\n  | get name)\nls | each {|x|\n  $x\n}\n| sort";
+        let expected = "# nufmt: preserve_line_breaks=false
+ls | where size > 1kb | get name
+ls | length # n
+  | describe
+(ls | get name)
+ls | each {|x|
+  $x
+}
+  | sort";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+        assert!(invariants::audit(nu, &formatted).is_empty());
+
+        let nu =
+            "# nufmt: preserve_line_breaks=false max_width=30\nls |\n  where size > 1kb | get name";
+        let expected = "# nufmt: preserve_line_breaks=false max_width=30\nls\n  | where size > 1kb\n  | get name";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn chain_with_leading_pipes() {
+        let nu = "# nufmt: pipe_placement=leading\n1..10 |\neach {|i| $i * 2} |\nwhere $it > 2";
+        let expected = "# nufmt: pipe_placement=leading
+1..10
+  | each {|i| $i * 2 }
+  | where $it > 2";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn chains_are_stable() {
+        let directives = [
+            "",
+            "# nufmt: pipe_placement=leading\n",
+            "# nufmt: pipe_placement=trailing\n",
+            "# nufmt: operator_placement=leading\n",
+        ];
+        for directive in &directives {
+            for chain in CHAINS {
+                let nu = format!("{}{}", directive, chain);
+                let formatted = format_nu(&nu, Indentation::Default);
+                assert_eq!(
+                    formatted,
+                    format_nu(&formatted, Indentation::Default),
+                    "{}",
+                    nu
+                );
+                assert_eq!(
+                    Vec::<invariants::Violation>::new(),
+                    invariants::audit(&nu, &formatted)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn attributes() {
+        let nu = "ls\n@example \"adds\" {   add 1 2 } --result 3  \n@search-terms [add sum]\n\n  @category math\ndef add [a, b] { $a + $b }";
+        let expected = "ls
+@example \"adds\" {   add 1 2 } --result 3
+@search-terms [add sum]
+
+@category math
+def add [a, b] {
+  $a + $b
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn multiline_attribute() {
+        let nu = "module m {\n@example \"a\" {\n  add 1 2\n}\nexport def f [] {}\n}";
+        let expected = "module m {
+  @example \"a\" {
+  add 1 2
+}
+  export def f [] {}
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn parse_time_statements() {
+        let nu = "const LIB = ($nu.default-config-dir | path join \"scripts\")\nsource   ($LIB | path join \"x.nu\")  \nuse std [log   assert]\noverlay use (path self | path dirname)/m.nu\nexport use a.nu {b: 1}";
+        let expected = "const LIB = ($nu.default-config-dir | path join \"scripts\")
+source   ($LIB | path join \"x.nu\")
+use std [log   assert]
+overlay use (path self | path dirname)/m.nu
+export use a.nu {b: 1}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn parse_time_statements_in_blocks() {
+        let nu = "def f [] {use  std log; log info  x}";
+        let expected = "def f [] {\n  use  std log; log info x\n}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn const_statements() {
+        let nu = "const a = {b: [1, 2]}; const  c  =  3\nconst d = (\n1 + 2)";
+        let expected = "const a = {b: [1, 2]}; const c = 3
+const d = (
+  1 + 2
+)";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn item_spacing() {
+        let nu = "# nufmt: item_spacing=true max_blank_lines=0\nuse std\ndef a [] {\n  ls\n\n  ps\n}\n\n\n\ndef b [] {}\nlet x = 1\n\nlet y = 2\nexport def c [] {}\n# doc\n@example \"x\" { f }\ndef f [] {}\nls |\nlength\nmodule m {\n  def d [] {}\n  def e [] {}\n}";
+        let expected = "# nufmt: item_spacing=true max_blank_lines=0
+use std
+
+def a [] {
+  ls
+  ps
+}
+
+def b [] {}
+
+let x = 1
+let y = 2
+
+export def c [] {}
+
+# doc
+@example \"x\" { f }
+def f [] {}
+
+ls |
+length
+
+module m {
+  def d [] {}
+  def e [] {}
+}";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+    }
+
+    #[test]
+    fn definition_blank_lines() {
+        let nu = "# nufmt: definition_blank_lines=1\ndef a [] {\n  ls\n\n  ps\n}\n\n\n\nexport def b [] {}\n# doc\n@example \"x\" { f }\ndef f [] {}\nalias ll = ls -l\nlet x = 1\ndef g [] {}\nexport const c = 1";
+        let expected = "# nufmt: definition_blank_lines=1
+def a [] {
+  ls
+
+  ps
+}
+
+export def b [] {}
+
+# doc
+@example \"x\" { f }
+def f [] {}
+
+alias ll = ls -l
+let x = 1
+def g [] {}
+export const c = 1";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+
+        let nu = "# nufmt: definition_blank_lines=0\ndef a [] {}\n\nexport alias b = ls\n\nls";
+        let expected = "# nufmt: definition_blank_lines=0\ndef a [] {}\nexport alias b = ls\n\nls";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+        let nu = "# nufmt: definition_blank_lines=2\ndef a [] {}\ndef b [] {}";
+        let expected = "# nufmt: definition_blank_lines=2\ndef a [] {}\n\n\ndef b [] {}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn comment_runs() {
+        let nu = "# nufmt: item_spacing=true\n# header\n\ndef a [] {}\n# one\n  # two\nlet x = 1\n# three\n\n# four\n@example \"x\" { b }\ndef b [] {}\n# end";
+        let expected = "# nufmt: item_spacing=true
+# header
+
+def a [] {}
+
+# one
+# two
+let x = 1
+# three
+
+# four
+@example \"x\" { b }
+def b [] {}
+
+# end";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+    }
+
+    #[test]
+    fn flag_grouping() {
+        let nu = "# nufmt: flag_grouping=true\nlet out = (^cargo build src --release\n --features a,b --target x)\n(git log\n  --oneline -n 5 main | lines)\n(^cargo build --release)\n(^ls -la\n -h)";
+        let expected = "# nufmt: flag_grouping=true
+let out = (^cargo build src
+  --release
+  --features a,b
+  --target x
+)
+(git log
+  --oneline
+  -n 5 main | lines
+)
+(^cargo build --release)
+(^ls -la
+  -h
+)";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn signature_comments() {
+        let nu = "def f [\nx: int, # the x\n  --flag (-f): string # what it does\n  # about y\n  y? # optional\n] {}";
+        let expected = "def f [
+  x: int, # the x
+  --flag (-f): string # what it does
+  # about y
+  y? # optional
+] {}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+
+        let nu = format!(
+            "# nufmt: signature_comment_alignment=true\n{}\nls # files",
+            nu
+        );
+        let expected = "# nufmt: signature_comment_alignment=true
+def f [
+  x: int,             # the x
+  --flag (-f): string # what it does
+  # about y
+  y?                  # optional
+] {}
+ls # files";
+        let formatted = format_nu(&nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+        assert!(invariants::audit(&nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn entry_comments() {
+        let nu = "{a: 1, # one\n b: 2}\nlet x = [\n1, # one\n  # about two\n2 # two\n]";
+        let expected = "{
+  a: 1, # one
+  b: 2
+}
+let x = [
+  1, # one
+  # about two
+  2 # two
+]";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn multiline_external_calls() {
+        let nu = "(^cargo build --release\n --features a,b)";
+        let expected = "(^cargo build --release\n  --features a,b\n)";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn closure_style() {
+        let nu = "ls | each { |x| $x.name }\ndo { ls }\nlet f = { || 1 }\nif true { ls }\ndo {||}";
+        let explicit = "# nufmt: closure_style=explicit
+ls | each {|x| $x.name }
+do {||
+  ls
+}
+let f = {|| 1 }
+if true {
+  ls
+}
+do {||}";
+        let implicit = "# nufmt: closure_style=implicit
+ls | each {|x| $x.name }
+do {
+  ls
+}
+let f = {|| 1 }
+if true {
+  ls
+}
+do {||}";
+        for expected in [explicit, implicit] {
+            let directive = expected.lines().next().unwrap();
+            let nu = format!("{}\n{}", directive, nu);
+            let formatted = format_nu(&nu, Indentation::Default);
+            assert_eq!(expected, formatted);
+            assert!(invariants::audit(&nu, &formatted).is_empty());
+        }
+    }
+
+    #[test]
+    fn compact_if_values() {
+        let nu = "let x = if $c {1} else if $d {[1, 2]} else {  {a: 1}  }\nlet e = if $c {} else { 2 }; ls\ndef f [] { let w = if true { 1 } else { 0 } }";
+        let expected = "let x = if $c { 1 } else if $d { [1, 2] } else { {a: 1} }
+let e = if $c {} else { 2 }; ls
+def f [] {
+  let w = if true { 1 } else { 0 }
+}";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn collection_spacing() {
+        let nu = "let x = if $c { [ 1, [] ] } else { {a: 1} }\nlet [ x, y ] = $p\n{f: {|x|$x}, g: if $c { {|| 1} } else { {} }}";
+        let expected = "let x = if $c { [1, []] } else { {a: 1} }
+let [x, y] = $p
+{
+  f: {|x| $x },
+  g: if $c { {|| 1} } else { {} }
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+
+        let nu = format!("# nufmt: bracket_spacing=true brace_spacing=true\n{}", nu);
+        let expected = "# nufmt: bracket_spacing=true brace_spacing=true
+let x = if $c { [ 1, [] ] } else { { a: 1 } }
+let [ x, y ] = $p
+{
+  f: {|x| $x },
+  g: if $c { {|| 1 } } else { {} }
+}";
+        let formatted = format_nu(&nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+        assert!(invariants::audit(&nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn records_and_blocks() {
+        let nu = "let r = {a: 1 b: 2}\nlet x = {\n  a: 1 b: {c: 2 d: 3}\n  e: 4\n}\nif $x { a: 1 }\ntry { f: 1 } catch {|e| $e }";
+        let expected = "let r = {a: 1 b: 2}
+let x = {
+  a: 1
+  b: {c: 2 d: 3}
+  e: 4
+}
+if $x {
+  a: 1
+}
+try {
+  f: 1
+} catch {|e| $e }";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn short_record_values() {
+        let long = "a".repeat(70);
+        let nu = format!("{{render: {{|| date now | format date \"%Y\" }}, when: if $c {{ 1 }} else {{ 2 }}, p: {{|x|$x}}, long: {{|| echo {} }}, multiline: {{||\n1}}}}", long);
+        let expected = format!(
+            "{{
+  render: {{|| date now | format date \"%Y\" }},
+  when: if $c {{ 1 }} else {{ 2 }},
+  p: {{|x| $x }},
+  long: {{||
+    echo {}
+  }},
+  multiline: {{||
+    1
+  }}
+}}",
+            long
+        );
+        let formatted = format_at_80(&nu);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_at_80(&formatted));
+        assert!(invariants::audit(&nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn list_fill() {
+        let nu = "# nufmt: list_fill=true\nlet shells = [bash, zsh, fish, nu, \"power shell\", elvish, xonsh, ion, 1.5, 0x1f, tcsh, dash, ksh, csh, oil]\n{a: [1 2\n3]}\ndef f [a, b] { [[1], [\"a very long string item\", 2]] }";
+        let expected = "# nufmt: list_fill=true
+let shells = [
+  bash, zsh, fish, nu, \"power shell\", elvish, xonsh, ion, 1.5, 0x1f, tcsh, dash,
+  ksh, csh, oil
+]
+{
+  a: [
+    1 2 3
+  ]
+}
+def f [a, b] {
+  [[1], [\"a very long string item\", 2]]
+}";
+        let formatted = format_at_80(nu);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_at_80(&formatted));
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn list_fill_columns() {
+        let nu = "# nufmt: list_fill=true fill_max_items=4 fill_min_item_width=5\nlet ports = [\n80, 443, 8080, 8443, 3000, 5432, 27017]\nlet names = [\na bb ccc dddd eeeee ffffff]";
+        let expected = "# nufmt: list_fill=true fill_max_items=4 fill_min_item_width=5
+let ports = [
+  80,   443,  8080, 8443,
+  3000, 5432, 27017
+]
+let names = [
+  a     bb    ccc   dddd
+  eeeee ffffff
+]";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn windows_paths() {
+        let nu = "cd  C:\\Users\\me\nls C:\\Program` `Files\\ | length\nlet p = C:\\a\\b; echo \"x\\\"y\" D:\\\n^C:\\tools\\app.exe /a:b\n{dir: C:\\temp}";
+        let expected = "cd C:\\Users\\me
+ls C:\\Program` `Files\\ | length
+let p = C:\\a\\b; echo \"x\\\"y\" D:\\
+^C:\\tools\\app.exe /a:b
+{dir: C:\\temp}";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn non_ascii_names() {
+        let nu = "def grüße [--größe: int] { ls }\ngrüße  --größe 3 |   длина\nlet 名前 = \"値\"";
+        let expected = "def grüße [--größe: int] {
+  ls
+}
+grüße --größe 3 | длина
+let 名前 = \"値\"";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn wide_characters_count_twice() {
+        let narrow = "n".repeat(40);
+        let wide = "名".repeat(40);
+        let nu = format!("{{a: {{|| echo {} }}, b: {{|| echo {} }}}}", narrow, wide);
+        let expected = format!(
+            "{{\n  a: {{|| echo {} }},\n  b: {{||\n    echo {}\n  }}\n}}",
+            narrow, wide
+        );
+        assert_eq!(expected, format_at_80(&nu));
+    }
+
+    #[test]
+    fn trailing_comment_spacing() {
+        let nu = "ls    # files\n# nufmt: comment_min_spaces_before=2\nps # processes\n  # alone\nlet a = 1   # one";
+        let expected = "ls # files
+# nufmt: comment_min_spaces_before=2
+ps  # processes
+# alone
+let a = 1  # one";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn colons_starting_lines() {
+        let nu = "# c\n:x\nls\n:x";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(nu, formatted);
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn glued_colons() {
+        let nu = "echo http://a.b a:b ($x):b\n(http get\nhttps://a.b)\n[a:b]\nlet x:int = 1\nlet r = {a:1, b:{c:2}\nd:3}\nmut y:string = $\"a\":b\n{a:1}\ndef f [x:int] {}";
+        let expected = "echo http://a.b a:b ($x):b
+(http get
+  https://a.b
+)
+[a:b]
+let x: int = 1
+let r = {
+  a: 1,
+  b: {c: 2}
+  d: 3
+}
+mut y: string = $\"a\":b
+{a: 1}
+def f [x: int] {}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn long_tokens() {
+        let nu = "(http get https://example.com/a/very/long/path/to/some/resource/on/the/server?q=1 | from json)\n[a.nu /home/user/projects/some/deeply/nested/directory/with/a/longer/name/file.nu]";
+        assert_eq!(
+            "(\n  http get https://example.com/a/very/long/path/to/some/resource/on/the/server?q=1\n  | from json\n)\n[\n  a.nu /home/user/projects/some/deeply/nested/directory/with/a/longer/name/file.nu\n]",
+            format_at_80(nu)
+        );
+
+        let nu = format!(
+            "# nufmt: wrap_long_tokens=true\n{}\nls {}",
+            nu,
+            "x".repeat(90)
+        );
+        let expected = format!(
+            "# nufmt: wrap_long_tokens=true
+(
+  http get
+  https://example.com/a/very/long/path/to/some/resource/on/the/server?q=1
+  | from json
+)
+[
+  a.nu
+  /home/user/projects/some/deeply/nested/directory/with/a/longer/name/file.nu
+]
+ls {}",
+            "x".repeat(90)
+        );
+        assert_eq!(expected, format_at_80(&nu));
+    }
+
+    #[test]
+    fn disabled_rules() {
+        let nu = "# nufmt: disable=colon-spacing disable=comment-spacing\nlet r = {a:1, b: 2}    # kept\nlet x:int = 1\n# nufmt: enable=colon-spacing\nlet y:int = 2   # still kept\ndef f [] {\n  # nufmt: disable=none\n  {c:3}   # spaced\n}\n{d:4}  # kept";
+        let expected = "# nufmt: disable=colon-spacing disable=comment-spacing
+let r = {a:1, b: 2}    # kept
+let x:int = 1
+# nufmt: enable=colon-spacing
+let y: int = 2   # still kept
+def f [] {
+  # nufmt: disable=none
+  {c: 3} # spaced
+}
+{d: 4}  # kept";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn operator_spacing() {
+        let nu = "let x = (1    +    2)\nif $a  ==  $b and  $c {\n  $d  in  [1]\n}";
+        let expected = "let x = (1 + 2)\nif $a == $b and $c {\n  $d in [1]\n}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+
+        // only the operators keep their spaces, the rest of the line is normalized
+        let nu = format!("# nufmt: disable=operator-spacing\n{nu}\nlet   y =  {{a:1}}");
+        let expected = "# nufmt: disable=operator-spacing\nlet x = (1    +    2)\nif $a  ==  $b and  $c {\n  $d  in  [1]\n}\nlet y = {a: 1}";
+        let formatted = format_nu(&nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+    }
+
+    #[test]
+    fn table_alignment() {
+        // tables written on one line stay on it if they fit, like any list
+        let nu = "# nufmt: table_alignment=true\nlet t = [[name, size]; [a.nu, 10], [\"long name.nu\", 2000]]\n[[a b]; [1 2] [3 4]] | to md\n[[a b]; [1 [2]]]";
+        assert_eq!(nu, format_nu(nu, Indentation::Default));
+
+        let nu = "# nufmt: table_alignment=true\nlet t = [\n[name, size]; [a.nu, 10], [\"long name.nu\", 2000]]\n[[a b];\n[1 2] [3 4]] | to md\n[[a b]; [1 [2]]]";
+        let expected = "# nufmt: table_alignment=true
+let t = [
+  [name,           size];
+  [a.nu,           10],
+  [\"long name.nu\", 2000]
+]
+[
+  [a b];
+  [1 2]
+  [3 4]
+] | to md
+[[a b]; [1 [2]]]";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+
+        // neither long cells nor wide rows are padded
+        let long = "x".repeat(30);
+        let nu = format!("# nufmt: table_alignment=true\n[[a b]; [{} 1] [2 3]]", long);
+        assert_eq!(nu, format_nu(&nu, Indentation::Default));
+        let nu = format!(
+            "# nufmt: table_alignment=true\n[[a b];\n[{} 1] [2 3]]",
+            long
+        );
+        let expected = format!(
+            "# nufmt: table_alignment=true\n[\n  [a b];\n  [{} 1]\n  [2 3]\n]",
+            long
+        );
+        assert_eq!(expected, format_nu(&nu, Indentation::Default));
+        let row = vec!["x".repeat(19); 4].join(" ");
+        let nu = format!("# nufmt: table_alignment=true\n[[a b c d]; [{}]]", row);
+        let expected = format!(
+            "# nufmt: table_alignment=true\n[\n  [a b c d];\n  [{}]\n]",
+            row
+        );
+        assert_eq!(expected, format_at_80(&nu));
+    }
+
+    #[test]
+    fn preserved_alignment() {
+        let nu = "let config = {\nname:    \"nufmt\",\n    version: \"0.1\",\nauthors: [a]\n}\nlet a   = 1 # one\nlet bcd = 2     # two\nlet e = 3";
+        let collapsed = "let config = {
+  name: \"nufmt\",
+  version: \"0.1\",
+  authors: [a]
+}
+let a = 1 # one
+let bcd = 2 # two
+let e = 3";
+        assert_eq!(collapsed, format_nu(nu, Indentation::Default));
+
+        let nu = format!("# nufmt: preserve_alignment=true\n{}", nu);
+        let expected = "# nufmt: preserve_alignment=true
+let config = {
+  name:    \"nufmt\",
+  version: \"0.1\",
+  authors: [a]
+}
+let a   = 1 # one
+let bcd = 2 # two
+let e = 3";
+        let formatted = format_nu(&nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+
+        // the aligned comments keep their minimum spacing
+        let nu = "# nufmt: preserve_alignment=true comment_min_spaces_before=2\nls     # files\nps -la # processes\nlet x = 1 # one";
+        let expected = "# nufmt: preserve_alignment=true comment_min_spaces_before=2
+ls      # files
+ps -la  # processes
+let x = 1  # one";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn if_statements_are_expanded() {
+        let nu = "if $c { 1 } else { 2 }\nlet x = if $c {\n1 } else { 2 }";
+        let expected = "if $c {
+  1
+} else {
+  2
+}
+let x = if $c {
+  1
+} else {
+  2
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn items_are_tight_by_default() {
+        let nu = "def a [] {}\ndef b [] {}\nlet x = 1";
+        assert_eq!(nu, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn match_arms() {
+        let nu =
+            "match $x {\n1 | 2 | 3 if $y > 2 => { \"small\" }\n  {a: $a} => $a,\n_ => \"other\"\n}";
+        let expected = "match $x {
+  1 | 2 | 3 if $y > 2 => {
+    \"small\"
+  }
+  {a: $a} => $a,
+  _ => \"other\"
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn multiline_match_patterns() {
+        let nu = "match ($x | get a) {\n# small ones\n1\n| 2\n| 3\nif $y > 2\n=> { \"small\" }\n_ => {\nmatch $z {\n4\n=> 4\n}\n}\n}";
+        let expected = "match ($x | get a) {
+  # small ones
+  1
+    | 2
+    | 3
+    if $y > 2 => {
+    \"small\"
+  }
+  _ => {
+    match $z {
+      4 => 4
+    }
+  }
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+        assert_eq!(expected, format_nu(expected, Indentation::Default));
+    }
+
+    #[test]
+    fn destructuring_patterns() {
+        let nu = "let {a,b} = $r\nlet [ x, y ] = [1, 2]\nmut {a: {b: $c},\n d} = $r";
+        let expected = "let {a, b} = $r
+let [x, y] = [1, 2]
+mut {a: {b: $c}, d} = $r";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn match_patterns() {
+        let nu = "match $r {\n{ a, b: 2 } => $a,\n[$h, ..$rest] | [$h] => { $h }\n}";
+        let expected = "match $r {
+  {a, b: 2} => $a,
+  [$h, ..$rest] | [$h] => {
+    $h
+  }
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    /// Strings holding the other kind of quotes, each kept as written
+    const QUOTING: &[&str] = &[
+        "\"it's\"",
+        "'say \"hi\"'",
+        "'it\"s' | str length",
+        "\"a \\\"it's\\\" b\"",
+        "\"'\" + '\"'",
+        "$\"it's (\"a\" + 'b')\"",
+        "$\"('a\"b')\"",
+        "$'say \"($x)\"'",
+        "$'a ($x | str join \"'\") [ b  {c} \\'",
+        "echo 'a  #  b'",
+        "^echo 'x,y: z' \"x,y: z\"",
+        "let s = 'a {b} [c]'",
+        "ls foo'bar baz'",
+    ];
+
+    #[test]
+    fn quoting() {
+        for nu in QUOTING {
+            assert_eq!(*nu, format_nu(nu, Indentation::Default));
+            let quoted = format!("{{k: {}}}", nu);
+            let expected = format!("{{k: {}}}", nu);
+            assert_eq!(expected, format_nu(&quoted, Indentation::Default));
+        }
+
+        let nu = "echo 'a  #  b'   # c";
+        assert_eq!("echo 'a  #  b' # c", format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn ansi_escape_sequences() {
+        let nu = "let red = \"\u{1b}[31m\"; print $\"\u{1b}[1m($x)\u{1b}[0m\" '\u{1b}[0m'";
+        assert_eq!(nu, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn parameters() {
+        let nu =
+            "def f [x=5, y ?: int, z? : int = 3, ...rest : string, --flag (-f) : string=\"a\"] {}";
+        let expected = "def f [
+  x = 5,
+  y?: int,
+  z?: int = 3,
+  ...rest: string,
+  --flag (-f): string = \"a\"
+] {}";
+        assert_eq!(expected, format_at_80(nu));
+        let expected =
+            "def f [x = 5, y?: int, z?: int = 3, ...rest: string, --flag (-f): string = \"a\"] {}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn short_signatures() {
+        let nu = "def f [x: int] {}\ndef greet [name: string,--loud(-l)] {}\ndef g [\n  x=5\n  y?: int\n]: string -> table { ls }";
+        let expected = "def f [x: int] {}
+def greet [name: string, --loud (-l)] {}
+def g [x = 5 y?: int]: string -> table {
+  ls
+}";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn only_parameters_are_normalized() {
+        let nu = "export def f [x= 5] { let l = [a=b] }";
+        let expected = "export def f [x = 5] {
+  let l = [a=b]
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn signature_types() {
+        let nu = "def foo [] : string->table { ls }\ndef bar []: nothing -> record<a: int,b: list<string>> {\n{}\n}";
+        let expected = "def foo []: string->table {
+  ls
+}
+def bar []: nothing -> record<a: int, b: list<string>> {
+  {}
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn signature_type_lists() {
+        let nu = "def bar [x: int]: [string -> table, nothing -> table<name: string>] {$x}";
+        let expected = "def bar [x: int]: [string -> table, nothing -> table<name: string>] {
+  $x
+}";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+
+        // short lists are joined, long ones get a type per line
+        let nu = "def f []: [\n  string -> table,\n  nothing -> int\n] {}\ndef g []: [string -> table, nothing -> table<name: string, size: filesize>, int -> string, record -> list<string>] {}";
+        let expected = "def f []: [string -> table, nothing -> int] {}
+def g []: [
+  string -> table,
+  nothing -> table<name: string, size: filesize>,
+  int -> string,
+  record -> list<string>
+] {}";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn leading_operators() {
+        let nu = "# nufmt: operator_placement=leading\nlet ok = ($a and\n$b or # c\n$c) and\n$d";
+        let expected =
+            "# nufmt: operator_placement=leading\nlet ok = ($a\n  and $b or # c\n  $c\n) and\n$d";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+
+        let nu = "# nufmt: operator_placement=leading\n(1 +\n# two\n2)";
+        let formatted = format_nu(nu, Indentation::Default);
+        assert_eq!(
+            "# nufmt: operator_placement=leading\n(1\n  # two\n  + 2\n)",
+            formatted
+        );
+        assert!(invariants::audit(nu, &formatted).is_empty());
+    }
+
+    #[test]
+    fn trailing_operators() {
+        let nu = "# nufmt: operator_placement=trailing\nlet n = (1\n  + 2 # c\n  - 3\n * 4)";
+        let expected =
+            "# nufmt: operator_placement=trailing\nlet n = (1 +\n  2 # c\n  - 3 *\n  4\n)";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn interpolation_with_inner_quotes() {
+        let nu = "$\"names: ($names | str join \", \")\"";
+        assert_eq!(nu, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn interpolation_spacing() {
+        let nu = "# nufmt: interpolation_spacing=true\nprint $\"a  ( $x   + 1 )  b\"";
+        let expected = "# nufmt: interpolation_spacing=true\nprint $\"a  ($x + 1)  b\"";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn single_quoted_interpolation_spacing() {
+        let nu = "# nufmt: interpolation_spacing=true\nprint $'a  ( $x | str join \"'\"  ) \\' [1]";
+        let expected =
+            "# nufmt: interpolation_spacing=true\nprint $'a  ($x | str join \"'\") \\' [1]";
+        assert_eq!(expected, format_nu(nu, Indentation::Default));
+    }
+
+    #[test]
+    fn interpolations_are_measured_once_normalized() {
+        let nu = "(print $\"file: (   $f.name   ) has size (   $f.size   ) and was modified (   $f.modified   )\")\n{render: {|| print $\"(   $f.name   ) is (   $f.size   ) big, see (   $f.modified   )\" }}";
+        let expected = "# nufmt: wrap_long_tokens=true interpolation_spacing=true
+(print $\"file: ($f.name) has size ($f.size) and was modified ($f.modified)\")
+{
+  render: {|| print $\"($f.name) is ($f.size) big, see ($f.modified)\" }
+}";
+        let directive = expected.lines().next().unwrap();
+        let formatted = format_nu(&format!("{}\n{}", directive, nu), Indentation::Default);
+        assert_eq!(expected, formatted);
+        assert_eq!(formatted, format_nu(&formatted, Indentation::Default));
+    }
+}
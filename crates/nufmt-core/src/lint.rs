@@ -0,0 +1,635 @@
+//!
+//! Diagnostics reported about nu without changing it.
+//!
+//! Besides the problems formatting does not fix, the [layout] rules report what it would change,
+//! for code bases adopting the formatter gradually.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+use crate::diff::matching_lines;
+use crate::lexer::{display_width, is_command_position, tokenize, Span, TokenKind};
+use crate::{format_nu, Indentation};
+
+/// The width above which [line_length] reports a line
+pub const DEFAULT_MAX_LINE_WIDTH: usize = 100;
+
+/// The keywords of nu, which are always lowercase
+pub const KEYWORDS: &[&str] = &[
+    "alias",
+    "break",
+    "catch",
+    "const",
+    "continue",
+    "def",
+    "else",
+    "export",
+    "export-env",
+    "extern",
+    "for",
+    "hide",
+    "hide-env",
+    "if",
+    "let",
+    "loop",
+    "match",
+    "module",
+    "mut",
+    "overlay",
+    "return",
+    "source",
+    "source-env",
+    "try",
+    "use",
+    "where",
+    "while",
+];
+
+/// The rules reporting diagnostics, by name
+pub const RULES: &[&str] = &[
+    "keyword-casing",
+    "unsupported-construct",
+    "indentation",
+    "line-length",
+    "trailing-whitespace",
+    "mixed-indentation",
+];
+
+/// How much a rule matters, set per rule in lint mode
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Severity {
+    /// The rule is not reported
+    Off,
+    /// The rule is reported without failing
+    Warning,
+    /// The rule is reported and fails the run
+    Error,
+}
+
+impl Severity {
+    /// Parses `off`, `warn` or `error`
+    pub fn parse(name: &str) -> Option<Severity> {
+        match name {
+            "off" => Some(Severity::Off),
+            "warn" | "warning" => Some(Severity::Warning),
+            "error" => Some(Severity::Error),
+            _ => None,
+        }
+    }
+
+    /// The label printed before messages
+    pub fn label(self) -> &'static str {
+        match self {
+            Severity::Off => "off",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// Parses a `rule=severity` setting, `None` if the rule is unknown or the severity invalid
+pub fn parse_severity(setting: &str) -> Option<(&'static str, Severity)> {
+    let (rule, severity) = setting.split_once('=')?;
+    let rule = RULES.iter().find(|known| **known == rule.trim())?;
+    Some((rule, Severity::parse(severity.trim())?))
+}
+
+///
+/// # The violations recorded when adopting lint mode
+///
+/// A baseline lists the diagnostics of a code base at some point, which are then no longer
+/// reported. Each one is recorded by file, rule and the trimmed text of its line rather than by
+/// line number, so editing other lines does not bring it back. It is written one diagnostic per
+/// line, `file<TAB>rule<TAB>line text`.
+///
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Baseline {
+    /// How many times a diagnostic is recorded
+    counts: HashMap<(String, String, String), usize>,
+}
+
+impl Baseline {
+    /// Parses a baseline file, lines not holding three fields are ignored
+    pub fn parse(text: &str) -> Baseline {
+        let mut baseline = Baseline::default();
+        for line in text.lines() {
+            let mut fields = line.splitn(3, '\t');
+            if let (Some(file), Some(rule), Some(source)) =
+                (fields.next(), fields.next(), fields.next())
+            {
+                *baseline
+                    .counts
+                    .entry((file.to_string(), rule.to_string(), source.to_string()))
+                    .or_default() += 1;
+            }
+        }
+        baseline
+    }
+
+    /// The baseline lines recording the diagnostics of `nu`, written in `file`
+    pub fn record(file: &str, nu: &str, diagnostics: &[Diagnostic]) -> String {
+        diagnostics
+            .iter()
+            .map(|diagnostic| {
+                format!(
+                    "{}\t{}\t{}\n",
+                    file,
+                    diagnostic.rule,
+                    source_line(nu, diagnostic)
+                )
+            })
+            .collect()
+    }
+
+    /// Removes the diagnostics recorded in the baseline, each record hides one diagnostic
+    pub fn filter(
+        &mut self,
+        file: &str,
+        nu: &str,
+        diagnostics: Vec<Diagnostic>,
+    ) -> Vec<Diagnostic> {
+        diagnostics
+            .into_iter()
+            .filter(|diagnostic| {
+                let key = (
+                    file.to_string(),
+                    diagnostic.rule.to_string(),
+                    source_line(nu, diagnostic).to_string(),
+                );
+                match self.counts.get_mut(&key) {
+                    Some(count) if *count > 0 => {
+                        *count -= 1;
+                        false
+                    }
+                    _ => true,
+                }
+            })
+            .collect()
+    }
+}
+
+/// The trimmed line of a diagnostic
+fn source_line<'a>(nu: &'a str, diagnostic: &Diagnostic) -> &'a str {
+    nu.lines()
+        .nth(diagnostic.line - 1)
+        .unwrap_or_default()
+        .trim()
+}
+
+/// A problem found in the source
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Diagnostic {
+    /// The name of the rule reporting the problem
+    pub rule: &'static str,
+    pub span: Span,
+    /// 1-based line of the start of the span
+    pub line: usize,
+    /// 1-based column of the start of the span, in characters
+    pub column: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Creates a diagnostic, computing its line and column in the script of `lines`
+    pub fn new(lines: &LineIndex, rule: &'static str, span: Span, message: String) -> Self {
+        let (line, column) = lines.line_column(span.start);
+        Diagnostic {
+            rule,
+            span,
+            line,
+            column,
+            message,
+        }
+    }
+}
+
+/// The 1-based line and column of a byte offset
+pub fn line_column(nu: &str, offset: usize) -> (usize, usize) {
+    let before = &nu[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+///
+/// # The starts of the lines of a script
+///
+/// Built once for a script, so that locating many offsets in it, a diagnostic or a folding range
+/// for each token, does not scan the script from its start every time.
+///
+#[derive(Debug, Clone)]
+pub struct LineIndex<'a> {
+    nu: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(nu: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(nu.match_indices('\n').map(|(newline, _)| newline + 1))
+            .collect();
+        LineIndex { nu, starts }
+    }
+
+    /// The byte offset of the start of a 1-based line
+    pub fn line_start(&self, line: usize) -> usize {
+        self.starts[line - 1]
+    }
+
+    /// The 1-based line and column of a byte offset, see [line_column]
+    pub fn line_column(&self, offset: usize) -> (usize, usize) {
+        let line = self.starts.partition_point(|&start| start <= offset);
+        let start = self.line_start(line);
+        (line, self.nu[start..offset].chars().count() + 1)
+    }
+}
+
+///
+/// # Escapes control characters before printing text to a terminal
+///
+/// Source text quoted in messages may hold raw ANSI escape sequences, common in prompt
+/// configurations, which the terminal would interpret. They are written as `\u{1b}` instead.
+///
+pub fn terminal_safe(text: &str) -> Cow<'_, str> {
+    let unsafe_char = |c: char| c.is_control() && c != '\t';
+    if !text.chars().any(unsafe_char) {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(
+        text.chars()
+            .map(|c| match c {
+                c if unsafe_char(c) => c.escape_default().to_string(),
+                c => c.to_string(),
+            })
+            .collect(),
+    )
+}
+
+///
+/// # Reports keywords that are not lowercase
+///
+/// Only words in command position are reported, so `IF` as an argument or a record key is fine.
+/// This catches scripts pasted from shells with case insensitive keywords.
+///
+pub fn keyword_casing(nu: &str) -> Vec<Diagnostic> {
+    let lines = LineIndex::new(nu);
+    miscased_keywords(nu)
+        .into_iter()
+        .map(|(span, keyword)| {
+            let message = format!(
+                "keyword `{}` should be written `{}`",
+                &nu[span.start..span.end],
+                keyword
+            );
+            Diagnostic::new(&lines, "keyword-casing", span, message)
+        })
+        .collect()
+}
+
+///
+/// # Lowercases the keywords reported by [keyword_casing]
+///
+/// This is opt-in as changing the case of a word changes which command is called.
+///
+pub fn fix_keyword_casing(nu: &str) -> String {
+    apply_edits(nu, miscased_keywords(nu))
+}
+
+/// The rules [fix] can apply
+pub const FIXABLE: &[&str] = &["indentation", "trailing-whitespace", "keyword-casing"];
+
+///
+/// # Applies the fixes of some rules only
+///
+/// Every `rules` must be [FIXABLE]. Only the text the rules report is changed, the rest of the nu
+/// is kept as written, e.g. to reindent a file without reformatting it.
+///
+pub fn fix(nu: &str, rules: &[&str], indent: Indentation) -> String {
+    let mut edits = Vec::new();
+    if rules.contains(&"indentation") {
+        edits.extend(reindentations(nu, indent));
+    }
+    if rules.contains(&"trailing-whitespace") {
+        let removals = trailing_whitespace(nu).into_iter();
+        edits.extend(removals.map(|diagnostic| (diagnostic.span, String::new())));
+    }
+    if rules.contains(&"keyword-casing") {
+        edits.extend(miscased_keywords(nu));
+    }
+    edits.sort_by_key(|(span, _)| span.start);
+    apply_edits(nu, edits)
+}
+
+/// Replaces the spans of `edits`, sorted by start, an edit overlapping the previous one is dropped
+fn apply_edits(nu: &str, edits: Vec<(Span, String)>) -> String {
+    let mut fixed = String::with_capacity(nu.len());
+    let mut end = 0;
+    for (span, replacement) in edits {
+        if span.start < end {
+            continue;
+        }
+        fixed.push_str(&nu[end..span.start]);
+        fixed.push_str(&replacement);
+        end = span.end;
+    }
+    fixed.push_str(&nu[end..]);
+    fixed
+}
+
+///
+/// # Reports the layout problems formatting would fix
+///
+/// Runs [indentation], [line_length], [trailing_whitespace] and [mixed_indentation], the
+/// diagnostics are sorted by position.
+///
+pub fn layout(nu: &str, indent: Indentation, max_width: usize) -> Vec<Diagnostic> {
+    let mut diagnostics = indentation(nu, indent);
+    diagnostics.extend(line_length(nu, max_width));
+    diagnostics.extend(trailing_whitespace(nu));
+    diagnostics.extend(mixed_indentation(nu));
+    diagnostics.sort_by_key(|diagnostic| diagnostic.span.start);
+    diagnostics
+}
+
+///
+/// # Reports lines indented differently than the formatter would
+///
+/// The nu is formatted and the lines left unchanged but for their indentation are compared, lines
+/// formatting changes otherwise are not reported here.
+///
+pub fn indentation(nu: &str, indent: Indentation) -> Vec<Diagnostic> {
+    let lines = LineIndex::new(nu);
+    reindentations(nu, indent)
+        .into_iter()
+        .map(|(span, expected)| {
+            let message = format!(
+                "indented with {} instead of {}",
+                describe_whitespace(&nu[span.start..span.end]),
+                describe_whitespace(&expected)
+            );
+            Diagnostic::new(&lines, "indentation", span, message)
+        })
+        .collect()
+}
+
+/// The indentation of the lines [indentation] reports, with the one they should have
+fn reindentations(nu: &str, indent: Indentation) -> Vec<(Span, String)> {
+    let formatted = format_nu(nu, indent);
+    let old: Vec<&str> = nu.lines().collect();
+    let new: Vec<&str> = formatted.lines().collect();
+    let trimmed = |lines: &[&'_ str]| -> Vec<String> {
+        lines.iter().map(|line| line.trim().to_string()).collect()
+    };
+    let (old_trimmed, new_trimmed) = (trimmed(&old), trimmed(&new));
+    let old_refs: Vec<&str> = old_trimmed.iter().map(String::as_str).collect();
+    let new_refs: Vec<&str> = new_trimmed.iter().map(String::as_str).collect();
+
+    let lines = LineIndex::new(nu);
+    matching_lines(&old_refs, &new_refs)
+        .into_iter()
+        .filter(|(o, _)| !old_trimmed[*o].is_empty())
+        .filter_map(|(o, n)| {
+            let found = leading_whitespace(old[o]);
+            let expected = leading_whitespace(new[n]);
+            if found == expected {
+                return None;
+            }
+            let start = lines.line_start(o + 1);
+            let span = Span {
+                start,
+                end: start + found.len(),
+            };
+            Some((span, expected.to_string()))
+        })
+        .collect()
+}
+
+/// Reports the lines wider than `max_width` characters
+pub fn line_length(nu: &str, max_width: usize) -> Vec<Diagnostic> {
+    let lines = LineIndex::new(nu);
+    let mut start = 0;
+    let mut diagnostics = Vec::new();
+    for line in nu.split('\n') {
+        let width = display_width(line.trim_end_matches('\r'));
+        if width > max_width {
+            let span = Span {
+                start,
+                end: start + line.len(),
+            };
+            let message = format!("line is {} characters wide, more than {}", width, max_width);
+            diagnostics.push(Diagnostic::new(&lines, "line-length", span, message));
+        }
+        start += line.len() + 1;
+    }
+    diagnostics
+}
+
+/// Reports the spaces and tabs ending lines, strings spanning several lines excepted
+pub fn trailing_whitespace(nu: &str) -> Vec<Diagnostic> {
+    let lines = LineIndex::new(nu);
+    let tokens = tokenize(nu);
+    (0..tokens.len())
+        .filter(|i| tokens[*i].kind == TokenKind::Whitespace)
+        .filter(|i| {
+            tokens
+                .get(i + 1)
+                .is_none_or(|next| next.kind == TokenKind::Newline)
+        })
+        .map(|i| {
+            let message = "trailing whitespace".to_string();
+            Diagnostic::new(&lines, "trailing-whitespace", tokens[i].span, message)
+        })
+        .collect()
+}
+
+/// Reports the lines indented with both tabs and spaces
+pub fn mixed_indentation(nu: &str) -> Vec<Diagnostic> {
+    let lines = LineIndex::new(nu);
+    let tokens = tokenize(nu);
+    (0..tokens.len())
+        .filter(|i| tokens[*i].kind == TokenKind::Whitespace)
+        .filter(|i| *i == 0 || tokens[i - 1].kind == TokenKind::Newline)
+        .filter(|i| {
+            let text = tokens[*i].text(nu);
+            text.contains(' ') && text.contains('\t')
+        })
+        .map(|i| {
+            let message = "indented with both tabs and spaces".to_string();
+            Diagnostic::new(&lines, "mixed-indentation", tokens[i].span, message)
+        })
+        .collect()
+}
+
+fn leading_whitespace(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// `2 spaces`, `1 tab and 2 spaces` or `no indentation`
+fn describe_whitespace(whitespace: &str) -> String {
+    let count = |c: char, name: &str| match whitespace.matches(c).count() {
+        0 => None,
+        1 => Some(format!("1 {}", name)),
+        n => Some(format!("{} {}s", n, name)),
+    };
+    match (count('\t', "tab"), count(' ', "space")) {
+        (Some(tabs), Some(spaces)) => format!("{} and {}", tabs, spaces),
+        (Some(counted), None) | (None, Some(counted)) => counted,
+        (None, None) => "no indentation".to_string(),
+    }
+}
+
+fn miscased_keywords(nu: &str) -> Vec<(Span, String)> {
+    let tokens = tokenize(nu);
+    (0..tokens.len())
+        .filter(|i| tokens[*i].kind == TokenKind::Word)
+        .filter_map(|i| {
+            let text = tokens[i].text(nu);
+            let keyword = text.to_lowercase();
+            let miscased = text != keyword && KEYWORDS.contains(&keyword.as_str());
+            if miscased && is_command_position(nu, &tokens, i) {
+                Some((tokens[i].span, keyword))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reports_miscased_keywords() {
+        let nu = "IF $x {\n  echo IF\n} Else {\n  Def: 1\n}";
+        let diagnostics = keyword_casing(nu);
+        let found: Vec<(usize, usize, &str)> = diagnostics
+            .iter()
+            .map(|d| (d.line, d.column, &nu[d.span.start..d.span.end]))
+            .collect();
+        assert_eq!(vec![(1, 1, "IF"), (3, 3, "Else")], found);
+        assert_eq!(
+            "keyword `IF` should be written `if`",
+            diagnostics[0].message
+        );
+    }
+
+    #[test]
+    fn fixes_only_keywords_in_command_position() {
+        let nu = "export DEF foo [] { echo DEF }";
+        assert_eq!("export def foo [] { echo DEF }", fix_keyword_casing(nu));
+    }
+
+    #[test]
+    fn reports_layout_problems() {
+        let nu = "def f [] {\n    ls \n\t  ps\n}\n# a very long comment\n";
+        let found: Vec<(&str, usize, usize, String)> = layout(nu, Indentation::Default, 16)
+            .into_iter()
+            .map(|d| (d.rule, d.line, d.column, d.message))
+            .collect();
+        let expected = vec![
+            (
+                "indentation",
+                2,
+                1,
+                "indented with 4 spaces instead of 2 spaces",
+            ),
+            ("trailing-whitespace", 2, 7, "trailing whitespace"),
+            (
+                "indentation",
+                3,
+                1,
+                "indented with 1 tab and 2 spaces instead of 2 spaces",
+            ),
+            (
+                "mixed-indentation",
+                3,
+                1,
+                "indented with both tabs and spaces",
+            ),
+            (
+                "line-length",
+                5,
+                1,
+                "line is 21 characters wide, more than 16",
+            ),
+        ];
+        let expected: Vec<(&str, usize, usize, String)> = expected
+            .into_iter()
+            .map(|(rule, line, column, message)| (rule, line, column, message.to_string()))
+            .collect();
+        assert_eq!(expected, found);
+    }
+
+    #[test]
+    fn formatted_nu_has_no_layout_problems() {
+        let nu = "def f [] {\n  ls\n}\n";
+        assert!(layout(nu, Indentation::Default, DEFAULT_MAX_LINE_WIDTH).is_empty());
+    }
+
+    #[test]
+    fn fixes_selected_rules_only() {
+        let nu = "IF $x {\n      ls  \n}\nlet a = [1,2]  ";
+        assert_eq!(
+            "IF $x {\n  ls  \n}\nlet a = [1,2]  ",
+            fix(nu, &["indentation"], Indentation::Default)
+        );
+        assert_eq!(
+            "if $x {\n      ls\n}\nlet a = [1,2]",
+            fix(
+                nu,
+                &["trailing-whitespace", "keyword-casing"],
+                Indentation::Default
+            )
+        );
+    }
+
+    #[test]
+    fn severities() {
+        assert_eq!(
+            Some(("line-length", Severity::Warning)),
+            parse_severity("line-length=warn")
+        );
+        assert_eq!(None, parse_severity("line-width=warn"));
+        assert_eq!(None, parse_severity("line-length=fatal"));
+    }
+
+    #[test]
+    fn baseline_hides_recorded_diagnostics() {
+        let nu = "IF $x {\n  IF $y { }\n}";
+        let recorded = Baseline::record("a.nu", nu, &keyword_casing(nu)[..1]);
+        assert_eq!("a.nu\tkeyword-casing\tIF $x {\n", recorded);
+
+        // the line moved down, and another one appeared
+        let nu = "ls\nIF $x {\n  IF $y { }\n}";
+        let mut baseline = Baseline::parse(&recorded);
+        let remaining = baseline.filter("a.nu", nu, keyword_casing(nu));
+        assert_eq!(
+            vec![3],
+            remaining.iter().map(|d| d.line).collect::<Vec<_>>()
+        );
+        assert_eq!(2, baseline.filter("b.nu", nu, keyword_casing(nu)).len());
+    }
+
+    #[test]
+    fn escapes_control_characters() {
+        assert_eq!("`\\u{1b}[31mIF`", terminal_safe("`\u{1b}[31mIF`"));
+        assert!(matches!(terminal_safe("plain\ttext"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        assert_eq!((2, 2), line_column("ö\nöö", 5));
+    }
+
+    #[test]
+    fn line_index() {
+        let nu = "ö\nöö\n\nls";
+        let lines = LineIndex::new(nu);
+        for offset in [0, 2, 3, 5, 8, 9, 10] {
+            assert_eq!(line_column(nu, offset), lines.line_column(offset));
+        }
+        assert_eq!((4, 3), lines.line_column(11));
+        assert_eq!(9, lines.line_start(4));
+    }
+}
@@ -0,0 +1,107 @@
+//!
+//! Helpers for adopting nufmt in an existing repository.
+//!
+//! Reformatting a whole tree in one commit makes the change hard to review, so the changed files
+//! are grouped per top-level directory and turned into a script of suggested commits.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+///
+/// # Builds the commit-splitting script for a migration
+///
+/// `changed` are the reformatted files, as paths below `root`. Files directly in `root` are
+/// grouped together. The script ends with the commands recording the formatting commits in
+/// `.git-blame-ignore-revs`.
+///
+pub fn migration_script<P: AsRef<Path>>(root: &Path, changed: &[P]) -> String {
+    let mut groups: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for path in changed {
+        let path = path.as_ref();
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        let group = match relative.parent().and_then(|parent| parent.iter().next()) {
+            Some(dir) => format!("{}/", dir.to_string_lossy()),
+            None => String::new(),
+        };
+        groups
+            .entry(group)
+            .or_default()
+            .push(path.to_string_lossy().into_owned());
+    }
+
+    let mut script = format!(
+        "#!/bin/sh\n\
+         # Generated by `nufmt migrate`: {} files reformatted in {} commits.\n\
+         # Review the changes, then run this script from the repository root.\n\
+         set -e\n",
+        changed.len(),
+        groups.len()
+    );
+
+    for (group, files) in &groups {
+        let quoted: Vec<String> = files.iter().map(|file| shell_quote(file)).collect();
+        let what = if group.is_empty() {
+            "top-level files".to_string()
+        } else {
+            group.clone()
+        };
+        script.push_str(&format!("\ngit add -- {}\n", quoted.join(" ")));
+        script.push_str(&format!(
+            "git commit -m {}\n",
+            shell_quote(&format!("Format {} with nufmt", what))
+        ));
+    }
+
+    if !groups.is_empty() {
+        script.push_str(&format!(
+            "\n# Hide the formatting commits from `git blame`\n\
+             echo '# nufmt migration' >> .git-blame-ignore-revs\n\
+             git log --format=%H -n {} >> .git-blame-ignore-revs\n",
+            groups.len()
+        ));
+    }
+
+    script
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn groups_per_top_level_directory() {
+        let changed = [
+            PathBuf::from("repo/lib/a.nu"),
+            PathBuf::from("repo/lib/nested/b.nu"),
+            PathBuf::from("repo/main.nu"),
+        ];
+        let script = migration_script(Path::new("repo"), &changed);
+
+        assert!(script.contains("3 files reformatted in 2 commits"));
+        assert!(script.contains(
+            "git add -- 'repo/main.nu'\ngit commit -m 'Format top-level files with nufmt'\n"
+        ));
+        assert!(script.contains(
+            "git add -- 'repo/lib/a.nu' 'repo/lib/nested/b.nu'\n\
+             git commit -m 'Format lib/ with nufmt'\n"
+        ));
+        assert!(script.contains("git log --format=%H -n 2 >> .git-blame-ignore-revs"));
+    }
+
+    #[test]
+    fn quotes_file_names() {
+        let script = migration_script(Path::new("."), &[PathBuf::from("./it's.nu")]);
+        assert!(script.contains("git add -- './it'\\''s.nu'"));
+    }
+
+    #[test]
+    fn nothing_changed() {
+        let script = migration_script::<PathBuf>(Path::new("."), &[]);
+        assert!(!script.contains("git "));
+    }
+}
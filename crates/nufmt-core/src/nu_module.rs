@@ -0,0 +1,91 @@
+//!
+//! A nu module wrapping the command line, to format from the shell.
+//!
+//! `nufmt nu-module | save nufmt.nu` writes it and `overlay use nufmt.nu` loads it, after which
+//! `open script.nu | nufmt format --indent 4` formats with nufmt. The options of `nufmt format`
+//! are those of the command line, so the module is written again when nufmt gets new ones.
+
+/// An option of the command line passed on by `nufmt format`
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Flag<'a> {
+    /// The name, without its dashes
+    pub long: &'a str,
+    pub short: Option<char>,
+    pub help: &'a str,
+    /// Whether it is a string option rather than a switch
+    pub takes_value: bool,
+}
+
+///
+/// # Writes the module
+///
+/// Its `nufmt format` command formats the nu piped in with the external `nufmt`, passing on the
+/// options set among `flags`.
+///
+pub fn nu_module(flags: &[Flag]) -> String {
+    let mut module = String::from(
+        "# Generated by `nufmt nu-module`, write it again rather than editing it.\n\
+         #\n\
+         # `overlay use` this file, then format with e.g. `open script.nu | nufmt format`.\n\
+         \n\
+         # Formats the nu piped in with nufmt\n\
+         export def \"nufmt format\" [\n",
+    );
+    for flag in flags {
+        module.push_str("  --");
+        module.push_str(flag.long);
+        if let Some(short) = flag.short {
+            module.push_str(&format!(" (-{})", short));
+        }
+        if flag.takes_value {
+            module.push_str(": string");
+        }
+        module.push_str(&format!(" # {}\n", flag.help));
+    }
+    module.push_str("] {\n  let nu = $in\n  mut args = []\n");
+    for flag in flags {
+        let variable = flag.long.replace('-', "_");
+        if flag.takes_value {
+            module.push_str(&format!(
+                "  if ${} != null {{\n    $args = ($args | append [\"--{}\" ${}])\n  }}\n",
+                variable, flag.long, variable
+            ));
+        } else {
+            module.push_str(&format!(
+                "  if ${} {{\n    $args = ($args | append \"--{}\")\n  }}\n",
+                variable, flag.long
+            ));
+        }
+    }
+    module.push_str("  $nu | ^nufmt ...$args\n}\n");
+    module
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{format_nu, Indentation};
+
+    #[test]
+    fn wraps_the_flags() {
+        let flags = [
+            Flag {
+                long: "indent",
+                short: Some('i'),
+                help: "Set the indentation used",
+                takes_value: true,
+            },
+            Flag {
+                long: "no-cache",
+                short: None,
+                help: "Neither read nor write the cache",
+                takes_value: false,
+            },
+        ];
+        let module = nu_module(&flags);
+        let expected = "export def \"nufmt format\" [\n  --indent (-i): string # Set the indentation used\n  --no-cache # Neither read nor write the cache\n] {\n  let nu = $in\n  mut args = []\n  if $indent != null {\n    $args = ($args | append [\"--indent\" $indent])\n  }\n  if $no_cache {\n    $args = ($args | append \"--no-cache\")\n  }\n  $nu | ^nufmt ...$args\n}\n";
+        assert!(module.ends_with(expected), "{}", module);
+        // formatted as nufmt formats it
+        assert_eq!(module, format_nu(&module, Indentation::Default));
+    }
+}
@@ -0,0 +1,164 @@
+//!
+//! Formatting of a selection, e.g. the lines an editor asks to format.
+//!
+//! A selection may start or end in the middle of a statement, formatting it as is would splice
+//! half a statement back into the script. It is expanded to the top level statements it touches
+//! first, and the range actually replaced is reported along with the formatted text.
+
+use crate::lexer::{statement_spans, tokenize, Span, TokenKind};
+use crate::{directive, format_nu_with_config, Config};
+use std::ops::Range;
+
+/// A formatted selection, see [format_range]
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FormattedRange {
+    /// The byte range of the source to replace, the selection expanded to whole statements
+    pub range: Range<usize>,
+    /// The formatted statements replacing the range
+    pub formatted: String,
+}
+
+///
+/// # Formats the statements a selection touches
+///
+/// `selection` is a byte range of `nu`, it is expanded to the whole lines of the top level
+/// statements it starts and ends in, which are formatted with `config` like the whole script. The
+/// directives written at the top level above the range still apply to it.
+///
+pub fn format_range(nu: &str, selection: Range<usize>, config: &Config) -> FormattedRange {
+    let range = statement_range(nu, selection);
+
+    // the directives above the range are formatted along with it, then left out
+    let directives: Vec<&str> = top_level_comments(nu, range.start)
+        .into_iter()
+        .filter(|comment| directive::parse_directive(&comment[1..]).is_some())
+        .collect();
+    let source = directives
+        .iter()
+        .map(|directive| format!("{}\n", directive))
+        .collect::<String>()
+        + &nu[range.clone()];
+    let formatted = format_nu_with_config(&source, config);
+    let formatted = formatted
+        .splitn(directives.len() + 1, '\n')
+        .last()
+        .unwrap_or_default()
+        .trim_start_matches('\n')
+        .to_string();
+
+    FormattedRange { range, formatted }
+}
+
+///
+/// # Expands a selection to whole top level statements
+///
+/// The range goes from the start of the line of the first [statement](statement_spans) the
+/// selection touches to the end of the line of the last one, a line holding several statements is
+/// kept whole. It is empty if the selection only holds blank lines.
+///
+pub fn statement_range(nu: &str, selection: Range<usize>) -> Range<usize> {
+    let end = selection.end.min(nu.len());
+    let start = selection.start.min(end);
+    // a selection of whole lines ends after the newline of its last one
+    let end = if end > start && nu.as_bytes()[end - 1] == b'\n' {
+        end - 1
+    } else {
+        end
+    };
+
+    let touched: Vec<Span> = statement_spans(nu)
+        .into_iter()
+        .filter(|span| span.start <= end && span.end >= start)
+        .collect();
+    match (touched.first(), touched.last()) {
+        (Some(first), Some(last)) => {
+            let line_start = nu[..first.start]
+                .rfind('\n')
+                .map_or(0, |newline| newline + 1);
+            let line_end = nu[last.end..]
+                .find('\n')
+                .map_or(nu.len(), |newline| last.end + newline);
+            line_start..line_end
+        }
+        _ => start..start,
+    }
+}
+
+///
+/// # The top level statement at a position
+///
+/// The block, definition or pipeline `offset` is in, as whole lines like a [statement_range], for
+/// editors formatting the statement at the cursor. It is `None` on a blank line.
+///
+pub fn block_at(nu: &str, offset: usize) -> Option<Range<usize>> {
+    let range = statement_range(nu, offset..offset);
+    (!range.is_empty()).then_some(range)
+}
+
+/// The comments at the top level before `end`, e.g. directives applying to what follows
+fn top_level_comments(nu: &str, end: usize) -> Vec<&str> {
+    let mut comments = Vec::new();
+    let mut depth = 0usize;
+    for token in tokenize(&nu[..end]) {
+        match token.kind {
+            TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen => depth += 1,
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => {
+                depth = depth.saturating_sub(1)
+            }
+            TokenKind::Comment if depth == 0 => comments.push(token.text(nu)),
+            _ => {}
+        }
+    }
+    comments
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expands_to_whole_statements() {
+        let nu = "let a = 1\ndef f [] {\n  ls\n}\nlet b = [\n1]\nls |\n  length";
+        // from the middle of `def` to the middle of the list
+        assert_eq!(10..40, statement_range(nu, 14..30));
+        assert_eq!("def f [] {\n  ls\n}\nlet b = [\n1]", &nu[10..40]);
+        // a pipeline continued on the next line
+        assert_eq!(41..54, statement_range(nu, 46..46));
+        // whole lines, newline included
+        assert_eq!(0..9, statement_range(nu, 0..10));
+        assert_eq!(3..3, statement_range("ls\n\n\nps", 3..4));
+    }
+
+    #[test]
+    fn blocks_at_positions() {
+        let nu = "let a = 1\ndef f [] {\n  ls\n}\n\nls |\n  length";
+        // within the body of the definition
+        assert_eq!(Some(10..27), block_at(nu, 22));
+        assert_eq!(Some(29..42), block_at(nu, 39));
+        assert_eq!(None, block_at(nu, 28));
+    }
+
+    #[test]
+    fn formats_the_expanded_range() {
+        let nu = "ls\nlet b = {a:1,\nb: 2}\nps";
+        let formatted = format_range(nu, 18..20, &Config::default());
+        let expected = FormattedRange {
+            range: 3..22,
+            formatted: "let b = {\n  a: 1,\n  b: 2\n}".to_string(),
+        };
+        assert_eq!(expected, formatted);
+        // the options of the config apply besides the indentation
+        let config = Config::builder().option("trailing_commas", "true").build();
+        let formatted = format_range(nu, 18..20, &config);
+        assert_eq!("let b = {\n  a: 1,\n  b: 2,\n}", formatted.formatted);
+    }
+
+    #[test]
+    fn directives_above_apply() {
+        let nu = "# nufmt: indent=4\nls\nlet b = [1]\ndef f [] {\n  # nufmt: indent=1\n}";
+        let formatted = format_range(nu, 21..22, &Config::default());
+        assert_eq!("let b = [1]", formatted.formatted);
+        let formatted = format_range(nu, 34..35, &Config::default());
+        assert_eq!("def f [] {\n    # nufmt: indent=1\n}", formatted.formatted);
+    }
+}
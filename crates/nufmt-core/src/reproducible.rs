@@ -0,0 +1,77 @@
+//!
+//! Checks that formatting gives the same bytes on every platform.
+//!
+//! A manifest lists the hash of the formatted output of every script of a corpus. Nothing in it
+//! depends on the platform: sources are read with their line endings normalized to LF, as git may
+//! check them out with CRLF on Windows, paths are written with `/` and sorted byte-wise, the
+//! scripts are formatted with the default [Config] whatever config files are around, and the hash
+//! is FNV-1a rather than the standard library hasher, which may change between Rust versions.
+
+use crate::tree::nu_files;
+use crate::{format_nu_with_config, Config};
+use std::io;
+use std::path::Path;
+
+/// The 64-bit FNV-1a hash of some bytes
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Formats a source as the manifest does, with LF line endings and the default config
+pub fn format_normalized(source: &str) -> String {
+    format_nu_with_config(&source.replace("\r\n", "\n"), &Config::default())
+}
+
+///
+/// # Builds the manifest of a corpus
+///
+/// Every nu script below `root` gets a line with the hash of its formatted output in hexadecimal
+/// and its path relative to `root`, separated by two spaces.
+///
+pub fn manifest(root: &Path) -> io::Result<String> {
+    let mut lines = Vec::new();
+    for path in nu_files(root)? {
+        let relative = path.strip_prefix(root).unwrap_or(&path);
+        let name: Vec<String> = relative
+            .iter()
+            .map(|part| part.to_string_lossy().into_owned())
+            .collect();
+        let formatted = format_normalized(&std::fs::read_to_string(&path)?);
+        lines.push((name.join("/"), fnv1a(formatted.as_bytes())));
+    }
+    lines.sort();
+
+    Ok(lines
+        .into_iter()
+        .map(|(name, hash)| format!("{:016x}  {}\n", hash, name))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hashes_are_fixed() {
+        assert_eq!(0xcbf2_9ce4_8422_2325, fnv1a(b""));
+        assert_eq!(0xaf63_dc4c_8601_ec8c, fnv1a(b"a"));
+    }
+
+    #[test]
+    fn line_endings_do_not_matter() {
+        let root = std::env::temp_dir().join(format!("nufmt-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("b")).unwrap();
+        std::fs::write(root.join("b").join("lf.nu"), "def f [] {\nls\n}\n").unwrap();
+        std::fs::write(root.join("crlf.nu"), "def f [] {\r\nls\r\n}\r\n").unwrap();
+
+        let manifest = manifest(&root);
+        std::fs::remove_dir_all(&root).unwrap();
+        let lines: Vec<&str> = manifest.as_deref().unwrap().lines().collect();
+        assert_eq!(2, lines.len());
+        assert!(lines[0].ends_with("  b/lf.nu"));
+        assert!(lines[1].ends_with("  crlf.nu"));
+        assert_eq!(lines[0][..16], lines[1][..16]);
+    }
+}
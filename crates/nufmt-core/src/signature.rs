@@ -0,0 +1,214 @@
+//!
+//! The layout of def signatures, `def f [x: int, --flag (-f)]: string -> table {`.
+//!
+//! Parameters are written `x = 5`, `x?: int` and `--flag (-f)`. A signature is joined on one line
+//! if it fits in the width, its parameters laid out one per line otherwise, and so are the lists
+//! of input and output types.
+
+use crate::lexer::{display_width, joined_width, tokens, Token, TokenKind};
+
+/// The nesting of type parameters after `word`, e.g. 1 after `record<a`
+pub(crate) fn type_parameter_depth(depth: usize, word: &str) -> usize {
+    let bytes = word.as_bytes();
+    bytes
+        .iter()
+        .enumerate()
+        .fold(depth, |depth, (i, byte)| match byte {
+            // a comparison has spaces around it, a type parameter follows the type name
+            b'<' if i > 0 && bytes[i - 1].is_ascii_alphabetic() => depth + 1,
+            // the > of a -> is no closing angle bracket
+            b'>' if depth > 0 && (i == 0 || bytes[i - 1] != b'-') => depth - 1,
+            _ => depth,
+        })
+}
+
+///
+/// # Writes a parameter word
+///
+/// Default values get spaces around their `=`, `x=5` is written `x = 5`. Returns the text written
+/// and whether a space follows it, the default value being the next word, `x= 5`.
+///
+pub(crate) fn parameter(word: &str) -> (String, bool) {
+    match word.split_once('=') {
+        Some((name, value)) => {
+            let equals = if name.is_empty() { "=" } else { " =" };
+            let text = match value.is_empty() {
+                true => format!("{}{}", name, equals),
+                false => format!("{}{} {}", name, equals, value),
+            };
+            (text, value.is_empty())
+        }
+        None => (word.to_string(), false),
+    }
+}
+
+///
+/// # Measures the parameters of a def once joined on one line
+///
+/// Like [joined_width], `rest` starting after the `[` of the parameters, along with the spacing
+/// the formatter normalizes: `x = 5` for default values, `x?: int` for optional parameters and
+/// `--flag (-f)` for short flags.
+///
+pub(crate) fn parameters_width(rest: &str) -> Option<usize> {
+    let width = joined_width(rest)?;
+    let tokens = list_tokens(rest);
+    let mut adjusted = width;
+    let mut depth = 0usize;
+    for (i, token) in tokens.iter().enumerate() {
+        let text = token.text(rest);
+        let spaced = i > 0 && tokens[i - 1].kind == TokenKind::Whitespace;
+        match token.kind {
+            TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen => {
+                let short_flag = i > 0 && tokens[i - 1].text(rest).starts_with("--");
+                adjusted += usize::from(token.kind == TokenKind::OpenParen && short_flag);
+                depth += 1;
+            }
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+            }
+            TokenKind::Word => match text.split_once('=') {
+                // `x=5` and `x="a"` get spaces around their `=`, `=5` one after it
+                Some((name, value)) => {
+                    let glued = value.is_empty()
+                        && tokens
+                            .get(i + 1)
+                            .is_some_and(|next| next.kind != TokenKind::Whitespace);
+                    adjusted +=
+                        usize::from(!name.is_empty()) + usize::from(!value.is_empty() || glued)
+                }
+                None if text.starts_with('?') && spaced => adjusted -= 1,
+                None => {}
+            },
+            _ => {}
+        }
+    }
+    Some(adjusted)
+}
+
+///
+/// # Measures the line of a def's signature once its parameters are joined
+///
+/// `rest` starts after the `[` of the parameters, measured with [parameters_width], up to the `{`
+/// of the body included, or the `[` of a list of input and output types which is measured on its
+/// own. Returns `None` if the signature holds a comment or a newline after its parameters.
+///
+pub(crate) fn signature_width(rest: &str) -> Option<usize> {
+    let parameters = parameters_width(rest)?;
+    let types = &rest[list_tokens(rest).last()?.span.end..];
+    // `]`, then `: string -> table {` as it is written once spaced out
+    let mut width = parameters + 1;
+    let mut space = false;
+    for token in tokens(types) {
+        match token.kind {
+            TokenKind::Whitespace => space = true,
+            TokenKind::Newline | TokenKind::Comment => return None,
+            TokenKind::OpenBrace | TokenKind::OpenBracket => return Some(width + 2),
+            // a colon is followed by a space, not preceded by one
+            TokenKind::Colon => {
+                width += 1;
+                space = true;
+            }
+            _ => {
+                width += usize::from(space) + display_width(token.text(types));
+                space = false;
+            }
+        }
+    }
+    Some(width)
+}
+
+/// The tokens of `rest` up to the bracket closing the list it starts in, included
+fn list_tokens(rest: &str) -> Vec<Token> {
+    let mut depth = 0usize;
+    let mut list = Vec::new();
+    for token in tokens(rest) {
+        list.push(token);
+        match token.kind {
+            TokenKind::OpenBracket | TokenKind::OpenBrace | TokenKind::OpenParen => depth += 1,
+            TokenKind::CloseBracket | TokenKind::CloseBrace | TokenKind::CloseParen => {
+                if depth == 0 {
+                    break;
+                }
+                depth -= 1;
+            }
+            _ => {}
+        }
+    }
+    list
+}
+
+///
+/// # Checks whether a list of a def's signature is joined on one line
+///
+/// `rest` starts after the `[` of the parameters if `parameters`, of the input and output types
+/// otherwise, which is opened at `column`. Either is joined if the line fits in `max_width` up
+/// to the `{` of the body, `[string -> table] {`.
+///
+pub(crate) fn joins(rest: &str, parameters: bool, column: usize, max_width: usize) -> bool {
+    let width = match parameters {
+        true => signature_width(rest),
+        // the `]` and ` {` after the types
+        false => joined_width(rest).map(|width| width + 3),
+    };
+    width.is_some_and(|width| column + 1 + width <= max_width)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn type_parameters() {
+        assert_eq!(1, type_parameter_depth(0, "record<a"));
+        assert_eq!(0, type_parameter_depth(2, "string>>"));
+        assert_eq!(0, type_parameter_depth(0, "0..<$n"));
+        assert_eq!(1, type_parameter_depth(1, "->"));
+    }
+
+    #[test]
+    fn parameters() {
+        assert_eq!(("x = 5".to_string(), false), parameter("x=5"));
+        assert_eq!(("x =".to_string(), true), parameter("x="));
+        assert_eq!(("= 5".to_string(), false), parameter("=5"));
+        assert_eq!(("--flag".to_string(), false), parameter("--flag"));
+    }
+
+    #[test]
+    fn parameters_widths() {
+        // `x = 5, y?: int, --flag (-f): string`
+        assert_eq!(
+            Some(35),
+            parameters_width("x=5, y ?: int, --flag(-f): string]")
+        );
+        assert_eq!(parameters_width("x = 5]"), parameters_width("x =5]"));
+        assert_eq!(Some(0), parameters_width("] {}"));
+        assert_eq!(Some(22), parameters_width("l: list = [1 2], x=1] { $x=1 }"));
+        assert_eq!(Some(26), parameters_width("--flag(-f): string, x=1]"));
+        // `s: string = "a"`
+        assert_eq!(Some(15), parameters_width("s: string=\"a\"]"));
+    }
+
+    #[test]
+    fn signature_widths() {
+        // `x = 5] {`
+        assert_eq!(Some(8), signature_width("x=5] { $x }"));
+        // `x: int]: string->table {`
+        assert_eq!(Some(24), signature_width("x: int] :string->table {"));
+        assert_eq!(Some(10), signature_width("x: int]: [string -> table]"));
+        assert_eq!(None, signature_width("x: int]: string # x\n{}"));
+    }
+
+    #[test]
+    fn joined_lists() {
+        // `def f [x: int] {` at 6
+        assert!(joins("x: int] { $x }", true, 6, 16));
+        assert!(!joins("x: int] { $x }", true, 6, 15));
+        // `]: [string -> table] {` at 3
+        assert!(joins("string -> table] { }", false, 3, 22));
+        assert!(!joins("string -> table] { }", false, 3, 21));
+        assert!(!joins("\n  string -> table # all\n] { }", false, 3, 100));
+    }
+}
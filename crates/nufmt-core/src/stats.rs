@@ -0,0 +1,188 @@
+//!
+//! Usage statistics kept on the machine of the user, recorded only when `NUFMT_STATS` is set.
+//!
+//! Every run formatting files appends a line to a local file, nothing is ever sent anywhere. The
+//! lines are tab separated: the time of the run in seconds since the Unix epoch, how many files it
+//! formatted, how many of them changed and how long it took in milliseconds.
+
+use crate::cache::cache_dir;
+use crate::header::date_of_day;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// What a run of nufmt did
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct RunStats {
+    /// When the run started, in seconds since the Unix epoch
+    pub timestamp: u64,
+    pub files: usize,
+    pub changed: usize,
+    pub millis: u64,
+}
+
+impl RunStats {
+    /// Parses a line of the stats file
+    pub fn parse(line: &str) -> Option<RunStats> {
+        let mut fields = line.split('\t');
+        let stats = RunStats {
+            timestamp: fields.next()?.parse().ok()?,
+            files: fields.next()?.parse().ok()?,
+            changed: fields.next()?.parse().ok()?,
+            millis: fields.next()?.parse().ok()?,
+        };
+        match fields.next() {
+            Some(_) => None,
+            None => Some(stats),
+        }
+    }
+
+    /// The line of the stats file, without its newline
+    pub fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}",
+            self.timestamp, self.files, self.changed, self.millis
+        )
+    }
+}
+
+/// Whether the user opted in, with `NUFMT_STATS` set to anything but empty or `0`
+pub fn enabled() -> bool {
+    std::env::var_os("NUFMT_STATS").is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+///
+/// # The stats file of the current user
+///
+/// `NUFMT_STATS_FILE` if set, else `stats.tsv` in the [cache directory](cache_dir), which clearing
+/// the cache leaves alone. `None` if neither is known.
+///
+pub fn stats_file() -> Option<PathBuf> {
+    match std::env::var_os("NUFMT_STATS_FILE").filter(|value| !value.is_empty()) {
+        Some(file) => Some(PathBuf::from(file)),
+        None => cache_dir().map(|dir| dir.join("stats.tsv")),
+    }
+}
+
+/// Appends a run to a stats file, creating it and its directory if needed
+pub fn record(path: &Path, stats: &RunStats) -> io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    // a single write so runs finishing together do not interleave their lines
+    file.write_all(format!("{}\n", stats.to_line()).as_bytes())
+}
+
+/// The runs of a stats file, lines which cannot be parsed, e.g. cut by a crash, are left out
+pub fn history(contents: &str) -> Vec<RunStats> {
+    contents.lines().filter_map(RunStats::parse).collect()
+}
+
+/// The UTC date of a timestamp, as `YYYY-MM-DD`
+fn date(timestamp: u64) -> String {
+    let (year, month, day) = date_of_day((timestamp / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+fn row(label: &str, runs: &[RunStats]) -> String {
+    let files: usize = runs.iter().map(|run| run.files).sum();
+    let changed: usize = runs.iter().map(|run| run.changed).sum();
+    let millis: u64 = runs.iter().map(|run| run.millis).sum();
+    let per_file = if files == 0 {
+        0.0
+    } else {
+        millis as f64 / files as f64
+    };
+    format!(
+        "{:<10}  {:>5}  {:>7}  {:>7}  {:>8}  {:>7.2}\n",
+        label,
+        runs.len(),
+        files,
+        changed,
+        millis,
+        per_file
+    )
+}
+
+const HEADER: &str = "date         runs    files  changed  total ms  ms/file\n";
+
+/// A table of the runs grouped by day, oldest first, to follow the trends
+pub fn daily_summary(runs: &[RunStats]) -> String {
+    let mut runs = runs.to_vec();
+    runs.sort_by_key(|run| run.timestamp);
+
+    let mut summary = HEADER.to_string();
+    let mut start = 0;
+    while start < runs.len() {
+        let day = date(runs[start].timestamp);
+        let end = start
+            + runs[start..]
+                .iter()
+                .take_while(|run| date(run.timestamp) == day)
+                .count();
+        summary.push_str(&row(&day, &runs[start..end]));
+        start = end;
+    }
+    summary
+}
+
+/// The totals of every run
+pub fn totals(runs: &[RunStats]) -> String {
+    format!("{}{}", HEADER, row("total", runs))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DAY: u64 = 86_400;
+
+    fn run(timestamp: u64, files: usize, changed: usize, millis: u64) -> RunStats {
+        RunStats {
+            timestamp,
+            files,
+            changed,
+            millis,
+        }
+    }
+
+    #[test]
+    fn parses_lines() {
+        let stats = run(1_700_000_000, 12, 3, 45);
+        assert_eq!(Some(stats), RunStats::parse(&stats.to_line()));
+        assert_eq!(None, RunStats::parse("1700000000\t12\t3"));
+        assert_eq!(None, RunStats::parse("1700000000\t12\t3\t45\t1"));
+        assert_eq!(None, RunStats::parse("1700000000\ttwelve\t3\t45"));
+    }
+
+    #[test]
+    fn records_runs() {
+        let dir = std::env::temp_dir().join(format!("nufmt-stats-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("nested").join("stats.tsv");
+
+        record(&path, &run(0, 1, 0, 2)).unwrap();
+        record(&path, &run(DAY, 4, 1, 8)).unwrap();
+        let contents = fs::read_to_string(&path).unwrap() + "17000";
+        assert_eq!(vec![run(0, 1, 0, 2), run(DAY, 4, 1, 8)], history(&contents));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn summarizes_days() {
+        let runs = [run(DAY + 60, 4, 1, 8), run(10, 1, 0, 2), run(20, 3, 3, 4)];
+        assert_eq!(
+            "date         runs    files  changed  total ms  ms/file\n\
+             1970-01-01      2        4        3         6     1.50\n\
+             1970-01-02      1        4        1         8     2.00\n",
+            daily_summary(&runs)
+        );
+        assert_eq!(
+            "date         runs    files  changed  total ms  ms/file\n\
+             total           3        8        4        14     1.75\n",
+            totals(&runs)
+        );
+        assert_eq!(HEADER, daily_summary(&[]));
+    }
+}
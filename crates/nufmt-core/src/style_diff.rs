@@ -0,0 +1,144 @@
+//!
+//! Comparison of two styles on a corpus, e.g. before changing the config of a repository.
+//!
+//! nufmt has no style editions, a style is a [Config], usually read from a config file. Every
+//! script is formatted with both and the kind of difference between the outputs is reported, so
+//! the size of the change is known before reformatting anything.
+
+use crate::tree::nu_files;
+use crate::{format_nu_with_config, Config};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// How the outputs of two styles differ, from the least to the most intrusive
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub enum Difference {
+    /// Only the indentation of some lines
+    Indentation,
+    /// Only the spaces within lines
+    Spacing,
+    /// The lines are broken differently
+    LineBreaks,
+    /// The text itself, e.g. closure parameters added or removed
+    Other,
+}
+
+impl Difference {
+    pub fn name(self) -> &'static str {
+        match self {
+            Difference::Indentation => "indentation",
+            Difference::Spacing => "spacing",
+            Difference::LineBreaks => "line breaks",
+            Difference::Other => "other",
+        }
+    }
+}
+
+/// How two formatted versions of a script differ, `None` if they are the same
+pub fn classify(before: &str, after: &str) -> Option<Difference> {
+    if before == after {
+        return None;
+    }
+    let without_whitespace =
+        |text: &str| -> String { text.chars().filter(|c| !c.is_whitespace()).collect() };
+    if without_whitespace(before) != without_whitespace(after) {
+        return Some(Difference::Other);
+    }
+    if before.lines().count() != after.lines().count() {
+        return Some(Difference::LineBreaks);
+    }
+    let same_lines = before
+        .lines()
+        .zip(after.lines())
+        .all(|(before, after)| before.trim_start() == after.trim_start());
+    Some(if same_lines {
+        Difference::Indentation
+    } else {
+        Difference::Spacing
+    })
+}
+
+///
+/// # Compares two styles on the scripts below a directory
+///
+/// Returns the scripts formatted differently, with how, in the order of [nu_files].
+///
+pub fn style_diff(
+    root: &Path,
+    before: &Config,
+    after: &Config,
+) -> io::Result<Vec<(PathBuf, Difference)>> {
+    let mut differences = Vec::new();
+    for path in nu_files(root)? {
+        let source = std::fs::read_to_string(&path)?;
+        let formatted = |config| format_nu_with_config(&source, config);
+        if let Some(difference) = classify(&formatted(before), &formatted(after)) {
+            differences.push((path, difference));
+        }
+    }
+    Ok(differences)
+}
+
+/// Summarizes the result of [style_diff] on `total` scripts, a line per kind of difference
+pub fn summary(differences: &[(PathBuf, Difference)], total: usize) -> String {
+    let mut summary = format!(
+        "{} of {} files formatted differently\n",
+        differences.len(),
+        total
+    );
+    let kinds = [
+        Difference::Indentation,
+        Difference::Spacing,
+        Difference::LineBreaks,
+        Difference::Other,
+    ];
+    for kind in kinds {
+        let files: Vec<String> = differences
+            .iter()
+            .filter(|(_, difference)| *difference == kind)
+            .map(|(path, _)| path.display().to_string())
+            .collect();
+        if !files.is_empty() {
+            summary.push_str(&format!(
+                "{}: {} ({})\n",
+                kind.name(),
+                files.len(),
+                files.join(", ")
+            ));
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn classifies_differences() {
+        assert_eq!(None, classify("ls", "ls"));
+        assert_eq!(
+            Some(Difference::Indentation),
+            classify("{\n  a\n}", "{\n    a\n}")
+        );
+        assert_eq!(Some(Difference::Spacing), classify("[1 2]", "[ 1 2 ]"));
+        assert_eq!(
+            Some(Difference::LineBreaks),
+            classify("[1 2]", "[\n  1\n  2\n]")
+        );
+        assert_eq!(Some(Difference::Other), classify("{|| ls}", "{ ls }"));
+    }
+
+    #[test]
+    fn summarizes() {
+        let differences = vec![
+            (PathBuf::from("a.nu"), Difference::Spacing),
+            (PathBuf::from("b.nu"), Difference::Other),
+            (PathBuf::from("c.nu"), Difference::Spacing),
+        ];
+        assert_eq!(
+            "3 of 5 files formatted differently\nspacing: 2 (a.nu, c.nu)\nother: 1 (b.nu)\n",
+            summary(&differences, 5)
+        );
+    }
+}
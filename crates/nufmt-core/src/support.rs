@@ -0,0 +1,216 @@
+//!
+//! What nufmt does with each nu construct.
+//!
+//! The table is the single source of truth for `nufmt support` and for lenient mode, which passes
+//! scripts containing unsafe constructs through untouched instead of risking to break them.
+
+use crate::lexer::{tokenize, Span, Token, TokenKind};
+
+/// How well a construct is supported
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Support {
+    /// Laid out by the formatter
+    Formatted,
+    /// Kept exactly as written
+    Passthrough,
+    /// May be changed in a way that alters the script
+    Unsafe,
+}
+
+impl Support {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Support::Formatted => "formatted",
+            Support::Passthrough => "passthrough",
+            Support::Unsafe => "unsafe",
+        }
+    }
+}
+
+/// Finds the first occurrence of a construct in a script and its tokens
+pub type Detector = fn(&str, &[Token]) -> Option<Span>;
+
+/// A nu construct and its support status
+#[derive(Debug)]
+pub struct Construct {
+    pub name: &'static str,
+    pub example: &'static str,
+    pub support: Support,
+    /// Finds the first occurrence of the construct, only needed for unsafe constructs
+    pub detect: Option<Detector>,
+}
+
+/// Every construct nufmt knows about
+pub const CONSTRUCTS: &[Construct] = &[
+    Construct {
+        name: "list",
+        example: "[1, 2, 3]",
+        support: Support::Formatted,
+        detect: None,
+    },
+    Construct {
+        name: "record",
+        example: "{name: nu, version: 1}",
+        support: Support::Formatted,
+        detect: None,
+    },
+    Construct {
+        name: "block",
+        example: "if $x { ls }",
+        support: Support::Formatted,
+        detect: None,
+    },
+    Construct {
+        name: "closure",
+        example: "{|x| $x + 1}",
+        support: Support::Formatted,
+        detect: None,
+    },
+    Construct {
+        name: "pipeline",
+        example: "ls | where size > 1kb",
+        support: Support::Formatted,
+        detect: None,
+    },
+    Construct {
+        name: "comment",
+        example: "# a comment",
+        support: Support::Passthrough,
+        detect: None,
+    },
+    Construct {
+        name: "double quoted string",
+        example: "\"a string\"",
+        support: Support::Passthrough,
+        detect: None,
+    },
+    Construct {
+        name: "external call",
+        example: "^git log --format=%H:%s",
+        support: Support::Passthrough,
+        detect: None,
+    },
+    Construct {
+        name: "subexpression",
+        example: "(ls | length)",
+        support: Support::Passthrough,
+        detect: None,
+    },
+    Construct {
+        name: "attribute",
+        example: "@example \"adds\" { add 1 2 }",
+        support: Support::Passthrough,
+        detect: None,
+    },
+    Construct {
+        name: "single quoted string",
+        example: "'a string'",
+        support: Support::Passthrough,
+        detect: None,
+    },
+    Construct {
+        name: "backtick string",
+        example: "`a path`",
+        support: Support::Passthrough,
+        detect: None,
+    },
+    Construct {
+        name: "raw string",
+        example: "r#'a string'#",
+        support: Support::Passthrough,
+        detect: None,
+    },
+    Construct {
+        name: "string interpolation",
+        example: "$\"total: ($x + 1)\"",
+        support: Support::Passthrough,
+        detect: None,
+    },
+    Construct {
+        name: "url argument",
+        example: "http get https://example.com",
+        support: Support::Unsafe,
+        detect: Some(detect_url),
+    },
+];
+
+///
+/// # Finds the unsafe constructs of a script
+///
+/// Returns every unsafe construct used along with its first occurrence.
+///
+pub fn unsafe_constructs(nu: &str) -> Vec<(&'static Construct, Span)> {
+    let tokens = tokenize(nu);
+    CONSTRUCTS
+        .iter()
+        .filter(|construct| construct.support == Support::Unsafe)
+        .filter_map(|construct| {
+            let span = construct.detect.and_then(|detect| detect(nu, &tokens))?;
+            Some((construct, span))
+        })
+        .collect()
+}
+
+/// Finds the first token followed by a token matching `predicate`
+fn find_pair(tokens: &[Token], predicate: impl Fn(&Token, &Token) -> bool) -> Option<Span> {
+    tokens
+        .windows(2)
+        .find(|pair| predicate(&pair[0], &pair[1]))
+        .map(|pair| pair[0].span)
+}
+
+fn detect_url(nu: &str, tokens: &[Token]) -> Option<Span> {
+    find_pair(tokens, |first, second| {
+        first.kind == TokenKind::Word
+            && second.kind == TokenKind::Colon
+            && !first.text(nu).starts_with('^')
+            && nu[second.span.end..].starts_with("//")
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(nu: &str) -> Vec<&'static str> {
+        unsafe_constructs(nu)
+            .iter()
+            .map(|(construct, _)| construct.name)
+            .collect()
+    }
+
+    #[test]
+    fn unsafe_constructs_have_detectors() {
+        for construct in CONSTRUCTS {
+            let detectable = construct.detect.is_some();
+            assert_eq!(
+                construct.support == Support::Unsafe,
+                detectable,
+                "{}",
+                construct.name
+            );
+        }
+    }
+
+    #[test]
+    fn examples_are_detected() {
+        for construct in CONSTRUCTS.iter().filter(|c| c.support == Support::Unsafe) {
+            assert_eq!(vec![construct.name], names(construct.example));
+        }
+    }
+
+    #[test]
+    fn safe_script() {
+        assert!(names("ls | where name =~ \"'\" # it's fine\n{a: 1}\nls `a b` r#'c'#").is_empty());
+    }
+
+    #[test]
+    fn span_of_first_occurrence() {
+        let nu = "ls\nhttp get https://a | http get https://b";
+        let spans: Vec<Span> = unsafe_constructs(nu)
+            .iter()
+            .map(|(_, span)| *span)
+            .collect();
+        assert_eq!(vec![Span { start: 12, end: 17 }], spans);
+    }
+}
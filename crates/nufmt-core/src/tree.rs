@@ -0,0 +1,250 @@
+//!
+//! Discovery of the nu scripts in a directory tree.
+//!
+//! Scripts can be filtered with glob patterns, matched against their path relative to the root
+//! with `/` separators: `*` matches any characters but `/`, `**` any directories and `?` a single
+//! character. A pattern without `/` matches the file name only, e.g. `*_test.nu`.
+
+use crate::ignore::{nufmt_ignored, Ignores};
+use std::io;
+use std::path::{Path, PathBuf};
+
+///
+/// # Lists the nu scripts below a directory
+///
+/// Hidden directories such as `.git` are skipped, and so is what the [ignore
+/// files](crate::ignore) and the `.nufmtignore` files ignore. The result is sorted so runs are
+/// reproducible.
+///
+pub fn nu_files(root: &Path) -> io::Result<Vec<PathBuf>> {
+    walk(root, true)
+}
+
+///
+/// # Lists the nu scripts below a directory which match the filters
+///
+/// A script is kept if it matches one of the `include` patterns, or if there is none, and none of
+/// the `exclude` patterns. The ignore files are only followed if `respect_ignore_files` is set,
+/// except the `.nufmtignore` files, which always are.
+///
+pub fn filtered_nu_files(
+    root: &Path,
+    include: &[&str],
+    exclude: &[&str],
+    respect_ignore_files: bool,
+) -> io::Result<Vec<PathBuf>> {
+    let mut files = walk(root, respect_ignore_files)?;
+    files.retain(|file| {
+        let relative = file
+            .strip_prefix(root)
+            .unwrap_or(file)
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let matches = |pattern: &&str| glob_match(pattern, &relative);
+        (include.is_empty() || include.iter().any(matches)) && !exclude.iter().any(matches)
+    });
+    Ok(files)
+}
+
+/// Whether a relative path with `/` separators matches a glob pattern
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    if pattern.contains('/') {
+        anchored_glob_match(pattern.trim_start_matches("./"), path)
+    } else {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        match_name(pattern.as_bytes(), name.as_bytes())
+    }
+}
+
+/// Whether a relative path with `/` separators matches a glob pattern from its first component
+pub(crate) fn anchored_glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    match_components(&pattern, &path)
+}
+
+fn match_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => {
+            (0..=path.len()).any(|skipped| match_components(rest, &path[skipped..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path)) => {
+                match_name(first.as_bytes(), name.as_bytes()) && match_components(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_name(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((b'*', rest)) => (0..=name.len()).any(|skipped| match_name(rest, &name[skipped..])),
+        Some((b'?', rest)) => !name.is_empty() && match_name(rest, &name[1..]),
+        Some((byte, rest)) => name.first() == Some(byte) && match_name(rest, &name[1..]),
+    }
+}
+
+fn walk(root: &Path, respect_ignore_files: bool) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut ignores = if respect_ignore_files {
+        Some(Ignores::above(root)?)
+    } else {
+        None
+    };
+    let absolute = root.canonicalize()?;
+    collect_nu_files(root, &absolute, &mut ignores, &mut files)?;
+    let mut kept = Vec::with_capacity(files.len());
+    for file in files {
+        if !nufmt_ignored(&file)? {
+            kept.push(file);
+        }
+    }
+    kept.sort();
+    Ok(kept)
+}
+
+/// Collects the scripts of a directory, `absolute` being its path the ignore files are matched to
+fn collect_nu_files(
+    dir: &Path,
+    absolute: &Path,
+    ignores: &mut Option<Ignores>,
+    files: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    let depth = ignores.as_ref().map(Ignores::len);
+    if let Some(ignores) = ignores {
+        ignores.load(absolute)?;
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let path = dir.join(&name);
+        let is_dir = path.is_dir();
+        let ignored = ignores
+            .as_ref()
+            .is_some_and(|ignores| ignores.is_ignored(&absolute.join(&name), is_dir));
+        if ignored {
+            continue;
+        }
+        if is_dir {
+            if !name.to_string_lossy().starts_with('.') {
+                collect_nu_files(&path, &absolute.join(&name), ignores, files)?;
+            }
+        } else if path.extension().is_some_and(|ext| ext == "nu") {
+            files.push(path);
+        }
+    }
+
+    if let (Some(ignores), Some(depth)) = (ignores, depth) {
+        ignores.truncate(depth);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+
+    fn create(root: &Path, files: &[&str]) {
+        let _ = fs::remove_dir_all(root);
+        for file in files {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "ls\n").unwrap();
+        }
+    }
+
+    fn relative(root: &Path, files: Vec<PathBuf>) -> Vec<String> {
+        files
+            .iter()
+            .map(|file| {
+                let relative = file.strip_prefix(root).unwrap();
+                relative.to_string_lossy().replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_match("*.nu", "scripts/build.nu"));
+        assert!(glob_match("b?ild.nu", "build.nu"));
+        assert!(!glob_match("*_test.nu", "build.nu"));
+        assert!(glob_match("scripts/*.nu", "scripts/build.nu"));
+        assert!(!glob_match("scripts/*.nu", "scripts/ci/build.nu"));
+        assert!(glob_match("scripts/**/*.nu", "scripts/ci/release/build.nu"));
+        assert!(glob_match("**/vendor/**", "a/vendor/b/c.nu"));
+        assert!(glob_match("./vendor/**", "vendor/c.nu"));
+        assert!(!glob_match("vendor/**", "a/vendor/c.nu"));
+    }
+
+    #[test]
+    fn filters_files() {
+        let root = std::env::temp_dir().join(format!("nufmt-tree-{}", std::process::id()));
+        create(
+            &root,
+            &[
+                "a.nu",
+                "a_test.nu",
+                "vendor/b.nu",
+                ".git/c.nu",
+                "d.txt",
+                "e.nu",
+            ],
+        );
+        fs::write(root.join(".gitignore"), "e.nu\n").unwrap();
+
+        let names = |include: &[&str], exclude: &[&str]| {
+            relative(
+                &root,
+                filtered_nu_files(&root, include, exclude, true).unwrap(),
+            )
+        };
+        assert_eq!(vec!["a.nu", "a_test.nu", "vendor/b.nu"], names(&[], &[]));
+        assert_eq!(vec!["a.nu", "a_test.nu"], names(&[], &["vendor/**"]));
+        assert_eq!(vec!["a_test.nu"], names(&["*_test.nu"], &[]));
+        let all = filtered_nu_files(&root, &[], &[], false).unwrap();
+        assert_eq!(
+            vec!["a.nu", "a_test.nu", "e.nu", "vendor/b.nu"],
+            relative(&root, all)
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn follows_ignore_files() {
+        let root = std::env::temp_dir().join(format!("nufmt-tree-ignore-{}", std::process::id()));
+        let files = [
+            "repo/.git/HEAD",
+            "repo/a.nu",
+            "repo/vendor/b.nu",
+            "repo/src/c.nu",
+            "repo/src/d.nu",
+            "repo/src/d_gen.nu",
+        ];
+        create(&root, &files);
+        // the files above the root of the repository do not apply
+        fs::write(root.join(".gitignore"), "*.nu\n").unwrap();
+        fs::write(root.join("repo/.gitignore"), "vendor/\nsrc/*.nu\n").unwrap();
+        fs::write(root.join("repo/src/.ignore"), "!d*.nu\n").unwrap();
+        fs::write(root.join("repo/.nufmtignore"), "*_gen.nu\n").unwrap();
+
+        let repo = root.join("repo");
+        assert_eq!(
+            vec!["a.nu", "src/d.nu"],
+            relative(&repo, nu_files(&repo).unwrap())
+        );
+        let src = repo.join("src");
+        assert_eq!(vec!["d.nu"], relative(&src, nu_files(&src).unwrap()));
+        let all = filtered_nu_files(&repo, &[], &[], false).unwrap();
+        assert_eq!(
+            vec!["a.nu", "src/c.nu", "src/d.nu", "vendor/b.nu"],
+            relative(&repo, all)
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+}